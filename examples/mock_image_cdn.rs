@@ -0,0 +1,89 @@
+//! Local stand-in for a platform's image CDN, for trying the `/img/` proxy.
+//!
+//!     cargo run --example mock_image_cdn -- fixtures/images/avatar.png
+//!
+//! Send chat with an avatar or emoji pointing at one of these, then load its `/img/` URL:
+//!
+//! - `/avatar.png` serves the fixture.
+//! - `/large.png` is the fixture padded to 400 KiB, so a few fill a small cache.
+//! - `/huge.png` claims to be a PNG but is larger than the default `images.max_size`.
+//! - `/streamed.png` is the same without a Content-Length.
+//! - `/page.png` is HTML sent as `image/png`.
+//! - `/text` is plain text.
+//! - `/slow.png` answers after 30 seconds.
+//! - Anything else is 404.
+//!
+//! Each request is printed, so it's easy to see which images were fetched only once.
+//! The image cache's tests serve these too.
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use futures::stream;
+use std::time::Duration;
+
+const LARGE: usize = 400 * 1024;
+const HUGE: usize = 3 * 1024 * 1024;
+
+pub async fn handle(req: HttpRequest, fixture: web::Data<Vec<u8>>) -> HttpResponse {
+    println!("{} {}", req.method(), req.path());
+    match req.path() {
+        "/avatar.png" => HttpResponse::Ok()
+            .content_type("image/png")
+            .body(fixture.to_vec()),
+        "/large.png" => {
+            let mut body = fixture.to_vec();
+            body.resize(LARGE, 0);
+            HttpResponse::Ok().content_type("image/png").body(body)
+        }
+        "/huge.png" => {
+            let mut body = fixture.to_vec();
+            body.resize(HUGE, 0);
+            HttpResponse::Ok().content_type("image/png").body(body)
+        }
+        "/streamed.png" => {
+            let mut body = fixture.to_vec();
+            body.resize(HUGE, 0);
+            let chunks: Vec<Result<web::Bytes, actix_web::Error>> = body
+                .chunks(64 * 1024)
+                .map(|chunk| Ok(web::Bytes::copy_from_slice(chunk)))
+                .collect();
+            HttpResponse::Ok()
+                .content_type("image/png")
+                .streaming(stream::iter(chunks))
+        }
+        "/page.png" => HttpResponse::Ok()
+            .content_type("image/png")
+            .body("<html><body>Not an image</body></html>"),
+        "/text" => HttpResponse::Ok()
+            .content_type("text/plain")
+            .body("Not an image"),
+        "/slow.png" => {
+            actix_web::rt::time::sleep(Duration::from_secs(30)).await;
+            HttpResponse::Ok()
+                .content_type("image/png")
+                .body(fixture.to_vec())
+        }
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: mock_image_cdn <fixture> [address]");
+        std::process::exit(1);
+    }
+    let fixture = web::Data::new(std::fs::read(&args[1])?);
+    let address = args.get(2).map(String::as_str).unwrap_or("127.0.0.1:6690");
+
+    println!("Listening on http://{}", address);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(fixture.clone())
+            .default_service(web::to(handle))
+    })
+    .bind(address)?
+    .run()
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Local stand-in for Twitch's IRC websocket. Waits for a client to JOIN, then
+//! replays a recorded chat log to it, one line every half second.
+//!
+//!     cargo run --example mock_twitch_irc -- fixtures/twitch/chat.irc
+//!
+//! Point a twitch connector in `config.toml` at it with `url = "ws://127.0.0.1:6680"`.
+//! Lines the client sends are printed, so PONGs can be checked.
+
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: mock_twitch_irc <fixture> [address]");
+        std::process::exit(1);
+    }
+    // Fixtures write \u0001 for the CTCP delimiter so they stay printable.
+    let fixture = std::fs::read_to_string(&args[1])?.replace("\\u0001", "\u{1}");
+    let address = args.get(2).map(String::as_str).unwrap_or("127.0.0.1:6680");
+
+    let listener = TcpListener::bind(address).await?;
+    println!("Listening on ws://{}", address);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        println!("Connection from {}", peer);
+        let fixture = fixture.to_owned();
+        actix_web::rt::spawn(async move {
+            if let Err(err) = serve(stream, &fixture, Duration::from_millis(500)).await {
+                eprintln!("Connection from {} failed: {}", peer, err);
+            }
+        });
+    }
+}
+
+/// Replays `fixture` to one client once it has joined, a line every `interval`.
+pub async fn serve(
+    stream: tokio::net::TcpStream,
+    fixture: &str,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+
+    // Wait for the JOIN, acknowledging it the way Twitch does.
+    let mut nick = String::from("justinfan");
+    while let Some(frame) = socket.next().await {
+        let Message::Text(text) = frame? else {
+            continue;
+        };
+        for line in text.lines() {
+            println!("> {}", line);
+            if let Some(name) = line.strip_prefix("NICK ") {
+                nick = name.to_owned();
+            }
+            if let Some(channels) = line.strip_prefix("JOIN ") {
+                for channel in channels.split(',') {
+                    socket
+                        .send(Message::Text(format!(
+                            ":{nick}!{nick}@{nick}.tmi.twitch.tv JOIN {channel}"
+                        )))
+                        .await?;
+                }
+            }
+        }
+        if text.contains("JOIN ") {
+            break;
+        }
+    }
+
+    socket
+        .send(Message::Text("PING :tmi.twitch.tv".to_owned()))
+        .await?;
+
+    for line in fixture.lines().filter(|line| !line.is_empty()) {
+        tokio::time::sleep(interval).await;
+        println!("< {}", line);
+        socket.send(Message::Text(line.to_owned())).await?;
+    }
+
+    // Keep the connection open, printing whatever the client sends.
+    while let Some(frame) = socket.next().await {
+        if let Message::Text(text) = frame? {
+            println!("> {}", text);
+        }
+    }
+    Ok(())
+}
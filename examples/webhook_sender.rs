@@ -0,0 +1,78 @@
+//! Local stand-in for donation services. Signs a recorded payload the way the
+//! provider would and posts it to a running server.
+//!
+//!     cargo run --example webhook_sender -- kofi fixtures/webhooks/kofi.json
+//!
+//! Secrets are read from the variables that override the server's `[webhooks]`
+//! settings, including `.env`, e.g. `KOFI_VERIFICATION_TOKEN`.
+
+use hmac::{Hmac, Mac};
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+type HmacMd5 = Hmac<md5::Md5>;
+
+fn sign<M: Mac + hmac::digest::KeyInit>(secret: &str, body: &[u8]) -> String {
+    let mut mac = <M as Mac>::new_from_slice(secret.as_bytes()).expect("Invalid secret.");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: webhook_sender <kofi|patreon|streamelements|streamlabs> <fixture> [server]"
+        );
+        std::process::exit(1);
+    }
+    let provider = args[1].as_str();
+    let fixture = std::fs::read_to_string(&args[2])?;
+    let server = args
+        .get(3)
+        .cloned()
+        .unwrap_or_else(|| "http://127.0.0.1:1350".to_owned());
+    let url = format!("{}/api/v1/webhooks/{}", server, provider);
+    let secret = |var: &str| dotenvy::var(var).unwrap_or_default();
+
+    let client = reqwest::Client::new();
+    let request = match provider {
+        "kofi" => {
+            let data = fixture.replace("VERIFICATION_TOKEN", &secret("KOFI_VERIFICATION_TOKEN"));
+            client
+                .post(&url)
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(serde_urlencoded::to_string([("data", data)])?)
+        }
+        "patreon" => client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-Patreon-Event", "members:pledge:create")
+            .header(
+                "X-Patreon-Signature",
+                sign::<HmacMd5>(&secret("PATREON_WEBHOOK_SECRET"), fixture.as_bytes()),
+            )
+            .body(fixture),
+        "streamelements" => client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header(
+                "X-StreamElements-Signature",
+                sign::<HmacSha256>(&secret("STREAMELEMENTS_WEBHOOK_SECRET"), fixture.as_bytes()),
+            )
+            .body(fixture),
+        "streamlabs" => client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header(
+                "X-Streamlabs-Signature",
+                sign::<HmacSha256>(&secret("STREAMLABS_WEBHOOK_SECRET"), fixture.as_bytes()),
+            )
+            .body(fixture),
+        _ => anyhow::bail!("Unknown provider {:?}.", provider),
+    };
+
+    let response = request.send().await?;
+    println!("{} {}", response.status(), response.text().await?);
+    Ok(())
+}
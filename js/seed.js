@@ -34,6 +34,9 @@
 // https://chromewebstore.google.com/detail/content-security-policy-o/lhieoncdgamiiogcllfmboilhgoknmpi?hl=en
 // ["https://twitter\\.com", [["connect-src", "connect-src ws://127.0.0.2:1350"]]]
 //
+// Feeders that can't hold a websocket may instead POST the same JSON to
+// http://127.0.0.2:1350/api/v1/ingest, e.g. through GM_xmlhttpRequest.
+//
 
 (async function () {
     'use strict';
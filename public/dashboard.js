@@ -29,52 +29,89 @@ class ChatMessage {
 
 var socket = null;
 (function () {
-    // Create WebSocket connection.
-    socket = new WebSocket("ws://127.0.0.2:1350/chat.ws");
+    // Sequence number of the last event received, used to resume after reconnecting.
+    let last_seq = null;
+    const socket_url = () => {
+        if (last_seq !== null) {
+            return `ws://127.0.0.2:1350/chat.ws?since=${last_seq}`;
+        }
+        return "ws://127.0.0.2:1350/chat.ws?backlog=100";
+    };
+
     const reconnect = () => {
         // check if socket is connected
-        if (socket.readyState === WebSocket.OPEN || socket.readyState === WebSocket.CONNECTING) {
+        if (socket !== null && (socket.readyState === WebSocket.OPEN || socket.readyState === WebSocket.CONNECTING)) {
             return true;
         }
         // attempt to connect
-        socket = new WebSocket("ws://127.0.0.2:1350/chat.ws");
+        socket = new WebSocket(socket_url());
+        bindWebsocketEvents();
     };
 
-    // Connection opened
-    socket.addEventListener("open", (event) => {
-        console.log("[SNEED] Connection established.");
-    });
+    const bindWebsocketEvents = () => {
+        // Connection opened
+        socket.addEventListener("open", (event) => {
+            console.log("[SNEED] Connection established.");
+        });
+
+        // Listen for messages
+        socket.addEventListener("message", (event) => {
+            const data = JSON.parse(event.data);
+            const message = JSON.parse(data.message);
+            if (data.seq !== undefined) {
+                last_seq = data.seq;
+            }
+            switch (data.tag) {
+                case "chat_message":
+                    handle_message(message);
+                    break;
+                case "chat_message_edit":
+                    handle_message_edit(message);
+                    break;
+                case "chat_message_void":
+                    handle_message_void(message);
+                    break;
+                case "chat_message_delete":
+                    message.forEach((id) => handle_message_void(id));
+                    break;
+                case "feature_message":
+                    handle_feature_message(message);
+                    break;
+                case "viewers":
+                    handle_viewers(message);
+                    break;
+                case "sources":
+                    handle_sources(message);
+                    break;
+                case "marker":
+                    handle_marker(message);
+                    break;
+                case "config":
+                    document.title = `${message.branding.title} Dashboard`;
+                    break;
+                case "error":
+                    console.warn("[SNEED] Server refused request:", message.code, message.reason);
+                    break;
+                default:
+                    console.log("Unknown tag:", message.tag);
+                    break;
 
-    // Listen for messages
-    socket.addEventListener("message", (event) => {
-        const data = JSON.parse(event.data);
-        const message = JSON.parse(data.message);
-        switch (data.tag) {
-            case "chat_message":
-                handle_message(message);
-                break;
-            case "feature_message":
-                handle_feature_message(message);
-                break;
-            case "viewers":
-                handle_viewers(message);
-                break;
-            default:
-                console.log("Unknown tag:", message.tag);
-                break;
+            }
+        });
 
-        }
-    });
+        socket.addEventListener("close", (event) => {
+            console.log("[SNEED] Socket has closed. Attempting reconnect.", event.reason);
+            setTimeout(function () { reconnect(); }, 3000);
+        });
 
-    socket.addEventListener("close", (event) => {
-        console.log("[SNEED] Socket has closed. Attempting reconnect.", event.reason);
-        setTimeout(function () { reconnect(); }, 3000);
-    });
+        socket.addEventListener("error", (event) => {
+            socket.close();
+            setTimeout(function () { reconnect(); }, 3000);
+        });
+    };
 
-    socket.addEventListener("error", (event) => {
-        socket.close();
-        setTimeout(function () { reconnect(); }, 3000);
-    });
+    // Create WebSocket connection.
+    reconnect();
 })();
 
 function new_poll_option(count = 1) {
@@ -191,23 +228,85 @@ function send_message(msg) {
     socket.send(JSON.stringify(data));
 }
 
-function send_paid_message() {
-    let msg = new ChatMessage(uuidv5(), "none", "none");
-    msg.platform = document.getElementById("donation-platform")?.value;
-    msg.username = document.getElementById("donation-username")?.value;
-    msg.amount = parseFloat(document.getElementById("donation-amount")?.value);
-    msg.currency = document.getElementById("donation-currency")?.value;
-    msg.message = document.getElementById("donation-message")?.value;
+// Manual donations go through the API so they can be edited and voided later.
+async function donation_request(method, url, body) {
+    const res = await fetch(url, {
+        method: method,
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify(body),
+    });
+    if (!res.ok) {
+        const err = await res.json().catch(() => ({ error: res.statusText }));
+        alert(`Donation not saved: ${err.error}`);
+        return false;
+    }
+    return true;
+}
+
+function clear_paid_message() {
+    document.getElementById("donation-id").value = "";
+    document.getElementById("donation-amount").value = "";
+    document.getElementById("donation-message").value = "";
+    document.getElementById("donation-void").hidden = true;
+}
+
+async function send_paid_message() {
+    const id = document.getElementById("donation-id")?.value;
+    const donation = {
+        username: document.getElementById("donation-username")?.value,
+        amount: parseFloat(document.getElementById("donation-amount")?.value),
+        currency: document.getElementById("donation-currency")?.value,
+        message: document.getElementById("donation-message")?.value,
+    };
 
-    switch (msg.platform) {
-        case "mail":
-        case "usps":
-            msg.avatar = "/logo/United_States_Postal_Service_(emblem).png";
-            break;
+    let ok;
+    if (id) {
+        ok = await donation_request("PATCH", `/api/v1/donations/${id}`, donation);
+    }
+    else {
+        donation.platform = document.getElementById("donation-platform")?.value;
+        ok = await donation_request("POST", "/api/v1/donations", donation);
     }
 
-    console.log(msg);
-    return send_message(msg);
+    if (ok) {
+        clear_paid_message();
+    }
+}
+
+async function void_paid_message() {
+    const id = document.getElementById("donation-id")?.value;
+    if (!id) {
+        return;
+    }
+
+    const reason = prompt("Reason for voiding this donation:");
+    if (reason === null) {
+        return;
+    }
+
+    if (await donation_request("POST", `/api/v1/donations/${id}/void`, { reason: reason })) {
+        clear_paid_message();
+    }
+}
+
+// Load a donation into the form for editing.
+async function on_dblclick_donation(event) {
+    const res = await fetch(`/api/v1/messages/${this.id}`);
+    if (!res.ok) {
+        return;
+    }
+    const message = await res.json();
+
+    // message text is stored as html, edit it as plain text
+    const text = new DOMParser().parseFromString(message.message, "text/html").body.textContent;
+
+    document.getElementById("donation-id").value = message.id;
+    document.getElementById("donation-platform").value = message.platform;
+    document.getElementById("donation-username").value = message.username;
+    document.getElementById("donation-amount").value = message.amount;
+    document.getElementById("donation-currency").value = message.currency;
+    document.getElementById("donation-message").value = text;
+    document.getElementById("donation-void").hidden = false;
 }
 
 function send_simple_message(text, is_owner = false) {
@@ -231,6 +330,30 @@ function handle_feature_message(id) {
     }
 }
 
+function handle_message_edit(message) {
+    const el = document.getElementById(message.id);
+    if (el === null) {
+        return;
+    }
+
+    const sticky = el.classList.contains("msg--sticky");
+    el.outerHTML = message.html;
+
+    const new_el = document.getElementById(message.id);
+    if (sticky) {
+        new_el.classList.add("msg--sticky");
+    }
+    new_el.addEventListener("click", on_click_message);
+    new_el.addEventListener("dblclick", on_dblclick_donation);
+}
+
+function handle_message_void(id) {
+    document.getElementById(id)?.remove();
+    if (document.getElementById("donation-id").value === id) {
+        clear_paid_message();
+    }
+}
+
 function handle_message(message) {
     // check if element already exists
     const existingEl = document.getElementById(message.id);
@@ -264,12 +387,299 @@ function handle_message(message) {
     }
 
     document.getElementById(message.id).addEventListener("click", on_click_message);
+    if (message.amount > 0) {
+        document.getElementById(message.id).addEventListener("dblclick", on_dblclick_donation);
+    }
 }
 
 function handle_viewers() {
     // Do nothing.
 }
 
+const marker_list = document.querySelector("#marker-list");
+
+// Time since the session started, as H:MM:SS.
+function format_offset(offset) {
+    const secs = Math.floor(offset / 1000);
+    const mm = String(Math.floor(secs / 60) % 60).padStart(2, "0");
+    const ss = String(secs % 60).padStart(2, "0");
+    return `${Math.floor(secs / 3600)}:${mm}:${ss}`;
+}
+
+function handle_marker(marker) {
+    const el = document.createElement("li");
+    el.classList.add("marker", `marker--${marker.kind}`);
+    el.textContent = `${format_offset(marker.offset)} ${marker.label}`;
+    marker_list.prepend(el);
+}
+
+function add_marker() {
+    const input = document.querySelector("#marker-label");
+    const label = input.value.trim() || "Marker";
+    fetch("/api/v1/markers", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ label: label }),
+    })
+        .then((res) => {
+            if (res.ok) {
+                input.value = "";
+            }
+        })
+        .catch((err) => console.warn("[SNEED] Failed to add marker:", err));
+}
+
+function reload_config() {
+    const status = document.querySelector("#config-status");
+    status.textContent = "Reloading…";
+    fetch("/api/v1/config/reload", { method: "POST" })
+        .then((res) => res.json().then((body) => [res.ok, body]))
+        .then(([ok, body]) => {
+            if (!ok) {
+                status.textContent = body.error;
+                return;
+            }
+            const notes = body.warnings.slice();
+            if (body.needs_restart.length > 0) {
+                notes.push(`Restart to apply ${body.needs_restart.join(", ")}.`);
+            }
+            status.textContent = ["Reloaded."].concat(notes).join("\n");
+        })
+        .catch((err) => {
+            status.textContent = `Failed to reload: ${err}`;
+        });
+}
+
+fetch("/api/v1/markers")
+    .then((res) => res.json())
+    .then((markers) => markers.forEach(handle_marker))
+    .catch(() => { });
+
+const emote_packs = document.querySelector("#emote-packs");
+const emote_status = document.querySelector("#emote-status");
+
+// Sends a change to an emote pack, then shows the packs again.
+async function emote_request(method, url, body, content_type = "application/json") {
+    emote_status.textContent = "";
+    try {
+        const res = await fetch(url, {
+            method: method,
+            headers: body === undefined ? {} : { "Content-Type": content_type },
+            body: body,
+        });
+        if (!res.ok) {
+            const error = await res.json().catch(() => ({ error: res.statusText }));
+            emote_status.textContent = error.error;
+            return false;
+        }
+        load_emotes();
+        return true;
+    }
+    catch (err) {
+        emote_status.textContent = `Failed to update emotes: ${err}`;
+        return false;
+    }
+}
+
+function handle_emote_packs(packs) {
+    emote_packs.replaceChildren();
+    packs.forEach((pack) => {
+        const el = document.createElement("div");
+        el.classList.add("emote-pack");
+        if (!pack.enabled) {
+            el.classList.add("emote-pack--disabled");
+        }
+
+        const header = document.createElement("div");
+        header.classList.add("emote-pack-header");
+        const enabled = document.createElement("input");
+        enabled.type = "checkbox";
+        enabled.checked = pack.enabled;
+        const platforms = document.createElement("input");
+        platforms.type = "text";
+        platforms.placeholder = "All platforms";
+        platforms.value = pack.platforms.join(", ");
+        const save = () => emote_request("PUT", `/api/v1/emotes/${pack.name}`, JSON.stringify({
+            enabled: enabled.checked,
+            platforms: platforms.value.split(",").map((p) => p.trim()).filter((p) => p),
+        }));
+        enabled.addEventListener("change", save);
+        platforms.addEventListener("change", save);
+        const name = document.createElement("span");
+        name.classList.add("emote-pack-name");
+        name.textContent = pack.name;
+        const remove = document.createElement("button");
+        remove.textContent = "Delete";
+        remove.addEventListener("click", () => {
+            if (confirm(`Delete the ${pack.name} emote pack?`)) {
+                emote_request("DELETE", `/api/v1/emotes/${pack.name}`);
+            }
+        });
+        header.append(enabled, name, platforms, remove);
+        el.appendChild(header);
+
+        const list = document.createElement("div");
+        list.classList.add("emote-list");
+        for (const [code, url] of Object.entries(pack.emotes)) {
+            const img = document.createElement("img");
+            img.classList.add("emote");
+            img.src = url;
+            img.alt = code;
+            img.title = `${code} (click to remove)`;
+            img.addEventListener("click", () => {
+                if (confirm(`Remove ${code} from ${pack.name}?`)) {
+                    emote_request("DELETE", `/api/v1/emotes/${pack.name}/${code}`);
+                }
+            });
+            list.appendChild(img);
+        }
+        el.appendChild(list);
+        emote_packs.appendChild(el);
+    });
+}
+
+function load_emotes() {
+    fetch("/api/v1/emotes")
+        .then((res) => res.json())
+        .then(handle_emote_packs)
+        .catch(() => { });
+}
+
+async function add_emote() {
+    const pack = document.querySelector("#emote-pack").value.trim();
+    const code = document.querySelector("#emote-code").value.trim();
+    const file = document.querySelector("#emote-file");
+    const url = document.querySelector("#emote-url");
+    if (!pack || !code) {
+        emote_status.textContent = "A pack and code are required.";
+        return;
+    }
+
+    const target = `/api/v1/emotes/${encodeURIComponent(pack)}/${encodeURIComponent(code)}`;
+    let added;
+    if (file.files.length > 0) {
+        const image = file.files[0];
+        added = await emote_request("PUT", target, image, image.type || "application/octet-stream");
+    }
+    else if (url.value.trim()) {
+        added = await emote_request("PUT", target, JSON.stringify({ url: url.value.trim() }));
+    }
+    else {
+        emote_status.textContent = "Choose an image or give its URL.";
+        return;
+    }
+    if (added) {
+        document.querySelector("#emote-code").value = "";
+        file.value = "";
+        url.value = "";
+    }
+}
+
+load_emotes();
+
+const viewer_report = document.querySelector("#viewer-report");
+const viewer_chart = document.querySelector("#viewer-chart");
+
+// Chart this session's viewers over time, with the peak and average.
+function draw_viewer_history(samples, report) {
+    const ctx = viewer_chart.getContext("2d");
+    const width = viewer_chart.width;
+    const height = viewer_chart.height;
+    ctx.clearRect(0, 0, width, height);
+
+    if (samples.length === 0) {
+        viewer_report.textContent = "No viewers yet this session.";
+        return;
+    }
+
+    const peak_at = new Date(report.peak_at).toLocaleTimeString();
+    viewer_report.textContent = `Peak ${report.peak} at ${peak_at} · Average ${Math.round(report.average)}`;
+
+    const first = samples[0].at;
+    const span = Math.max(samples[samples.length - 1].at - first, 1);
+    const top = Math.max(report.peak, 1);
+    const x = (at) => (at - first) / span * width;
+    const y = (total) => height - total / top * (height - 10);
+
+    const style = getComputedStyle(document.body);
+    ctx.strokeStyle = style.getPropertyValue("--foreground-inactive") || "#888";
+    ctx.setLineDash([4, 4]);
+    ctx.beginPath();
+    ctx.moveTo(0, y(report.average));
+    ctx.lineTo(width, y(report.average));
+    ctx.stroke();
+
+    ctx.strokeStyle = style.getPropertyValue("--foreground") || "#fff";
+    ctx.setLineDash([]);
+    ctx.beginPath();
+    samples.forEach((sample, i) => {
+        if (i === 0) {
+            ctx.moveTo(x(sample.at), y(sample.total));
+        }
+        else {
+            ctx.lineTo(x(sample.at), y(sample.total));
+        }
+    });
+    ctx.stroke();
+}
+
+function refresh_viewer_history() {
+    Promise.all([
+        fetch("/api/v1/viewers/history").then((res) => res.json()),
+        fetch("/api/v1/viewers/report").then((res) => res.json()),
+    ])
+        .then(([samples, report]) => draw_viewer_history(samples, report))
+        .catch(() => { });
+}
+
+refresh_viewer_history();
+setInterval(refresh_viewer_history, 30000);
+
+const source_list = document.querySelector("#sources");
+const dashboard_title = document.title;
+
+// Show every chat source, flagging ones that dropped or went quiet while live.
+function handle_sources(sources) {
+    source_list.replaceChildren();
+
+    let warnings = 0;
+    sources.forEach((source) => {
+        const el = document.createElement("span");
+        el.classList.add("source");
+        const name = source.channel ? `${source.platform}/${source.channel}` : source.platform;
+        let status = `${Math.round(source.rate)}/min`;
+
+        if (!source.connected) {
+            el.classList.add("source--disconnected");
+            status = "disconnected";
+            warnings++;
+        }
+        else if (source.quiet) {
+            el.classList.add("source--quiet");
+            const since = source.last_message_at ?? source.connected_at;
+            status = `quiet ${Math.round((Date.now() - since) / 60000)}m`;
+            warnings++;
+        }
+
+        el.textContent = `${name} · ${status}`;
+        source_list.appendChild(el);
+    });
+
+    document.title = warnings > 0 ? `(${warnings}) ⚠ ${dashboard_title}` : dashboard_title;
+}
+
+// Rates change without a broadcast, so refresh them now and then.
+setInterval(() => {
+    fetch("/api/v1/sources")
+        .then((res) => res.json())
+        .then(handle_sources)
+        .catch(() => { });
+}, 15000);
+
 for (el of document.getElementsByClassName("msg")) {
     el.addEventListener("click", on_click_message);
+}
+
+for (el of donation_history.getElementsByClassName("msg")) {
+    el.addEventListener("dblclick", on_dblclick_donation);
 }
\ No newline at end of file
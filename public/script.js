@@ -1,15 +1,31 @@
 const chat_history = document.querySelector("#chat-messages");
 const feature_message = document.querySelector("#show-message");
 
+// Overlay profile settings, see templates/overlay.html. The chat page has none.
+const profile = document.body.dataset.profile ?? null;
+const max_messages = parseInt(document.body.dataset.maxMessages ?? "1000", 10);
+const stream_query = document.body.dataset.query ?? "";
+
+// Sequence number of the last event received, used to resume after reconnecting.
+let last_seq = null;
+const socket_url = () => {
+    const filter = stream_query !== "" ? `&${stream_query}` : "";
+    // resume where we left off, or ask for recent chat on a fresh page
+    if (last_seq !== null) {
+        return `ws://127.0.0.2:1350/chat.ws?since=${last_seq}${filter}`;
+    }
+    return `ws://127.0.0.2:1350/chat.ws?backlog=100${filter}`;
+};
+
 // Create WebSocket connection.
-let socket = new WebSocket("ws://127.0.0.2:1350/chat.ws");
+let socket = new WebSocket(socket_url());
 const reconnect = () => {
     // check if socket is connected
     if (socket.readyState === WebSocket.OPEN || socket.readyState === WebSocket.CONNECTING) {
         return true;
     }
     // attempt to connect
-    socket = new WebSocket("ws://127.0.0.2:1350/chat.ws");
+    socket = new WebSocket(socket_url());
     bindWebsocketEvents(socket);
 };
 
@@ -23,9 +39,21 @@ const bindWebsocketEvents = () => {
     socket.addEventListener("message", (event) => {
         const data = JSON.parse(event.data);
         const message = JSON.parse(data.message);
+        if (data.seq !== undefined) {
+            last_seq = data.seq;
+        }
         switch (data.tag) {
             case "chat_message":
-                handle_message(message);
+                handle_message(message, data.highlight === true);
+                break;
+            case "chat_message_edit":
+                handle_message_edit(message);
+                break;
+            case "chat_message_void":
+                handle_message_void(message);
+                break;
+            case "chat_message_delete":
+                message.forEach((id) => handle_message_void(id));
                 break;
             case "feature_message":
                 handle_feature_message(message);
@@ -33,6 +61,13 @@ const bindWebsocketEvents = () => {
             case "viewers":
                 handle_viewers(message);
                 break;
+            case "config":
+                handle_config(message);
+                break;
+            case "sources":
+            case "marker":
+                // Only the dashboard shows these.
+                break;
             default:
                 console.log("Unknown tag:", message.tag);
                 break;
@@ -73,6 +108,9 @@ function handle_emote(node, message) {
 
 function handle_feature_message(id) {
     // Check if message is a set or unset.
+    if (feature_message === null) {
+        return;
+    }
     if (id === null) {
         feature_message.innerHTML = "";
     }
@@ -90,7 +128,25 @@ function handle_feature_message(id) {
     }
 }
 
-function handle_message(message) {
+function handle_message_edit(message) {
+    const el = document.getElementById(message.id);
+    if (el !== null) {
+        el.outerHTML = message.html;
+    }
+
+    // refresh the featured copy too
+    const fel = document.getElementById(`feature-${message.id}`);
+    if (fel !== null) {
+        handle_feature_message(message.id);
+    }
+}
+
+function handle_message_void(id) {
+    document.getElementById(id)?.remove();
+    document.getElementById(`feature-${id}`)?.remove();
+}
+
+function handle_message(message, highlight = false) {
     // check if element already exists
     const existingEl = document.getElementById(message.id);
     if (existingEl !== null) {
@@ -109,6 +165,10 @@ function handle_message(message) {
     el.outerHTML = message.html;
     el = document.getElementById(message.id);
 
+    // matched one of this overlay's highlight keywords
+    if (highlight)
+        el.classList.add("msg--highlight");
+
     // apply premium style
     if (message.amount > 0)
         handle_premium(el, message);
@@ -117,7 +177,7 @@ function handle_message(message) {
     //    handle_emote(el, message);
 
     // remove first old message that is not sticky.
-    while (chat_history.children.length > 1000) {
+    while (chat_history.children.length > max_messages) {
         for (let i = 0; i < chat_history.children.length; i++) {
             let classes = chat_history.childNodes[i].classList;
             if (!classes.contains("msg--sticky") && !classes.contains("msg--t")) {
@@ -148,6 +208,8 @@ function handle_viewers(message) {
     let total = 0;
     console.log("VIEWERS", message);
 
+    // Every platform is sent each time, so forget ones that are gone.
+    window.livestream_viewers = {};
     for (const [key, value] of Object.entries(message)) {
         window.livestream_viewers[key] = parseInt(value, 10);
     }
@@ -157,7 +219,24 @@ function handle_viewers(message) {
     }
 
     total = Math.max(total, 0);
-    document.getElementById("live-totals").innerHTML = total;
+    const totals = document.getElementById("live-totals");
+    if (totals !== null) {
+        totals.innerHTML = total;
+    }
+}
+
+// Apply settings from a reloaded config without reloading the page.
+function handle_config(config) {
+    const branding = config.overlays[profile] ?? config.branding;
+    document.title = `${branding.title} Chat Overlay`;
+    const name = document.getElementById("name");
+    if (name !== null) {
+        name.textContent = branding.name;
+    }
+    const url = document.getElementById("url");
+    if (url !== null) {
+        url.textContent = branding.url;
+    }
 }
 
 function recalculate_premium_positions() {
@@ -375,6 +454,9 @@ function set_date(dateObj) {
     };
 
     const date = `${month} ${day}${nthNumber(day)}, ${year}`;
-    document.getElementById("date").innerHTML = date;
+    const el = document.getElementById("date");
+    if (el !== null) {
+        el.innerHTML = date;
+    }
 }
 set_date(new Date());
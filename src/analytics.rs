@@ -0,0 +1,274 @@
+//! Chat statistics for the current session, built up as messages arrive.
+//!
+//! Chatters are remembered across sessions in `chatters.jsonl` so each session can
+//! tell new chatters from returning ones.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::config;
+use crate::message::Message;
+
+const CHATTERS: &str = "chatters.jsonl";
+/// Emotes and words listed in a report.
+const TOP_LIMIT: usize = 20;
+/// Busiest minutes listed in a report.
+const PEAK_LIMIT: usize = 5;
+/// Shorter words are left out of the word counts.
+const MIN_WORD_LEN: usize = 3;
+const STOP_WORDS: &[&str] = &[
+    "and", "are", "but", "can", "for", "from", "had", "has", "have", "her", "him", "his", "how",
+    "its", "it's", "not", "she", "that", "the", "their", "them", "then", "there", "they", "this",
+    "was", "were", "what", "when", "who", "why", "will", "with", "you", "your",
+];
+
+/// First time a chatter was seen, in any session.
+#[derive(Serialize, Deserialize, Debug)]
+struct Chatter {
+    platform: String,
+    /// Lowercase.
+    username: String,
+    session: String,
+    /// Unix timestamp in milliseconds.
+    first_seen: i64,
+}
+
+/// Platform and lowercase username of every chatter from earlier sessions.
+fn known_chatters() -> HashSet<(String, String)> {
+    let f = match std::fs::File::open(config::get().paths.data_file(CHATTERS)) {
+        Ok(f) => f,
+        Err(_) => return HashSet::new(),
+    };
+
+    BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<Chatter>(&line).ok())
+        .map(|chatter| (chatter.platform, chatter.username))
+        .collect()
+}
+
+fn append_chatter(chatter: &Chatter) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::get().paths.data_file(CHATTERS))?;
+    writeln!(f, "{}", serde_json::to_string(chatter)?)
+}
+
+#[derive(Default)]
+struct PlatformStats {
+    messages: usize,
+    /// Lowercase usernames.
+    chatters: HashSet<String>,
+    new_chatters: usize,
+    returning_chatters: usize,
+}
+
+pub struct Analytics {
+    session: String,
+    /// Chatters seen in any session so far.
+    known: HashSet<(String, String)>,
+    platforms: BTreeMap<String, PlatformStats>,
+    /// Messages per platform, by the unix timestamp in milliseconds the minute starts at.
+    minutes: BTreeMap<i64, BTreeMap<String, usize>>,
+    emotes: HashMap<String, usize>,
+    words: HashMap<String, usize>,
+}
+
+impl Analytics {
+    pub fn new(session: &str) -> Self {
+        Self {
+            session: session.to_owned(),
+            known: known_chatters(),
+            platforms: BTreeMap::new(),
+            minutes: BTreeMap::new(),
+            emotes: HashMap::new(),
+            words: HashMap::new(),
+        }
+    }
+
+    /// Counts a chat message. `msg.message` must be the text as sent, before rendering.
+    pub fn record(&mut self, msg: &Message) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let stats = self.platforms.entry(msg.platform.to_owned()).or_default();
+        stats.messages += 1;
+
+        let username = msg.username.to_lowercase();
+        if stats.chatters.insert(username.to_owned()) {
+            let key = (msg.platform.to_owned(), username);
+            if self.known.contains(&key) {
+                stats.returning_chatters += 1;
+            } else {
+                stats.new_chatters += 1;
+                let chatter = Chatter {
+                    platform: key.0.to_owned(),
+                    username: key.1.to_owned(),
+                    session: self.session.to_owned(),
+                    first_seen: now,
+                };
+                if let Err(err) = append_chatter(&chatter) {
+                    log::error!("Failed to record new chatter: {:?}", err);
+                }
+                self.known.insert(key);
+            }
+        }
+
+        let minute = now - now.rem_euclid(60_000);
+        *self
+            .minutes
+            .entry(minute)
+            .or_default()
+            .entry(msg.platform.to_owned())
+            .or_default() += 1;
+
+        let mut text = msg.message.to_owned();
+        for (find, _, name) in &msg.emojis {
+            let count = text.matches(find.as_str()).count();
+            if count > 0 {
+                *self.emotes.entry(name.to_owned()).or_default() += count;
+                text = text.replace(find.as_str(), " ");
+            }
+        }
+
+        for word in text.split_whitespace() {
+            if word.starts_with("http://") || word.starts_with("https://") {
+                continue;
+            }
+            let word = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if word.chars().count() < MIN_WORD_LEN
+                || word.chars().all(|c| c.is_numeric())
+                || STOP_WORDS.contains(&word.as_str())
+            {
+                continue;
+            }
+            *self.words.entry(word).or_default() += 1;
+        }
+    }
+
+    pub fn report(&self) -> AnalyticsReport {
+        let generated_at = chrono::Utc::now().timestamp_millis();
+        let first_at = self.minutes.keys().next().copied();
+        // Minutes from the first message until now, at least one.
+        let minutes = first_at.map_or(1.0, |first| {
+            ((generated_at - first) as f64 / 60_000.0).max(1.0)
+        });
+
+        let platforms: Vec<PlatformReport> = self
+            .platforms
+            .iter()
+            .map(|(platform, stats)| PlatformReport {
+                platform: platform.to_owned(),
+                messages: stats.messages,
+                messages_per_minute: stats.messages as f64 / minutes,
+                chatters: stats.chatters.len(),
+                new_chatters: stats.new_chatters,
+                returning_chatters: stats.returning_chatters,
+            })
+            .collect();
+        let messages = platforms.iter().map(|p| p.messages).sum();
+
+        let timeline: Vec<Minute> = self
+            .minutes
+            .iter()
+            .map(|(at, platforms)| Minute {
+                at: *at,
+                messages: platforms.values().sum(),
+                platforms: platforms.to_owned(),
+            })
+            .collect();
+        let mut peaks = timeline.to_owned();
+        peaks.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.at.cmp(&b.at)));
+        peaks.truncate(PEAK_LIMIT);
+
+        AnalyticsReport {
+            session: self.session.to_owned(),
+            generated_at,
+            first_message_at: first_at,
+            messages,
+            messages_per_minute: messages as f64 / minutes,
+            chatters: platforms.iter().map(|p| p.chatters).sum(),
+            new_chatters: platforms.iter().map(|p| p.new_chatters).sum(),
+            returning_chatters: platforms.iter().map(|p| p.returning_chatters).sum(),
+            platforms,
+            top_emotes: top(&self.emotes),
+            top_words: top(&self.words),
+            peaks,
+            timeline,
+        }
+    }
+}
+
+/// The most frequent entries, most frequent first.
+fn top(counts: &HashMap<String, usize>) -> Vec<Count> {
+    let mut top: Vec<Count> = counts
+        .iter()
+        .map(|(name, count)| Count {
+            name: name.to_owned(),
+            count: *count,
+        })
+        .collect();
+    top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    top.truncate(TOP_LIMIT);
+    top
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Count {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Minute {
+    /// Unix timestamp in milliseconds of the start of the minute.
+    pub at: i64,
+    pub messages: usize,
+    pub platforms: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlatformReport {
+    pub platform: String,
+    pub messages: usize,
+    pub messages_per_minute: f64,
+    /// Unique chatters this session.
+    pub chatters: usize,
+    /// Chatters never seen in an earlier session.
+    pub new_chatters: usize,
+    pub returning_chatters: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AnalyticsReport {
+    pub session: String,
+    /// Unix timestamp in milliseconds.
+    pub generated_at: i64,
+    /// Unix timestamp in milliseconds of the start of the first minute with chat.
+    pub first_message_at: Option<i64>,
+    pub messages: usize,
+    pub messages_per_minute: f64,
+    pub chatters: usize,
+    pub new_chatters: usize,
+    pub returning_chatters: usize,
+    pub platforms: Vec<PlatformReport>,
+    pub top_emotes: Vec<Count>,
+    pub top_words: Vec<Count>,
+    /// Busiest minutes, busiest first.
+    pub peaks: Vec<Minute>,
+    /// Messages in every minute with chat, oldest first.
+    pub timeline: Vec<Minute>,
+}
+
+/// Writes the report to `analytics-<session>.json` in the data directory and returns the path.
+pub fn save_report(report: &AnalyticsReport) -> std::io::Result<String> {
+    let path = config::get()
+        .paths
+        .data_file(&format!("analytics-{}.json", report.session));
+    std::fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    Ok(path.display().to_string())
+}
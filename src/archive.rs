@@ -0,0 +1,118 @@
+//! Every chat message as it was received, for exports and replays.
+//!
+//! Messages are kept before rendering, with their original text, emotes and currency.
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::config;
+use crate::message::Message;
+
+const ARCHIVE: &str = "chat.jsonl";
+/// Session ids are the local time the server started, in this format.
+pub const SESSION_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchivedMessage {
+    session: String,
+    message: Message,
+}
+
+/// The archive file, kept open by the chat server while it runs.
+#[derive(Default)]
+pub struct Archive {
+    /// Opened on first use, and again after a failed write.
+    file: Option<BufWriter<File>>,
+}
+
+impl Archive {
+    pub fn append(&mut self, session: &str, message: &Message) -> std::io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(config::get().paths.data_file(ARCHIVE))?,
+            )),
+        };
+        let archived = ArchivedMessage {
+            session: session.to_owned(),
+            message: message.to_owned(),
+        };
+        let written = writeln!(file, "{}", serde_json::to_string(&archived)?);
+        if written.is_err() {
+            self.file = None;
+        }
+        written
+    }
+
+    /// Writes out buffered messages. Call it often so a crash loses little chat.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The whole archive, read once for a command that needs it.
+pub struct Archived(Vec<ArchivedMessage>);
+
+pub fn read() -> Archived {
+    let f = match File::open(config::get().paths.data_file(ARCHIVE)) {
+        Ok(f) => f,
+        Err(_) => return Archived(Vec::new()),
+    };
+
+    Archived(
+        BufReader::new(f)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| match serde_json::from_str(&line) {
+                Ok(archived) => Some(archived),
+                Err(err) => {
+                    log::warn!("Skipping unreadable archived message: {:?}", err);
+                    None
+                }
+            })
+            .collect(),
+    )
+}
+
+impl Archived {
+    /// Messages received during `session`, in the order they arrived.
+    pub fn messages(&self, session: &str) -> Vec<Message> {
+        let mut messages: Vec<Message> = self
+            .0
+            .iter()
+            .filter(|archived| archived.session == session)
+            .map(|archived| archived.message.to_owned())
+            .collect();
+        messages.sort_by_key(|msg| msg.received_at);
+        messages
+    }
+
+    /// Every session in the archive, oldest first.
+    pub fn sessions(&self) -> Vec<String> {
+        let mut sessions: Vec<String> = self
+            .0
+            .iter()
+            .map(|archived| archived.session.to_owned())
+            .collect();
+        sessions.sort();
+        sessions.dedup();
+        sessions
+    }
+}
+
+/// Unix timestamp in milliseconds a session started at, from its id.
+pub fn session_start(session: &str) -> Option<i64> {
+    NaiveDateTime::parse_from_str(session, SESSION_FORMAT)
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|start| start.timestamp_millis())
+}
@@ -0,0 +1,92 @@
+//! Append-only log of every change made to manual donations.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use uuid::Uuid;
+
+use crate::config;
+use crate::message::Message;
+
+const AUDIT_LOG: &str = "donations_audit.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Edit,
+    Void,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    /// Unix timestamp in milliseconds.
+    pub at: i64,
+    pub action: AuditAction,
+    pub id: Uuid,
+    /// The donation as it was before this change, for edits and voids.
+    pub before: Option<Message>,
+    /// The donation after this change, for creates and edits.
+    pub after: Option<Message>,
+    /// Operator's note, e.g. why a donation was voided.
+    pub reason: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction, id: Uuid) -> Self {
+        Self {
+            at: chrono::Utc::now().timestamp_millis(),
+            action,
+            id,
+            before: None,
+            after: None,
+            reason: None,
+        }
+    }
+}
+
+pub fn append(entry: &AuditEntry) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::get().paths.data_file(AUDIT_LOG))?;
+    writeln!(f, "{}", serde_json::to_string(entry)?)
+}
+
+/// Every entry in the log, oldest first. Unreadable lines are skipped.
+pub fn entries() -> Vec<AuditEntry> {
+    let f = match std::fs::File::open(config::get().paths.data_file(AUDIT_LOG)) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+
+    BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                log::warn!("Skipping unreadable audit log entry: {:?}", err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Manual donations that have been created and not voided.
+pub fn open_donations() -> HashSet<Uuid> {
+    let mut ids = HashSet::new();
+    for entry in entries() {
+        match entry.action {
+            AuditAction::Create => {
+                ids.insert(entry.id);
+            }
+            AuditAction::Void => {
+                ids.remove(&entry.id);
+            }
+            AuditAction::Edit => (),
+        }
+    }
+    ids
+}
@@ -0,0 +1,246 @@
+//! `stream-nexus export`: chat from the archive, superchats from the ledger.
+
+use clap::{Args, ValueEnum};
+use std::path::PathBuf;
+
+use super::write_output;
+use crate::archive::{self, Archived};
+use crate::ledger::{self, Export, LedgerFilter};
+use crate::message::Message;
+use crate::text::{csv_field, one_line};
+
+/// Seconds each chat subtitle stays on screen.
+const SUBTITLE_SECS: i64 = 5;
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[arg(value_enum)]
+    what: ExportKind,
+    /// Output format. Defaults to jsonl for chat, csv for superchats and srt for subtitles.
+    #[arg(short, long, value_enum)]
+    format: Option<ExportFormat>,
+    /// Session id, e.g. 20240301-190000. Chat and subtitles default to the latest session,
+    /// superchats to every session.
+    #[arg(short, long)]
+    session: Option<String>,
+    /// Superchats sent from this date or RFC 3339 timestamp.
+    #[arg(long)]
+    from: Option<String>,
+    /// Superchats sent up to this date, inclusive, or RFC 3339 timestamp.
+    #[arg(long)]
+    to: Option<String>,
+    /// Seconds to shift subtitles by, e.g. if the recording started before the server.
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    offset: i64,
+    /// File to write. Defaults to stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportKind {
+    Chat,
+    Superchats,
+    Subtitles,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Jsonl,
+    Json,
+    Csv,
+    Txt,
+    Srt,
+    Vtt,
+}
+
+pub fn run(args: ExportArgs) -> anyhow::Result<()> {
+    let text = match args.what {
+        ExportKind::Chat => {
+            let archived = archive::read();
+            let messages = archived.messages(&chat_session(&archived, &args.session)?);
+            match args.format.unwrap_or(ExportFormat::Jsonl) {
+                ExportFormat::Jsonl => to_jsonl(&messages)?,
+                ExportFormat::Csv => to_csv(&messages),
+                ExportFormat::Txt => to_txt(&messages),
+                format => return Err(unsupported("Chat", format)),
+            }
+        }
+        ExportKind::Superchats => {
+            let mut filter = LedgerFilter {
+                session: args.session.to_owned(),
+                ..Default::default()
+            };
+            for (value, bound, end_of_day) in [
+                (&args.from, &mut filter.from, false),
+                (&args.to, &mut filter.to, true),
+            ] {
+                if let Some(value) = value {
+                    *bound = Some(ledger::parse_time(value, end_of_day).ok_or_else(|| {
+                        anyhow::anyhow!("Expected a date or RFC 3339 timestamp, got {:?}.", value)
+                    })?);
+                }
+            }
+            let donations = ledger::donations(&filter);
+            let subtotals = ledger::subtotals(&donations);
+            match args.format.unwrap_or(ExportFormat::Csv) {
+                ExportFormat::Csv => ledger::to_csv(&donations, &subtotals),
+                ExportFormat::Json => {
+                    serde_json::to_string_pretty(&Export::new(donations, subtotals))? + "\n"
+                }
+                format => return Err(unsupported("Superchats", format)),
+            }
+        }
+        ExportKind::Subtitles => {
+            let archived = archive::read();
+            let session = chat_session(&archived, &args.session)?;
+            let messages = archived.messages(&session);
+            let start = archive::session_start(&session)
+                .or_else(|| messages.first().map(|msg| msg.received_at))
+                .unwrap_or_default()
+                - args.offset * 1000;
+            match args.format.unwrap_or(ExportFormat::Srt) {
+                ExportFormat::Srt => to_srt(&messages, start),
+                ExportFormat::Vtt => to_vtt(&messages, start),
+                format => return Err(unsupported("Subtitles", format)),
+            }
+        }
+    };
+    write_output(&args.output, &text)
+}
+
+fn unsupported(what: &str, format: ExportFormat) -> anyhow::Error {
+    let name = format
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+        .unwrap_or_default();
+    anyhow::anyhow!("{} can't be exported as {}.", what, name)
+}
+
+/// The session asked for, or the latest one in the chat archive.
+fn chat_session(archived: &Archived, session: &Option<String>) -> anyhow::Result<String> {
+    match session {
+        Some(session) => Ok(session.to_owned()),
+        None => archived
+            .sessions()
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("The chat archive is empty.")),
+    }
+}
+
+fn to_jsonl(messages: &[Message]) -> anyhow::Result<String> {
+    let mut jsonl = String::new();
+    for msg in messages {
+        jsonl.push_str(&serde_json::to_string(msg)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+fn rfc3339(at: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(at)
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn to_csv(messages: &[Message]) -> String {
+    let mut csv = String::from("id,received_at,platform,username,message,amount,currency\n");
+    for msg in messages {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            msg.id,
+            rfc3339(msg.received_at),
+            csv_field(&msg.platform),
+            csv_field(&msg.username),
+            csv_field(&msg.message),
+            msg.amount,
+            csv_field(&msg.currency)
+        ));
+    }
+    csv
+}
+
+/// What a chat line says, with the donation if there is one.
+fn chat_line(msg: &Message) -> String {
+    if msg.amount > 0.0 {
+        format!(
+            "{} ({:.2} {}): {}",
+            msg.username,
+            msg.amount,
+            msg.currency,
+            one_line(&msg.message)
+        )
+    } else {
+        format!("{}: {}", msg.username, one_line(&msg.message))
+    }
+}
+
+fn to_txt(messages: &[Message]) -> String {
+    let mut txt = String::new();
+    for msg in messages {
+        let at = chrono::DateTime::from_timestamp_millis(msg.received_at)
+            .map(|dt| {
+                dt.with_timezone(&chrono::Local)
+                    .format("%H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        txt.push_str(&format!("[{}] [{}] {}\n", at, msg.platform, chat_line(msg)));
+    }
+    txt
+}
+
+/// `HH:MM:SS` and milliseconds after `separator`, as subtitle formats want it.
+fn subtitle_time(offset: i64, separator: char) -> String {
+    let offset = offset.max(0);
+    let secs = offset / 1000;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        separator,
+        offset % 1000
+    )
+}
+
+/// Each message with its start and end in milliseconds since `start`.
+/// Messages from before `start` are left out.
+fn cues(messages: &[Message], start: i64) -> impl Iterator<Item = (i64, i64, String)> + '_ {
+    messages
+        .iter()
+        .filter(move |msg| msg.received_at >= start)
+        .map(move |msg| {
+            let at = msg.received_at - start;
+            (at, at + SUBTITLE_SECS * 1000, chat_line(msg))
+        })
+}
+
+fn to_srt(messages: &[Message], start: i64) -> String {
+    let mut srt = String::new();
+    for (i, (from, to, text)) in cues(messages, start).enumerate() {
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            subtitle_time(from, ','),
+            subtitle_time(to, ','),
+            text
+        ));
+    }
+    srt
+}
+
+fn to_vtt(messages: &[Message], start: i64) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for (from, to, text) in cues(messages, start) {
+        // "-->" would end the cue timing early.
+        let text = text.replace("-->", "->");
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            subtitle_time(from, '.'),
+            subtitle_time(to, '.'),
+            text
+        ));
+    }
+    vtt
+}
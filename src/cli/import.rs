@@ -0,0 +1,67 @@
+//! `stream-nexus import`: superchats from `super_chats.json` into the donation ledger.
+
+use clap::Args;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::archive;
+use crate::config;
+use crate::ledger::{self, LedgerEntry};
+use crate::message::Message;
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// Superchats saved by an older version. Defaults to super_chats.json in the data directory.
+    file: Option<PathBuf>,
+    /// Session to record them under. Defaults to the time of the first superchat.
+    #[arg(short, long)]
+    session: Option<String>,
+}
+
+pub fn run(args: ImportArgs) -> anyhow::Result<()> {
+    let path = args
+        .file
+        .unwrap_or_else(|| config::get().paths.data_file("super_chats.json"));
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path.display(), err))?;
+    let mut super_chats: Vec<Message> = serde_json::from_str(&text)
+        .map_err(|err| anyhow::anyhow!("{} is invalid: {}", path.display(), err))?;
+    super_chats.sort_by_key(|msg| msg.sent_at);
+
+    let session = match args.session {
+        Some(session) => session,
+        None => super_chats
+            .first()
+            .and_then(|msg| chrono::DateTime::from_timestamp_millis(msg.sent_at))
+            .map(|dt| {
+                dt.with_timezone(&chrono::Local)
+                    .format(archive::SESSION_FORMAT)
+                    .to_string()
+            })
+            .unwrap_or_default(),
+    };
+
+    let mut recorded: HashSet<_> = ledger::entries()
+        .into_iter()
+        .map(|entry| entry.id)
+        .collect();
+    let (mut imported, mut skipped) = (0, 0);
+    for msg in super_chats.iter().filter(|msg| msg.amount > 0.0) {
+        if !recorded.insert(msg.id) {
+            skipped += 1;
+            continue;
+        }
+        // Saved superchats were already converted, so the original amount is lost.
+        ledger::append(&LedgerEntry::new(&session, msg, msg.amount, &msg.currency))?;
+        imported += 1;
+    }
+
+    println!(
+        "Imported {} superchat(s) from {} as session {}, skipped {} already in the ledger.",
+        imported,
+        path.display(),
+        session,
+        skipped
+    );
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Command line interface. Every command but `serve` works on the data directory or
+//! talks to a running server, so they can be used while it runs.
+
+mod export;
+mod import;
+mod rates;
+mod send;
+
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Severity};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Unifies livestream chats from many platforms.")]
+pub struct Cli {
+    /// Config file. Defaults to config.toml, which may be missing.
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the chat server. This is the default.
+    Serve,
+    /// Write a session's chat, superchats or chat subtitles.
+    Export(export::ExportArgs),
+    /// Send a past session's chat to a running server again, as it happened.
+    Replay(send::ReplayArgs),
+    /// Add superchats saved by older versions to the donation ledger.
+    Import(import::ImportArgs),
+    /// Show, refresh or override exchange rates.
+    Rates {
+        #[command(subcommand)]
+        command: rates::RatesCommand,
+    },
+    /// Send a test message to a running server.
+    Send(send::SendArgs),
+    /// Config file tools.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Check the config for errors and print what the server would use.
+    Check,
+}
+
+/// Runs any command but `serve`. Returns whether it succeeded.
+pub async fn run(command: Command, config: &Config, path: &Path) -> anyhow::Result<bool> {
+    match command {
+        Command::Serve => unreachable!("serve is run by main."),
+        Command::Export(args) => export::run(args)?,
+        Command::Replay(args) => send::replay(args, config).await?,
+        Command::Import(args) => import::run(args)?,
+        Command::Rates { command } => rates::run(command, config).await?,
+        Command::Send(args) => send::send(args, config).await?,
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => return Ok(check_config(config, path)),
+    }
+    Ok(true)
+}
+
+/// Prints every problem with `config` and returns whether it can be served.
+fn check_config(config: &Config, path: &Path) -> bool {
+    let problems = config.check();
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        println!("{}: {} error(s).", path.display(), errors);
+        return false;
+    }
+
+    if let Some(addr) = config.listen_addr() {
+        if let Err(err) = std::net::TcpListener::bind(addr) {
+            println!("warning: Can't listen on {} right now: {}", addr, err);
+        }
+        println!("Listens on {}", addr);
+    }
+    println!("Data in {}", config.paths.data.display());
+    println!(
+        "Overlays: {}",
+        config.overlays().into_keys().collect::<Vec<_>>().join(", ")
+    );
+    println!(
+        "{} of {} connector(s) enabled",
+        config.connectors.iter().filter(|c| c.enabled).count(),
+        config.connectors.len()
+    );
+    println!("{}: OK", path.display());
+    true
+}
+
+/// Writes `text` to `output`, or to stdout without one.
+fn write_output(output: &Option<PathBuf>, text: &str) -> anyhow::Result<()> {
+    match output {
+        Some(path) => std::fs::write(path, text)
+            .map_err(|err| anyhow::anyhow!("Failed to write {}: {}", path.display(), err)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! `stream-nexus rates`: the exchange rates donations are converted with.
+
+use clap::Subcommand;
+
+use crate::config::Config;
+use crate::exchange::{self, ExchangeRates};
+
+#[derive(Subcommand, Debug)]
+pub enum RatesCommand {
+    /// Print the saved rates, with overrides applied.
+    Show {
+        /// Only these currencies, e.g. GBP JPY.
+        currencies: Vec<String>,
+    },
+    /// Fetch the latest published rates and save them.
+    Refresh,
+    /// Use RATE units of CURRENCY per euro in place of the published rate.
+    Set {
+        currency: String,
+        #[arg(allow_negative_numbers = true)]
+        rate: f64,
+    },
+    /// Go back to the published rate for CURRENCY.
+    Unset { currency: String },
+}
+
+pub async fn run(command: RatesCommand, config: &Config) -> anyhow::Result<()> {
+    let backup = config.paths.data_file(exchange::BACKUP);
+    let overrides_path = config.paths.data_file(exchange::OVERRIDES);
+    let mut overrides = exchange::overrides(&overrides_path)?;
+
+    match command {
+        RatesCommand::Show { currencies } => {
+            let rates = exchange::load_backup(&backup)?.with_overrides(&overrides);
+            let currencies: Vec<String> = currencies.iter().map(|c| c.to_uppercase()).collect();
+            print_rates(&rates, &currencies, |currency| {
+                overrides.contains_key(currency)
+            });
+        }
+        RatesCommand::Refresh => {
+            let rates = exchange::download(&config.exchange.url, &backup).await?;
+            println!(
+                "Saved {} rates from {} to {}.",
+                rates.rates().len(),
+                config.exchange.url,
+                backup.display()
+            );
+        }
+        RatesCommand::Set { currency, rate } => {
+            if !rate.is_finite() || rate <= 0.0 {
+                anyhow::bail!("The rate must be more than 0.");
+            }
+            let currency = currency.to_uppercase();
+            overrides.insert(currency.to_owned(), rate);
+            exchange::save_overrides(&overrides_path, &overrides)?;
+            println!(
+                "{} is now {} per EUR. Restart the server to use it.",
+                currency, rate
+            );
+        }
+        RatesCommand::Unset { currency } => {
+            let currency = currency.to_uppercase();
+            if overrides.remove(&currency).is_none() {
+                anyhow::bail!("{} has no override.", currency);
+            }
+            exchange::save_overrides(&overrides_path, &overrides)?;
+            println!(
+                "{} uses the published rate again. Restart the server to use it.",
+                currency
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_rates(rates: &ExchangeRates, only: &[String], is_override: impl Fn(&str) -> bool) {
+    println!("{:<8} {:>14} {:>14}", "currency", "per EUR", "USD each");
+    for (currency, rate) in rates.rates() {
+        if !only.is_empty() && !only.iter().any(|c| c == currency) {
+            continue;
+        }
+        println!(
+            "{:<8} {:>14.4} {:>14.6}{}",
+            currency,
+            rate,
+            rates.get_usd(currency, &1.0),
+            if is_override(currency) {
+                "  (override)"
+            } else {
+                ""
+            }
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! `stream-nexus send` and `replay`: chat posted to a running server's ingest endpoint.
+//! Both are marked as tests, so the server keeps them out of its records.
+
+use clap::Args;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::archive;
+use crate::config::Config;
+use crate::message::{LivestreamUpdate, Message};
+use crate::web::IngestAck;
+
+/// Most messages replayed in one request.
+const REPLAY_BATCH: usize = 100;
+
+#[derive(Args, Debug)]
+struct ServerArgs {
+    /// URL of the running server. Defaults to the address in the config.
+    #[arg(long)]
+    server: Option<String>,
+    /// Ingest token. Defaults to ingest.token from the config.
+    #[arg(long)]
+    token: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct SendArgs {
+    /// Text of the message.
+    message: String,
+    #[arg(short, long, default_value = "test")]
+    platform: String,
+    #[arg(short, long, default_value = "Tester")]
+    username: String,
+    /// Makes it a superchat.
+    #[arg(short, long, default_value_t = 0.0)]
+    amount: f64,
+    #[arg(long, default_value = "USD")]
+    currency: String,
+    /// Avatar image URL.
+    #[arg(long, default_value = "")]
+    avatar: String,
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Session id, e.g. 20240301-190000. Defaults to the latest session.
+    #[arg(short, long)]
+    session: Option<String>,
+    /// How many times faster than it happened. 0 sends everything at once.
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
+/// A running server's ingest endpoint.
+struct Ingest {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl Ingest {
+    fn new(args: &ServerArgs, config: &Config) -> anyhow::Result<Self> {
+        let server = match &args.server {
+            Some(server) => server.trim_end_matches('/').to_owned(),
+            None => {
+                let addr = config
+                    .listen_addr()
+                    .ok_or_else(|| anyhow::anyhow!("server.ip in the config is invalid."))?;
+                // A server listening on every address can be reached on loopback.
+                let ip = match addr.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    ip => ip,
+                };
+                format!("http://{}", SocketAddr::new(ip, addr.port()))
+            }
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: format!("{}/api/v1/ingest", server),
+            token: args
+                .token
+                .to_owned()
+                .unwrap_or_else(|| config.ingest.token.to_owned()),
+        })
+    }
+
+    async fn post(&self, updates: &[LivestreamUpdate]) -> anyhow::Result<IngestAck> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(updates)?);
+        if !self.token.is_empty() {
+            request = request.bearer_auth(&self.token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to reach {}: {}", self.url, err))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("{} answered {}: {}", self.url, status, body);
+        }
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+}
+
+/// An update carrying just `msg`.
+fn update(msg: Message) -> LivestreamUpdate {
+    LivestreamUpdate {
+        platform: msg.platform.to_owned(),
+        channel: None,
+        messages: Some(vec![msg]),
+        viewers: None,
+    }
+}
+
+pub async fn send(args: SendArgs, config: &Config) -> anyhow::Result<()> {
+    let ingest = Ingest::new(&args.server, config)?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let msg = Message {
+        id: Uuid::new_v4(),
+        platform: args.platform,
+        message: args.message,
+        text: String::new(),
+        emojis: Vec::new(),
+        sent_at: now,
+        received_at: now,
+        username: args.username,
+        user_id: None,
+        channel: None,
+        avatar: args.avatar,
+        color: None,
+        amount: args.amount,
+        currency: args.currency.to_uppercase(),
+        is_verified: false,
+        is_sub: false,
+        is_mod: false,
+        is_owner: false,
+        is_staff: false,
+        is_test: true,
+    };
+    let id = msg.id;
+
+    let ack = ingest.post(&[update(msg)]).await?;
+    if !ack.accepted.contains(&id) {
+        anyhow::bail!("The server did not accept the message.");
+    }
+    println!("Sent {}.", id);
+    Ok(())
+}
+
+pub async fn replay(args: ReplayArgs, config: &Config) -> anyhow::Result<()> {
+    if !args.speed.is_finite() || args.speed < 0.0 {
+        anyhow::bail!("--speed must be 0 or more.");
+    }
+    let archived = archive::read();
+    let session = match args.session {
+        Some(session) => session,
+        None => archived
+            .sessions()
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("The chat archive is empty."))?,
+    };
+    let messages = archived.messages(&session);
+    let Some(first_at) = messages.first().map(|msg| msg.received_at) else {
+        anyhow::bail!("Session {} has no chat.", session);
+    };
+    let ingest = Ingest::new(&args.server, config)?;
+
+    println!(
+        "Replaying {} messages from session {}.",
+        messages.len(),
+        session
+    );
+    // When a message is due, counted from the start of the replay.
+    let due = |msg: &Message| {
+        if args.speed == 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((msg.received_at - first_at) as f64 / 1000.0 / args.speed)
+        }
+    };
+    let started = Instant::now();
+    let mut accepted = 0;
+    let mut pending = messages.into_iter().peekable();
+    while let Some(next) = pending.peek() {
+        if let Some(wait) = due(next).checked_sub(started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+
+        // Everything due by now goes in one request.
+        let mut batch = Vec::new();
+        while let Some(msg) =
+            pending.next_if(|msg| batch.len() < REPLAY_BATCH && due(msg) <= started.elapsed())
+        {
+            // New ids, or the server would drop them as duplicates of the originals.
+            let now = chrono::Utc::now().timestamp_millis();
+            batch.push(update(Message {
+                id: Uuid::new_v4(),
+                sent_at: now,
+                received_at: now,
+                is_test: true,
+                ..msg
+            }));
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        accepted += ingest.post(&batch).await?.accepted.len();
+    }
+
+    println!("Replayed {} messages.", accepted);
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Username colors, the same for a chatter every time they talk.
+//!
+//! A chatter keeps the color they chose on their platform, if it sent one and
+//! `colors.platform_colors` is on. Anyone else gets a color from `colors.palette`,
+//! picked by hashing their platform and username. Palette colors are served as CSS
+//! variables in `user-colors.css`, so a theme can restyle them.
+//!
+//! Authors with a badge get no palette color, so without a platform color the
+//! overlay's role colors for owners, staff, mods, verified accounts and subs still
+//! tell them apart.
+
+use sha2::{Digest, Sha256};
+
+use crate::config;
+use crate::message::Message;
+use crate::platform;
+
+/// Whether `color` is a `#rrggbb` color, safe to put in a style attribute.
+pub fn is_hex(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Which palette color a chatter gets, out of `len`.
+fn pick(platform: &str, username: &str, len: usize) -> usize {
+    // Aliases like yt and YouTube are the same platform, so they share colors.
+    let platform = match platform::find(platform) {
+        Some(platform) => platform.id.to_owned(),
+        None => platform.trim().to_lowercase(),
+    };
+    let hash = Sha256::digest(format!("{}\n{}", platform, username.trim().to_lowercase()));
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    (u64::from_be_bytes(bytes) % len as u64) as usize
+}
+
+/// The color `msg`'s author should be shown in. `None` leaves it to the stylesheet.
+pub fn assign(msg: &Message) -> Option<String> {
+    let colors = &config::get().colors;
+    if colors.platform_colors {
+        if let Some(color) = msg.color.as_deref().filter(|color| is_hex(color)) {
+            return Some(color.to_owned());
+        }
+    }
+    if colors.palette.is_empty() || !msg.badges().is_empty() {
+        return None;
+    }
+    let index = pick(&msg.platform, &msg.username, colors.palette.len());
+    Some(colors.palette[index].to_owned())
+}
+
+/// Where `color` is in the palette, so it can be styled with its variable.
+pub fn palette_index(color: &str) -> Option<usize> {
+    config::get()
+        .colors
+        .palette
+        .iter()
+        .position(|other| other.eq_ignore_ascii_case(color))
+}
+
+/// The palette as `--user-color-{n}` variables, with a class for each.
+pub fn stylesheet() -> String {
+    let palette = &config::get().colors.palette;
+    let mut css = String::from(":root {\n");
+    for (index, color) in palette.iter().enumerate() {
+        css.push_str(&format!("    --user-color-{}: {};\n", index, color));
+    }
+    css.push_str("}\n");
+    for index in 0..palette.len() {
+        css.push_str(&format!(
+            "\n.msg-username--c{} {{\n    color: var(--user-color-{});\n}}\n",
+            index, index
+        ));
+    }
+    css
+}
@@ -0,0 +1,825 @@
+//! Settings read from `config.toml`, with environment variable overrides.
+//!
+//! Every setting has a default, so the file and each of its sections are optional.
+//! See `sneed_env` for the variables that override it. The file is read again on
+//! SIGHUP or `POST /api/v1/config/reload`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::colors;
+use crate::connector;
+use crate::exchange;
+use crate::platform::Badge;
+use crate::sneed_env;
+use crate::web::{assets, SlowConsumerPolicy};
+
+pub const DEFAULT_PATH: &str = "config.toml";
+
+/// The config in use and where it was read from, for reloading.
+struct Current {
+    path: PathBuf,
+    required: bool,
+    config: Arc<Config>,
+}
+
+static CURRENT: RwLock<Option<Current>> = RwLock::new(None);
+
+/// Makes `config`, read from `path`, the one returned by `get`.
+pub fn init(path: &Path, required: bool, config: Config) {
+    *CURRENT.write().expect("Config lock poisoned.") = Some(Current {
+        path: path.to_owned(),
+        required,
+        config: Arc::new(config),
+    });
+}
+
+/// Puts a config for unit tests in use: the defaults, with a 1 MiB image cache
+/// and records kept in a temporary directory.
+#[cfg(test)]
+pub fn init_for_tests() {
+    let mut config = Config::default();
+    config.images.cache_size = 1;
+    config.paths.data = std::env::temp_dir().join(format!("stream-nexus-{}", std::process::id()));
+    std::fs::create_dir_all(&config.paths.data).expect("Failed to create test data directory.");
+    init(Path::new("config.toml"), false, config);
+}
+
+/// The config in use. Hold on to it only as long as one request or event needs it,
+/// so a reload is picked up by the next one.
+pub fn get() -> Arc<Config> {
+    CURRENT
+        .read()
+        .expect("Config lock poisoned.")
+        .as_ref()
+        .map(|current| current.config.clone())
+        .expect("Config has not been loaded.")
+}
+
+/// A config that replaced the one in use.
+pub struct Reloaded {
+    pub config: Arc<Config>,
+    pub warnings: Vec<Problem>,
+    /// Settings that changed but only apply after a restart.
+    pub needs_restart: Vec<&'static str>,
+}
+
+/// Keeps the `old` value of a setting that only applies after a restart, noting it if
+/// the file changed it.
+fn keep_until_restart<T: PartialEq + Clone>(
+    name: &'static str,
+    new: &mut T,
+    old: &T,
+    needs_restart: &mut Vec<&'static str>,
+) {
+    if new != old {
+        *new = old.clone();
+        needs_restart.push(name);
+    }
+}
+
+/// Reads the config file again and, if it has no errors, puts it in use.
+/// The config in use is kept if the file can't be read or has errors.
+///
+/// Only the chat server calls this, as it handles `Reconfigure`, so reloads happen
+/// one at a time and in the order the server applies them.
+pub fn reload() -> anyhow::Result<Reloaded> {
+    let (path, required, old) = {
+        let current = CURRENT.read().expect("Config lock poisoned.");
+        let current = current.as_ref().expect("Config has not been loaded.");
+        (
+            current.path.to_owned(),
+            current.required,
+            current.config.clone(),
+        )
+    };
+
+    let mut config = Config::load(&path, required)?;
+    let (errors, warnings): (Vec<Problem>, Vec<Problem>) = config
+        .check()
+        .into_iter()
+        .partition(|problem| problem.severity == Severity::Error);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|problem| problem.to_string()).collect();
+        anyhow::bail!(
+            "{} has errors, keeping the config in use:\n{}",
+            path.display(),
+            errors.join("\n")
+        );
+    }
+
+    // Read once at startup, or by each client as it connects, so the values in use
+    // stay until a restart rather than applying to some clients and not others.
+    let mut needs_restart = Vec::new();
+    let restart = &mut needs_restart;
+    let server = &mut config.server;
+    keep_until_restart("server.ip", &mut server.ip, &old.server.ip, restart);
+    keep_until_restart("server.port", &mut server.port, &old.server.port, restart);
+    keep_until_restart(
+        "server.heartbeat_interval",
+        &mut server.heartbeat_interval,
+        &old.server.heartbeat_interval,
+        restart,
+    );
+    keep_until_restart(
+        "server.client_timeout",
+        &mut server.client_timeout,
+        &old.server.client_timeout,
+        restart,
+    );
+    keep_until_restart(
+        "server.chat_mailbox",
+        &mut server.chat_mailbox,
+        &old.server.chat_mailbox,
+        restart,
+    );
+    keep_until_restart(
+        "server.client_mailbox",
+        &mut server.client_mailbox,
+        &old.server.client_mailbox,
+        restart,
+    );
+    // Files of this session stay together.
+    keep_until_restart(
+        "paths.data",
+        &mut config.paths.data,
+        &old.paths.data,
+        restart,
+    );
+    keep_until_restart(
+        "exchange.url",
+        &mut config.exchange.url,
+        &old.exchange.url,
+        restart,
+    );
+    keep_until_restart(
+        "connectors",
+        &mut config.connectors,
+        &old.connectors,
+        restart,
+    );
+
+    let config = Arc::new(config);
+    if let Some(current) = CURRENT.write().expect("Config lock poisoned.").as_mut() {
+        current.config = config.clone();
+    }
+    Ok(Reloaded {
+        config,
+        warnings,
+        needs_restart,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub paths: PathsConfig,
+    pub exchange: ExchangeConfig,
+    pub branding: BrandingConfig,
+    pub ingest: IngestConfig,
+    pub webhooks: WebhooksConfig,
+    pub sources: SourcesConfig,
+    pub markers: MarkersConfig,
+    pub images: ImagesConfig,
+    pub colors: ColorsConfig,
+    /// Server-side ingesters, one per platform channel.
+    pub connectors: Vec<ConnectorConfig>,
+    /// Overlay profiles by name, added to or replacing the built-in ones.
+    pub overlays: BTreeMap<String, OverlayProfile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub ip: String,
+    pub port: u16,
+    /// Seconds between pings to websocket clients.
+    pub heartbeat_interval: u64,
+    /// Seconds without a pong before a websocket client is dropped.
+    pub client_timeout: u64,
+    /// Messages the chat server queues before senders have to wait.
+    pub chat_mailbox: usize,
+    /// Events each client queues before it counts as falling behind.
+    pub client_mailbox: usize,
+    /// Broadcast events kept for clients resuming after a reconnect.
+    pub history: usize,
+    /// Most recent chat messages sent to a client catching up.
+    pub recent_messages: usize,
+    /// Superchats saved less than this many seconds ago are loaded again on restart.
+    pub superchat_reload_window: u64,
+    /// What to do with a client whose mailbox is full: `drop` or `disconnect`.
+    pub slow_consumer: SlowConsumerPolicy,
+    /// Events a client may miss in a row before it is disconnected.
+    pub slow_consumer_max_lag: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ip: "127.0.0.1".to_owned(),
+            port: 1350,
+            heartbeat_interval: 1,
+            client_timeout: 5,
+            chat_mailbox: 256,
+            client_mailbox: 64,
+            history: 1000,
+            recent_messages: 100,
+            superchat_reload_window: 900,
+            slow_consumer: SlowConsumerPolicy::Disconnect,
+            slow_consumer_max_lag: 64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Files served in place of the built-in scripts, stylesheets and logos,
+    /// e.g. `custom.css` or `logo/twitch.svg`. Ignored if it does not exist.
+    pub overrides: PathBuf,
+    /// Where superchats, donations, markers and other records are kept.
+    pub data: PathBuf,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            overrides: PathBuf::from("overrides"),
+            data: PathBuf::from("."),
+        }
+    }
+}
+
+impl PathsConfig {
+    pub fn data_file(&self, name: &str) -> PathBuf {
+        self.data.join(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExchangeConfig {
+    /// Daily reference rates in the ECB's XML format.
+    pub url: String,
+}
+
+impl Default for ExchangeConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml".to_owned(),
+        }
+    }
+}
+
+/// Text shown on the overlay.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrandingConfig {
+    /// Page titles.
+    pub title: String,
+    /// Show name in the attribution line.
+    pub name: String,
+    pub url: String,
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        Self {
+            title: "S.N.E.E.D.".to_owned(),
+            name: "Mad at the Internet".to_owned(),
+            url: "mati.live".to_owned(),
+        }
+    }
+}
+
+/// How an overlay arranges the page.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayLayout {
+    /// Chat down the right of a landscape scene, with viewers and attribution.
+    Horizontal,
+    /// Chat across the bottom of a portrait scene, for mobile streams.
+    Vertical,
+    /// Chat alone, filling the page.
+    Chat,
+    /// Superchats alone, filling the page.
+    Superchats,
+    /// A single line of chat scrolling along the bottom.
+    Ticker,
+}
+
+impl OverlayLayout {
+    pub const ALL: [OverlayLayout; 5] = [
+        OverlayLayout::Horizontal,
+        OverlayLayout::Vertical,
+        OverlayLayout::Chat,
+        OverlayLayout::Superchats,
+        OverlayLayout::Ticker,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayLayout::Horizontal => "horizontal",
+            OverlayLayout::Vertical => "vertical",
+            OverlayLayout::Chat => "chat",
+            OverlayLayout::Superchats => "superchats",
+            OverlayLayout::Ticker => "ticker",
+        }
+    }
+}
+
+impl fmt::Display for OverlayLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An overlay served at `/overlay/{name}`. Every layout is also a built-in profile
+/// of the same name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayProfile {
+    pub layout: OverlayLayout,
+    /// Replace the page title, show name and URL from `[branding]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Extra stylesheet, `themes/{theme}.css` in the override directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Only chat from these platforms. Empty shows every platform.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Only chat from authors with any of these badges: owner, staff, mod, verified, sub.
+    #[serde(default)]
+    pub badges: Vec<String>,
+    /// Only chat containing any of these words.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Chat containing any of these words is highlighted.
+    #[serde(default)]
+    pub highlight: Vec<String>,
+    /// Chat messages kept on the page before the oldest are removed.
+    #[serde(default = "max_messages")]
+    pub max_messages: usize,
+}
+
+fn max_messages() -> usize {
+    1000
+}
+
+impl OverlayProfile {
+    pub fn new(layout: OverlayLayout) -> Self {
+        Self {
+            layout,
+            title: None,
+            name: None,
+            url: None,
+            theme: None,
+            platforms: Vec::new(),
+            badges: Vec::new(),
+            keywords: Vec::new(),
+            highlight: Vec::new(),
+            max_messages: match layout {
+                OverlayLayout::Superchats => 50,
+                OverlayLayout::Ticker => 20,
+                _ => max_messages(),
+            },
+        }
+    }
+
+    /// `branding` with this profile's replacements.
+    pub fn branding(&self, branding: &BrandingConfig) -> BrandingConfig {
+        BrandingConfig {
+            title: self.title.to_owned().unwrap_or(branding.title.to_owned()),
+            name: self.name.to_owned().unwrap_or(branding.name.to_owned()),
+            url: self.url.to_owned().unwrap_or(branding.url.to_owned()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestConfig {
+    /// Bearer token required by `POST /api/v1/ingest`. Empty allows anyone.
+    pub token: String,
+}
+
+/// Donation webhook secrets. An empty secret disables the provider.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    pub kofi_verification_token: String,
+    pub patreon_secret: String,
+    /// Currency of the Patreon campaign.
+    pub patreon_currency: String,
+    pub streamelements_secret: String,
+    pub streamlabs_secret: String,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            kofi_verification_token: String::new(),
+            patreon_secret: String::new(),
+            patreon_currency: "USD".to_owned(),
+            streamelements_secret: String::new(),
+            streamlabs_secret: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    /// Seconds without chat from a source, while live, before the dashboard warns about it.
+    pub quiet_after: u64,
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self { quiet_after: 120 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkersConfig {
+    /// Mark chat moving this many times faster than usual. Zero turns spike markers off.
+    pub spike_factor: f64,
+    /// Messages a second needed before a spike is marked, however quiet chat usually is.
+    pub spike_min_rate: f64,
+    /// Uses of one emote within ten seconds that count as a flood. Zero turns these off.
+    pub emote_flood: usize,
+    /// Donations of at least this many USD are marked. Zero turns these off.
+    pub big_donation: f64,
+}
+
+impl Default for MarkersConfig {
+    fn default() -> Self {
+        Self {
+            spike_factor: 3.0,
+            spike_min_rate: 1.0,
+            emote_flood: 20,
+            big_donation: 50.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorsConfig {
+    /// Username colors, as `#rrggbb`. Each chatter gets one picked by platform and username,
+    /// unless they have a badge and so a role color.
+    pub palette: Vec<String>,
+    /// Keep the color a chatter chose on their platform, like Twitch's, over the palette
+    /// and role colors.
+    pub platform_colors: bool,
+}
+
+impl Default for ColorsConfig {
+    fn default() -> Self {
+        Self {
+            // Twitch's defaults for chatters who haven't picked one.
+            palette: [
+                "#FF0000", "#0000FF", "#008000", "#B22222", "#FF7F50", "#9ACD32", "#FF4500",
+                "#2E8B57", "#DAA520", "#D2691E", "#5F9EA0", "#1E90FF", "#FF69B4", "#8A2BE2",
+                "#00FF7F",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+            platform_colors: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Serve avatars and emojis from `/img/` instead of linking to platform CDNs.
+    pub proxy: bool,
+    /// Largest image fetched, in KiB.
+    pub max_size: u64,
+    /// Disk space the image cache may use, in MiB. The least recently used go first.
+    pub cache_size: u64,
+    /// Seconds to wait for a platform CDN.
+    pub timeout: u64,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            proxy: true,
+            max_size: 2048,
+            cache_size: 256,
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectorConfig {
+    /// Which connector to run, e.g. `twitch`.
+    pub kind: String,
+    pub channel: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// Overrides the platform's endpoint, e.g. to point at a mock server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The server should not start.
+    Error,
+    /// Works, but probably not as intended.
+    Warning,
+}
+
+/// Something wrong with a config that parsed.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file at `path` and applies environment overrides.
+    ///
+    /// A missing file gives the defaults, unless `required` because it was asked for by name.
+    pub fn load(path: &Path, required: bool) -> anyhow::Result<Self> {
+        let config: Self = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|err| anyhow::anyhow!("{} is invalid: {}", path.display(), err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !required => Self::default(),
+            Err(err) => anyhow::bail!("Failed to read {}: {}", path.display(), err),
+        };
+
+        let mut table = toml::Table::try_from(&config)?;
+        let overridden = sneed_env::apply_overrides(&mut table)?;
+        if overridden.is_empty() {
+            return Ok(config);
+        }
+
+        log::info!(
+            "Settings overridden by the environment: {}",
+            overridden.join(", ")
+        );
+        toml::Value::Table(table).try_into().map_err(|err| {
+            anyhow::anyhow!(
+                "Environment overrides ({}) are invalid: {}",
+                overridden.join(", "),
+                err
+            )
+        })
+    }
+
+    /// Everything wrong with this config, errors first.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut error = |message: String| {
+            problems.push(Problem {
+                severity: Severity::Error,
+                message,
+            })
+        };
+
+        let server = &self.server;
+        if server.ip.parse::<IpAddr>().is_err() {
+            error(format!("server.ip {:?} is not an IP address.", server.ip));
+        }
+        if server.port == 0 {
+            error("server.port must not be 0.".to_owned());
+        }
+        if server.heartbeat_interval == 0 {
+            error("server.heartbeat_interval must be at least 1 second.".to_owned());
+        }
+        if server.client_timeout <= server.heartbeat_interval {
+            error(format!(
+                "server.client_timeout ({}s) must be longer than server.heartbeat_interval ({}s).",
+                server.client_timeout, server.heartbeat_interval
+            ));
+        }
+        for (key, value) in [
+            ("server.chat_mailbox", server.chat_mailbox),
+            ("server.client_mailbox", server.client_mailbox),
+            ("server.history", server.history),
+            ("server.slow_consumer_max_lag", server.slow_consumer_max_lag),
+        ] {
+            if value == 0 {
+                error(format!("{} must be at least 1.", key));
+            }
+        }
+
+        if self.paths.overrides.exists() && !self.paths.overrides.is_dir() {
+            error(format!(
+                "paths.overrides {:?} is not a directory.",
+                self.paths.overrides
+            ));
+        }
+        if !self.paths.data.is_dir() {
+            error(format!(
+                "paths.data {:?} is not a directory.",
+                self.paths.data
+            ));
+        }
+
+        if !self.exchange.url.starts_with("http://") && !self.exchange.url.starts_with("https://") {
+            error(format!(
+                "exchange.url {:?} is not an HTTP URL.",
+                self.exchange.url
+            ));
+        }
+
+        let currency = &self.webhooks.patreon_currency;
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+            error(format!(
+                "webhooks.patreon_currency {:?} is not a currency code.",
+                currency
+            ));
+        }
+
+        if self.colors.palette.is_empty() {
+            error("colors.palette needs at least one color.".to_owned());
+        }
+        for color in &self.colors.palette {
+            if !colors::is_hex(color) {
+                error(format!(
+                    "colors.palette has {:?}, which isn't a #rrggbb color.",
+                    color
+                ));
+            }
+        }
+
+        for (key, value) in [
+            ("images.max_size", self.images.max_size),
+            ("images.timeout", self.images.timeout),
+        ] {
+            if value == 0 {
+                error(format!("{} must be at least 1.", key));
+            }
+        }
+        if self.images.cache_size * 1024 < self.images.max_size {
+            error(format!(
+                "images.cache_size {} MiB can't hold an image of images.max_size {} KiB.",
+                self.images.cache_size, self.images.max_size
+            ));
+        }
+
+        for (key, value) in [
+            ("markers.spike_factor", self.markers.spike_factor),
+            ("markers.spike_min_rate", self.markers.spike_min_rate),
+            ("markers.big_donation", self.markers.big_donation),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                error(format!("{} must not be negative.", key));
+            }
+        }
+
+        let kinds = connector::kinds();
+        for (i, connector) in self.connectors.iter().enumerate() {
+            if !kinds.contains(&connector.kind.as_str()) {
+                error(format!(
+                    "connectors[{}] has unknown kind {:?}. Known kinds: {}.",
+                    i,
+                    connector.kind,
+                    kinds.join(", ")
+                ));
+            }
+            if connector.channel.trim().is_empty() {
+                error(format!("connectors[{}] has no channel.", i));
+            }
+        }
+
+        for (name, profile) in &self.overlays {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                error(format!(
+                    "overlays.{:?} must be letters, digits, - and _ to be used in a URL.",
+                    name
+                ));
+            }
+            if profile.max_messages == 0 {
+                error(format!(
+                    "overlays.{}.max_messages must be at least 1.",
+                    name
+                ));
+            }
+            for badge in &profile.badges {
+                if Badge::from_name(badge).is_none() {
+                    error(format!(
+                        "overlays.{}.badges has unknown badge {:?}. Known badges: {}.",
+                        name,
+                        badge,
+                        Badge::ALL.map(|b| b.name()).join(", ")
+                    ));
+                }
+            }
+        }
+
+        let mut warning = |message: String| {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                message,
+            })
+        };
+
+        let loopback = server.ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if !loopback && self.ingest.token.is_empty() {
+            warning(format!(
+                "ingest.token is empty, so anyone who can reach {} can post chat.",
+                server.ip
+            ));
+        }
+        if self.sources.quiet_after < 10 {
+            warning(format!(
+                "sources.quiet_after is {}s, sources are only checked every 10s.",
+                self.sources.quiet_after
+            ));
+        }
+        if !self.paths.data_file(exchange::BACKUP).is_file() {
+            warning(format!(
+                "No exchange rate backup in {:?}. The server will not start if {} is unreachable.",
+                self.paths.data, self.exchange.url
+            ));
+        }
+        for (name, profile) in &self.overlays {
+            if let Some(theme) = &profile.theme {
+                if !assets::exists(&self.paths.overrides, &format!("themes/{}.css", theme)) {
+                    warning(format!(
+                        "overlays.{}.theme {:?} has no themes/{}.css, built in or in {:?}.",
+                        name, theme, theme, self.paths.overrides
+                    ));
+                }
+            }
+        }
+        for (i, connector) in self.connectors.iter().enumerate() {
+            let duplicate = self.connectors[..i].iter().any(|other| {
+                other.enabled
+                    && other.kind == connector.kind
+                    && other.channel.eq_ignore_ascii_case(&connector.channel)
+            });
+            if connector.enabled && duplicate {
+                warning(format!(
+                    "connectors[{}] repeats {} channel {:?}, chat will be read twice.",
+                    i, connector.kind, connector.channel
+                ));
+            }
+        }
+
+        problems.sort_by_key(|problem| problem.severity != Severity::Error);
+        problems
+    }
+
+    /// The overlay profile called `name`, from the config or built in.
+    pub fn overlay(&self, name: &str) -> Option<OverlayProfile> {
+        self.overlays.get(name).cloned().or_else(|| {
+            OverlayLayout::ALL
+                .into_iter()
+                .find(|layout| layout.name() == name)
+                .map(OverlayProfile::new)
+        })
+    }
+
+    /// Every overlay profile by name, built-in ones included.
+    pub fn overlays(&self) -> BTreeMap<String, OverlayProfile> {
+        let mut overlays: BTreeMap<String, OverlayProfile> = OverlayLayout::ALL
+            .into_iter()
+            .map(|layout| (layout.name().to_owned(), OverlayProfile::new(layout)))
+            .collect();
+        overlays.extend(self.overlays.clone());
+        overlays
+    }
+
+    /// Address the web server listens on.
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        let ip: IpAddr = self.server.ip.parse().ok()?;
+        Some(SocketAddr::new(ip, self.server.port))
+    }
+}
@@ -0,0 +1,389 @@
+//! Server-side platform ingesters and the supervisor that keeps them running.
+//!
+//! A connector is an actor that reads one channel's chat and sends `LivestreamUpdate`s
+//! to `ChatServer`. It reports its health through a `Reporter` and stops itself when
+//! its connection fails; the supervisor restarts it with backoff.
+
+use actix::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::ConnectorConfig;
+use crate::twitch::TwitchChat;
+use crate::web::ChatServer;
+
+/// Delay before the first restart. Doubles with each failure in a row.
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// A connector that stayed connected this long before failing starts its backoff over.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Ids for connectors and browser feeders, unique for the life of the process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub trait Connector: Actor<Context = Context<Self>> + Sized {
+    /// Value of `kind` in the config file.
+    const KIND: &'static str;
+    /// Platform name used on chat messages.
+    const PLATFORM: &'static str;
+
+    fn new(
+        config: &ConnectorConfig,
+        server: Addr<ChatServer>,
+        reporter: Reporter,
+    ) -> anyhow::Result<Self>;
+}
+
+type SpawnFn = fn(&ConnectorConfig, Addr<ChatServer>, Reporter) -> anyhow::Result<()>;
+
+fn spawn<C: Connector>(
+    config: &ConnectorConfig,
+    server: Addr<ChatServer>,
+    reporter: Reporter,
+) -> anyhow::Result<()> {
+    C::new(config, server, reporter)?.start();
+    Ok(())
+}
+
+/// A connector kind that can be named in the config file.
+#[derive(Clone, Copy)]
+struct Kind {
+    kind: &'static str,
+    platform: &'static str,
+    spawn: SpawnFn,
+}
+
+impl Kind {
+    fn of<C: Connector>() -> Self {
+        Self {
+            kind: C::KIND,
+            platform: C::PLATFORM,
+            spawn: spawn::<C>,
+        }
+    }
+
+    fn all() -> [Self; 1] {
+        [Self::of::<TwitchChat>()]
+    }
+
+    fn find(kind: &str) -> Option<Self> {
+        Self::all().into_iter().find(|k| k.kind == kind)
+    }
+}
+
+/// Connector kinds that can be named in the config file.
+pub fn kinds() -> Vec<&'static str> {
+    Kind::all().iter().map(|k| k.kind).collect()
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectorState {
+    Connecting,
+    Connected,
+    /// Waiting to be restarted after a failure.
+    Backoff,
+    /// Stopped for good, e.g. it could not be configured.
+    Failed,
+}
+
+/// What a connector tells the supervisor.
+#[derive(Debug, Clone)]
+pub enum ConnectorEvent {
+    Connecting,
+    Connected,
+    /// Chat messages were received.
+    Messages(usize),
+    Error(String),
+    Stopped,
+}
+
+pub struct Report {
+    pub id: usize,
+    pub kind: &'static str,
+    pub platform: String,
+    pub channel: String,
+    pub event: ConnectorEvent,
+}
+
+impl Message for Report {
+    type Result = ();
+}
+
+/// Handle a connector uses to report its health.
+#[derive(Clone)]
+pub struct Reporter {
+    id: usize,
+    kind: &'static str,
+    platform: String,
+    channel: String,
+    supervisor: Recipient<Report>,
+}
+
+impl Reporter {
+    pub fn new(
+        kind: &'static str,
+        platform: &str,
+        channel: &str,
+        supervisor: Recipient<Report>,
+    ) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            kind,
+            platform: platform.to_owned(),
+            channel: channel.to_owned(),
+            supervisor,
+        }
+    }
+
+    fn report(&self, event: ConnectorEvent) {
+        self.supervisor.do_send(Report {
+            id: self.id,
+            kind: self.kind,
+            platform: self.platform.to_owned(),
+            channel: self.channel.to_owned(),
+            event,
+        });
+    }
+
+    pub fn connecting(&self) {
+        self.report(ConnectorEvent::Connecting);
+    }
+
+    pub fn connected(&self) {
+        self.report(ConnectorEvent::Connected);
+    }
+
+    pub fn messages(&self, count: usize) {
+        self.report(ConnectorEvent::Messages(count));
+    }
+
+    pub fn error(&self, err: impl std::fmt::Display) {
+        self.report(ConnectorEvent::Error(err.to_string()));
+    }
+
+    /// Call from `Actor::stopped`.
+    pub fn stopped(&self) {
+        self.report(ConnectorEvent::Stopped);
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectorHealth {
+    pub id: usize,
+    /// A connector kind from the config file, or `browser` for websocket feeders.
+    pub kind: &'static str,
+    pub platform: String,
+    pub channel: String,
+    pub state: ConnectorState,
+    /// Unix timestamp in milliseconds of the last state change.
+    pub since: i64,
+    /// Unix timestamp in milliseconds.
+    pub last_message_at: Option<i64>,
+    pub messages: u64,
+    pub last_error: Option<String>,
+    pub restarts: u32,
+    /// Unix timestamp in milliseconds of the next restart, while in backoff.
+    pub retry_at: Option<i64>,
+}
+
+impl ConnectorHealth {
+    fn new(id: usize, kind: &'static str, platform: &str, channel: &str) -> Self {
+        Self {
+            id,
+            kind,
+            platform: platform.to_owned(),
+            channel: channel.to_owned(),
+            state: ConnectorState::Connecting,
+            since: chrono::Utc::now().timestamp_millis(),
+            last_message_at: None,
+            messages: 0,
+            last_error: None,
+            restarts: 0,
+            retry_at: None,
+        }
+    }
+
+    fn set_state(&mut self, state: ConnectorState) {
+        if self.state != state {
+            self.state = state;
+            self.since = chrono::Utc::now().timestamp_millis();
+        }
+    }
+}
+
+/// A connector from the config file.
+struct Managed {
+    config: ConnectorConfig,
+    kind: Kind,
+    /// Failures in a row, for backoff.
+    failures: u32,
+    connected_at: Option<Instant>,
+}
+
+/// Health of every connector and browser feeder.
+pub struct ListConnectors;
+
+impl Message for ListConnectors {
+    type Result = Vec<ConnectorHealth>;
+}
+
+pub struct ConnectorSupervisor {
+    server: Addr<ChatServer>,
+    /// Configured connectors by id. Restarts keep the id.
+    managed: HashMap<usize, Managed>,
+    health: BTreeMap<usize, ConnectorHealth>,
+}
+
+impl ConnectorSupervisor {
+    pub fn new(server: Addr<ChatServer>) -> Self {
+        Self {
+            server,
+            managed: HashMap::new(),
+            health: BTreeMap::new(),
+        }
+    }
+
+    /// Starts every enabled connector in `configs`.
+    pub fn start_connectors(&mut self, configs: &[ConnectorConfig], ctx: &mut Context<Self>) {
+        for config in configs.iter().filter(|config| config.enabled) {
+            let Some(kind) = Kind::find(&config.kind) else {
+                log::error!(
+                    "Unknown connector kind {:?} for channel {}.",
+                    config.kind,
+                    config.channel
+                );
+                continue;
+            };
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            self.managed.insert(
+                id,
+                Managed {
+                    config: config.to_owned(),
+                    kind,
+                    failures: 0,
+                    connected_at: None,
+                },
+            );
+            self.spawn(id, ctx);
+        }
+    }
+
+    fn spawn(&mut self, id: usize, ctx: &mut Context<Self>) {
+        let Some(managed) = self.managed.get(&id) else {
+            return;
+        };
+        let reporter = Reporter {
+            id,
+            kind: managed.kind.kind,
+            platform: managed.kind.platform.to_owned(),
+            channel: managed.config.channel.to_owned(),
+            supervisor: ctx.address().recipient(),
+        };
+        let health = self.health.entry(id).or_insert_with(|| {
+            ConnectorHealth::new(id, reporter.kind, &reporter.platform, &reporter.channel)
+        });
+        health.set_state(ConnectorState::Connecting);
+        health.retry_at = None;
+
+        if let Err(err) = (managed.kind.spawn)(&managed.config, self.server.clone(), reporter) {
+            log::error!(
+                "Connector {} for {} failed to start: {}",
+                managed.config.kind,
+                managed.config.channel,
+                err
+            );
+            health.set_state(ConnectorState::Failed);
+            health.last_error = Some(err.to_string());
+            self.managed.remove(&id);
+        }
+    }
+
+    /// Schedules a restart, waiting longer after each failure in a row.
+    fn restart_later(&mut self, id: usize, ctx: &mut Context<Self>) {
+        let Some(managed) = self.managed.get_mut(&id) else {
+            return;
+        };
+        if managed
+            .connected_at
+            .take()
+            .is_some_and(|at| at.elapsed() >= STABLE_AFTER)
+        {
+            managed.failures = 0;
+        }
+        let delay = BACKOFF_MIN
+            .saturating_mul(2u32.saturating_pow(managed.failures))
+            .min(BACKOFF_MAX);
+        managed.failures += 1;
+
+        log::info!(
+            "Restarting {} connector for {} in {:?}.",
+            managed.config.kind,
+            managed.config.channel,
+            delay
+        );
+        if let Some(health) = self.health.get_mut(&id) {
+            health.set_state(ConnectorState::Backoff);
+            health.restarts += 1;
+            health.retry_at =
+                Some(chrono::Utc::now().timestamp_millis() + delay.as_millis() as i64);
+        }
+        ctx.run_later(delay, move |act, ctx| act.spawn(id, ctx));
+    }
+}
+
+impl Actor for ConnectorSupervisor {
+    type Context = Context<Self>;
+}
+
+impl Handler<Report> for ConnectorSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Report, ctx: &mut Self::Context) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let health = self
+            .health
+            .entry(msg.id)
+            .or_insert_with(|| ConnectorHealth::new(msg.id, msg.kind, &msg.platform, &msg.channel));
+
+        match msg.event {
+            ConnectorEvent::Connecting => {
+                health.set_state(ConnectorState::Connecting);
+                health.retry_at = None;
+            }
+            ConnectorEvent::Connected => {
+                health.set_state(ConnectorState::Connected);
+                health.last_error = None;
+                if let Some(managed) = self.managed.get_mut(&msg.id) {
+                    managed.connected_at = Some(Instant::now());
+                }
+            }
+            ConnectorEvent::Messages(count) => {
+                health.messages += count as u64;
+                health.last_message_at = Some(now);
+            }
+            ConnectorEvent::Error(err) => {
+                log::warn!("{} connector for {}: {}", msg.kind, msg.channel, err);
+                health.last_error = Some(err);
+            }
+            ConnectorEvent::Stopped => {
+                if self.managed.contains_key(&msg.id) {
+                    self.restart_later(msg.id, ctx);
+                } else {
+                    // Browser feeders come back on their own, with a new id.
+                    self.health.remove(&msg.id);
+                }
+            }
+        }
+    }
+}
+
+impl Handler<ListConnectors> for ConnectorSupervisor {
+    type Result = MessageResult<ListConnectors>;
+
+    fn handle(&mut self, _: ListConnectors, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.health.values().cloned().collect())
+    }
+}
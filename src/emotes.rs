@@ -0,0 +1,279 @@
+//! Emote packs, matched in chat from every platform.
+//!
+//! Each pack is a directory in `emotes/` in the data directory. Images in it are
+//! emotes named after the file, so `KEKW.webp` is `KEKW`. A `pack.toml` beside them
+//! can turn the pack off, limit it to some platforms, and add emotes hosted elsewhere,
+//! such as 7TV, BTTV or FFZ sets:
+//!
+//! ```toml
+//! enabled = true
+//! platforms = ["twitch", "kick"]
+//!
+//! [emotes]
+//! catJAM = "https://cdn.7tv.app/emote/60ae7316f7c927fad14e6ca2/2x.webp"
+//! ```
+//!
+//! Emotes only replace whole words, and codes are case sensitive. Packs are checked in
+//! name order, so the first pack with a code wins.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::images;
+use crate::platform;
+
+const EMOTES_DIR: &str = "emotes";
+const MANIFEST: &str = "pack.toml";
+/// Longest emote code.
+pub const MAX_CODE_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Manifest {
+    enabled: bool,
+    /// Platforms the pack applies to. Empty for all of them.
+    platforms: Vec<String>,
+    /// Emotes hosted elsewhere, by code.
+    emotes: BTreeMap<String, String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            platforms: Vec::new(),
+            emotes: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EmotePack {
+    pub name: String,
+    pub enabled: bool,
+    pub platforms: Vec<String>,
+    /// Image URL by code. Images in the pack's directory are under `/emote/`.
+    pub emotes: BTreeMap<String, String>,
+}
+
+/// Pack settings as changed from the dashboard.
+#[derive(Deserialize, Debug)]
+pub struct PackSettings {
+    pub enabled: bool,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+}
+
+fn dir() -> PathBuf {
+    config::get().paths.data_file(EMOTES_DIR)
+}
+
+/// Whether `name` can name a pack, and so be part of a path and URL.
+pub fn is_pack_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_CODE_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether `code` can be an emote. Codes are single words without anything that
+/// would need escaping in HTML or a file name.
+pub fn is_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_CODE_LEN
+        && code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-!?()".contains(c))
+}
+
+/// Path of a file in a pack, if both names are safe to use.
+pub fn file_path(pack: &str, file: &str) -> Option<PathBuf> {
+    let (code, ext) = file.rsplit_once('.')?;
+    (is_pack_name(pack) && is_code(code) && images::content_type(ext).is_some())
+        .then(|| dir().join(pack).join(file))
+}
+
+fn read_manifest(pack: &str) -> Result<Manifest> {
+    let path = dir().join(pack).join(MANIFEST);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).with_context(|| format!("Invalid {:?}", path)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+fn write_manifest(pack: &str, manifest: &Manifest) -> Result<()> {
+    let path = dir().join(pack).join(MANIFEST);
+    std::fs::write(&path, toml::to_string(manifest)?)
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn load_pack(name: &str) -> Result<EmotePack> {
+    let manifest = read_manifest(name)?;
+    let mut emotes = BTreeMap::new();
+    for (code, url) in manifest.emotes {
+        if is_code(&code) {
+            emotes.insert(code, url);
+        } else {
+            log::warn!(
+                "Skipping emote {:?} in pack {:?}: not a valid code.",
+                code,
+                name
+            );
+        }
+    }
+    // Images in the directory win over URLs for the same code.
+    for entry in std::fs::read_dir(dir().join(name))?.flatten() {
+        let file = entry.file_name().to_string_lossy().into_owned();
+        if file == MANIFEST {
+            continue;
+        }
+        match (file_path(name, &file), file.rsplit_once('.')) {
+            (Some(_), Some((code, _))) => {
+                emotes.insert(code.to_owned(), format!("/emote/{}/{}", name, file));
+            }
+            _ => log::warn!("Skipping {:?} in emote pack {:?}.", file, name),
+        }
+    }
+    Ok(EmotePack {
+        name: name.to_owned(),
+        enabled: manifest.enabled,
+        platforms: manifest
+            .platforms
+            .iter()
+            .map(|platform| platform.to_lowercase())
+            .collect(),
+        emotes,
+    })
+}
+
+/// Every pack, in name order. Packs that fail to load are logged and left out.
+pub fn load() -> Vec<EmotePack> {
+    let entries = match std::fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| is_pack_name(name))
+        .collect();
+    names.sort();
+
+    let mut packs = Vec::with_capacity(names.len());
+    for name in names {
+        match load_pack(&name) {
+            Ok(pack) => packs.push(pack),
+            Err(err) => log::error!("Failed to load emote pack {:?}: {:#}", name, err),
+        }
+    }
+    packs
+}
+
+/// Emotes from `packs` that appear as words in `text`, as (find, replace, name)
+/// triples like a message's own emojis.
+pub fn matches(packs: &[EmotePack], platform: &str, text: &str) -> Vec<(String, String, String)> {
+    let name = platform.to_lowercase();
+    let known = platform::find(platform);
+    let applies = |pack: &&EmotePack| {
+        pack.enabled
+            && (pack.platforms.is_empty()
+                || pack.platforms.iter().any(|p| {
+                    *p == name
+                        || known.is_some_and(|known| {
+                            platform::find(p).is_some_and(|other| other.id == known.id)
+                        })
+                }))
+    };
+
+    let mut found: Vec<(String, String, String)> = Vec::new();
+    for word in text.split_whitespace() {
+        if found.iter().any(|(code, _, _)| code == word) {
+            continue;
+        }
+        if let Some(url) = packs
+            .iter()
+            .filter(applies)
+            .find_map(|pack| pack.emotes.get(word))
+        {
+            found.push((word.to_owned(), url.to_owned(), word.to_owned()));
+        }
+    }
+    found
+}
+
+/// Sets whether a pack is used and where, creating it if it's new.
+pub fn save_pack(name: &str, settings: PackSettings) -> Result<EmotePack> {
+    std::fs::create_dir_all(dir().join(name))?;
+    let mut manifest = read_manifest(name)?;
+    manifest.enabled = settings.enabled;
+    manifest.platforms = settings.platforms;
+    write_manifest(name, &manifest)?;
+    load_pack(name)
+}
+
+/// Removes a pack and its images.
+pub fn delete_pack(name: &str) -> Result<bool> {
+    let path = dir().join(name);
+    if !path.is_dir() {
+        return Ok(false);
+    }
+    std::fs::remove_dir_all(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+    Ok(true)
+}
+
+/// Adds an emote hosted elsewhere to a pack, creating the pack if needed.
+pub fn add_url(pack: &str, code: &str, url: &str) -> Result<EmotePack> {
+    std::fs::create_dir_all(dir().join(pack))?;
+    remove_images(pack, code)?;
+    let mut manifest = read_manifest(pack)?;
+    manifest.emotes.insert(code.to_owned(), url.to_owned());
+    write_manifest(pack, &manifest)?;
+    load_pack(pack)
+}
+
+/// Adds an uploaded image to a pack, creating the pack if needed. `ext` is the
+/// image type it was checked to be.
+pub fn add_image(pack: &str, code: &str, ext: &str, body: &[u8]) -> Result<EmotePack> {
+    std::fs::create_dir_all(dir().join(pack))?;
+    remove_images(pack, code)?;
+    let mut manifest = read_manifest(pack)?;
+    if manifest.emotes.remove(code).is_some() {
+        write_manifest(pack, &manifest)?;
+    }
+    let path = dir().join(pack).join(format!("{}.{}", code, ext));
+    std::fs::write(&path, body).with_context(|| format!("Failed to write {:?}", path))?;
+    load_pack(pack)
+}
+
+/// Removes an emote from a pack. `None` if it had no such emote.
+pub fn remove(pack: &str, code: &str) -> Result<Option<EmotePack>> {
+    if !dir().join(pack).is_dir() {
+        return Ok(None);
+    }
+    let mut found = remove_images(pack, code)?;
+    let mut manifest = read_manifest(pack)?;
+    if manifest.emotes.remove(code).is_some() {
+        write_manifest(pack, &manifest)?;
+        found = true;
+    }
+    found.then(|| load_pack(pack)).transpose()
+}
+
+/// Removes the images for `code` in any format. Whether there were any.
+fn remove_images(pack: &str, code: &str) -> Result<bool> {
+    let mut found = false;
+    for (ext, _) in images::TYPES {
+        let path = dir().join(pack).join(format!("{}.{}", code, ext));
+        if path.is_file() {
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+            found = true;
+        }
+    }
+    Ok(found)
+}
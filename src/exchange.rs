@@ -1,12 +1,15 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 
 use anyhow::{anyhow, Result};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 
-const RATES_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+/// Last rates fetched, in the data directory, used when they can't be fetched.
+pub const BACKUP: &str = "exchange_rates.xml";
+/// Rates set by hand with `stream-nexus rates set`, in the data directory.
+/// They replace the published rates for their currencies.
+pub const OVERRIDES: &str = "exchange_overrides.json";
 
 pub struct ExchangeRates {
     rates: HashMap<String, f64>,
@@ -23,6 +26,27 @@ impl ExchangeRates {
             }
         }
     }
+
+    /// Whether there is a rate for `currency`.
+    pub fn knows(&self, currency: &str) -> bool {
+        self.rates.contains_key(currency)
+    }
+
+    /// Uses `overrides` in place of the published rates for their currencies.
+    pub fn with_overrides(mut self, overrides: &BTreeMap<String, f64>) -> Self {
+        for (currency, rate) in overrides {
+            self.rates.insert(currency.to_owned(), *rate);
+        }
+        self
+    }
+
+    /// Units of each currency per euro, by currency code.
+    pub fn rates(&self) -> BTreeMap<&str, f64> {
+        self.rates
+            .iter()
+            .map(|(currency, rate)| (currency.as_str(), *rate))
+            .collect()
+    }
 }
 
 fn parse_xml(body: &str) -> Result<ExchangeRates> {
@@ -34,33 +58,31 @@ fn parse_xml(body: &str) -> Result<ExchangeRates> {
     loop {
         match r.read_event_into(&mut buf) {
             Ok(Event::Eof) => break,
-            Ok(Event::Empty(e)) => match e.name().as_ref() {
-                b"Cube" => {
-                    let mut c = String::new();
-                    let mut v = String::new();
-
-                    for a in e.attributes().into_iter() {
-                        let attr = a.unwrap();
-
-                        match attr.key.as_ref() {
-                            b"currency" => c = String::from_utf8(attr.value.to_vec())?,
-                            b"rate" => v = String::from_utf8(attr.value.to_vec())?,
-                            _ => (),
-                        }
-                    }
-                    assert_ne!(c, v);
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"Cube" => {
+                let mut c = String::new();
+                let mut v = String::new();
 
-                    let rate: f64 = v.parse()?;
-                    rates.insert(c, rate);
+                for a in e.attributes() {
+                    let attr = a.unwrap();
+
+                    match attr.key.as_ref() {
+                        b"currency" => c = String::from_utf8(attr.value.to_vec())?,
+                        b"rate" => v = String::from_utf8(attr.value.to_vec())?,
+                        _ => (),
+                    }
                 }
-                _ => (),
-            },
+
+                let rate: f64 = v.parse()?;
+                rates.insert(c, rate);
+            }
             Err(e) => return Err(anyhow!(e)),
             _ => (),
         }
     }
 
-    assert_ne!(rates.len(), 0);
+    if rates.is_empty() {
+        return Err(anyhow!("No exchange rates found."));
+    }
     rates.insert(String::from("EUR"), 1.0);
     // Static RUB rate taken on 2024-08-24.
     rates.insert(String::from("RUB"), 102.33);
@@ -68,36 +90,69 @@ fn parse_xml(body: &str) -> Result<ExchangeRates> {
     Ok(ExchangeRates { rates })
 }
 
-pub async fn fetch_exchange_rates() -> Result<ExchangeRates> {
-    let mut f = OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open("exchange_rates.xml")
-        .expect("Failed to open exchange rates backup file.");
-
-    // Send an HTTP GET request to the URL
-    let response = reqwest::get(RATES_URL).await?;
-
-    // Check if the request was successful
-    if response.status().is_success() {
-        let text = response.text().await?;
-        // Check for XML subject text.
-        if text.contains("Reference rates") {
-            // Parses the XML response into an ExchangeRates.
-            match parse_xml(&text) {
-                Ok(r) => {
-                    f.write_all(text.as_bytes())
-                        .expect("Failed to write exchange write backup to file.");
-                    return Ok(r);
-                }
-                Err(_) => (),
-            }
-        }
+/// Rates published at `url`, saved to `backup` for when they can't be fetched.
+pub async fn download(url: &str, backup: &Path) -> Result<ExchangeRates> {
+    let text = fetch_xml(url)
+        .await
+        .map_err(|err| anyhow!("Failed to fetch exchange rates from {}: {}", url, err))?;
+    // Parses the XML response into an ExchangeRates.
+    let rates = parse_xml(&text)
+        .map_err(|err| anyhow!("Exchange rates from {} are unreadable: {}", url, err))?;
+    if let Err(err) = std::fs::write(backup, &text) {
+        log::error!(
+            "Failed to write exchange rates backup {}: {}",
+            backup.display(),
+            err
+        );
     }
+    Ok(rates)
+}
 
-    log::error!("Failed to fetch Exchange Rates! System will rely on old data!");
-    let mut text = String::new();
-    f.read_to_string(&mut text)?;
+/// The last rates saved by `download`.
+pub fn load_backup(backup: &Path) -> Result<ExchangeRates> {
+    let text = std::fs::read_to_string(backup).map_err(|err| {
+        anyhow!(
+            "Failed to read exchange rates backup {}: {}",
+            backup.display(),
+            err
+        )
+    })?;
     parse_xml(&text)
+        .map_err(|err| anyhow!("No usable exchange rates in {}: {}", backup.display(), err))
+}
+
+/// Rates published at `url`, or the last rates saved to `backup` if they can't be fetched.
+pub async fn fetch_exchange_rates(url: &str, backup: &Path) -> Result<ExchangeRates> {
+    match download(url, backup).await {
+        Ok(rates) => return Ok(rates),
+        Err(err) => log::error!("{}", err),
+    }
+
+    log::error!("Failed to fetch Exchange Rates! System will rely on old data!");
+    load_backup(backup)
+}
+
+/// Rates set by hand, by currency code. Missing file means none.
+pub fn overrides(path: &Path) -> Result<BTreeMap<String, f64>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|err| anyhow!("{} is invalid: {}", path.display(), err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(anyhow!("Failed to read {}: {}", path.display(), err)),
+    }
+}
+
+pub fn save_overrides(path: &Path, overrides: &BTreeMap<String, f64>) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(overrides)?)
+        .map_err(|err| anyhow!("Failed to write {}: {}", path.display(), err))
+}
+
+async fn fetch_xml(url: &str) -> Result<String> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let text = response.text().await?;
+    // Check for XML subject text.
+    if !text.contains("Reference rates") {
+        return Err(anyhow!("Response is not a reference rates document."));
+    }
+    Ok(text)
 }
@@ -0,0 +1,635 @@
+//! Avatars and emojis served from `/img/{hash}` instead of platform CDNs.
+//!
+//! A message's image URLs are swapped for proxy URLs as it comes in. Each image is
+//! fetched the first time an overlay asks for it, checked to really be an image, and
+//! kept in `img/` in the data directory. Once the cache is over `images.cache_size`,
+//! the images used longest ago are removed. They are fetched again if needed, since
+//! the URL each hash stands for is kept beside it, for the last `MAX_URLS` URLs.
+
+use actix_web::error::BlockingError;
+use actix_web::web;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::config;
+
+const CACHE_DIR: &str = "img";
+/// Hex characters of the URL's hash used as its name.
+const HASH_LEN: usize = 32;
+/// Most image URLs kept. The ones proxied longest ago are forgotten first.
+const MAX_URLS: usize = 10_000;
+
+/// Image types served, by extension.
+pub const TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+];
+
+#[derive(Debug, Clone)]
+pub enum ImageError {
+    /// No image has this hash.
+    NotFound,
+    /// The CDN couldn't be reached or didn't answer with an image.
+    Upstream(String),
+    Io(Arc<std::io::Error>),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::NotFound => write!(f, "No image with that hash."),
+            ImageError::Upstream(reason) => write!(f, "{}", reason),
+            ImageError::Io(err) => write!(f, "Image cache failed: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImageError {
+    fn from(err: std::io::Error) -> Self {
+        ImageError::Io(Arc::new(err))
+    }
+}
+
+impl From<BlockingError> for ImageError {
+    fn from(err: BlockingError) -> Self {
+        ImageError::Io(Arc::new(std::io::Error::other(err.to_string())))
+    }
+}
+
+impl From<reqwest::Error> for ImageError {
+    fn from(err: reqwest::Error) -> Self {
+        ImageError::Upstream(err.without_url().to_string())
+    }
+}
+
+/// A cached image ready to be served.
+#[derive(Clone)]
+pub struct Image {
+    pub body: web::Bytes,
+    pub content_type: &'static str,
+}
+
+struct Entry {
+    ext: &'static str,
+    size: u64,
+    used: SystemTime,
+}
+
+/// A URL written down so its hash can be fetched.
+struct Known {
+    url: String,
+    used: SystemTime,
+}
+
+#[derive(Default)]
+struct Index {
+    /// Hashes with their URL written down.
+    known: HashMap<String, Known>,
+    /// Images on disk.
+    cached: HashMap<String, Entry>,
+    /// Bytes used by `cached`.
+    total: u64,
+}
+
+impl Index {
+    /// Forgets the URLs proxied longest ago once there are more than `MAX_URLS`,
+    /// returning their hashes. A tenth go at once, so this doesn't sort for every URL.
+    fn forget_oldest(&mut self) -> Vec<String> {
+        if self.known.len() <= MAX_URLS {
+            return Vec::new();
+        }
+        let mut oldest: Vec<(SystemTime, String)> = self
+            .known
+            .iter()
+            .map(|(hash, known)| (known.used, hash.to_owned()))
+            .collect();
+        oldest.sort();
+        let excess = self.known.len() - MAX_URLS + MAX_URLS / 10;
+        oldest
+            .into_iter()
+            .take(excess)
+            .map(|(_, hash)| {
+                self.known.remove(&hash);
+                hash
+            })
+            .collect()
+    }
+}
+
+/// A fetch under way, shared by everyone waiting for the image.
+type Fetch = Shared<oneshot::Receiver<Result<Image, ImageError>>>;
+
+pub struct ImageCache {
+    dir: PathBuf,
+    client: reqwest::Client,
+    index: Mutex<Index>,
+    /// Fetches under way by hash, so an image is fetched once however many ask for it.
+    fetching: Mutex<HashMap<String, Fetch>>,
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == HASH_LEN && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+pub fn content_type(ext: &str) -> Option<&'static str> {
+    TYPES.iter().find(|(e, _)| *e == ext).map(|(_, t)| *t)
+}
+
+/// The image type `body` really is, going by its first bytes, as an extension.
+pub fn sniff(body: &[u8]) -> Option<&'static str> {
+    if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if body.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("jpg")
+    } else if body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if body.len() >= 12 && &body[..4] == b"RIFF" && &body[8..12] == b"WEBP" {
+        Some("webp")
+    } else if body.len() >= 12
+        && &body[4..8] == b"ftyp"
+        && matches!(&body[8..12], b"avif" | b"avis")
+    {
+        Some("avif")
+    } else {
+        // SVG is text, so look for its root element near the start.
+        let start = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_lowercase();
+        let start = start.trim_start_matches('\u{feff}').trim_start();
+        (start.starts_with('<') && start.contains("<svg")).then_some("svg")
+    }
+}
+
+/// Removes files the cache no longer needs, logging any that won't go.
+fn remove_files(paths: Vec<PathBuf>) {
+    for path in paths {
+        if let Err(err) = std::fs::remove_file(&path) {
+            log::warn!(
+                "Failed to remove {:?} from the image cache: {:?}",
+                path,
+                err
+            );
+        }
+    }
+}
+
+impl ImageCache {
+    /// Picks up images cached by earlier runs.
+    pub fn new() -> Self {
+        Self::open(config::get().paths.data_file(CACHE_DIR))
+    }
+
+    /// The cache kept in `dir`.
+    fn open(dir: PathBuf) -> Self {
+        let mut index = Index::default();
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(hash), Some(ext)) = (
+                    path.file_stem().and_then(|s| s.to_str()),
+                    path.extension().and_then(|s| s.to_str()),
+                ) else {
+                    continue;
+                };
+                if !is_hash(hash) {
+                    continue;
+                }
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                if ext == "url" {
+                    if let Ok(url) = std::fs::read_to_string(&path) {
+                        index.known.insert(hash.to_owned(), Known { url, used });
+                    }
+                } else if let Some((ext, _)) = TYPES.iter().find(|(e, _)| *e == ext) {
+                    index.total += meta.len();
+                    index.cached.insert(
+                        hash.to_owned(),
+                        Entry {
+                            ext,
+                            size: meta.len(),
+                            used,
+                        },
+                    );
+                } else if ext == "tmp" {
+                    // Left by a fetch that was cut off.
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        let forgotten = index.forget_oldest();
+        remove_files(
+            forgotten
+                .iter()
+                .map(|hash| dir.join(format!("{}.url", hash)))
+                .collect(),
+        );
+        if !index.cached.is_empty() {
+            log::info!(
+                "Image cache has {} images, {} KiB.",
+                index.cached.len(),
+                index.total / 1024
+            );
+        }
+
+        Self {
+            dir,
+            client: reqwest::Client::new(),
+            index: Mutex::new(index),
+            fetching: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The proxy URL for an image, or `url` unchanged if the proxy is off or it isn't
+    /// on the web, like `data:` URLs and our own logos.
+    pub fn proxy_url(&self, url: &str) -> String {
+        if !config::get().images.proxy
+            || !(url.starts_with("https://") || url.starts_with("http://"))
+        {
+            return url.to_owned();
+        }
+        let hash = hex::encode(Sha256::digest(url.as_bytes()))[..HASH_LEN].to_owned();
+
+        if let Some(known) = self.index.lock().unwrap().known.get_mut(&hash) {
+            known.used = SystemTime::now();
+            return format!("/img/{}", hash);
+        }
+        // Written down so cached messages still have images after a restart.
+        let path = self.dir.join(format!("{}.url", hash));
+        if let Err(err) =
+            std::fs::create_dir_all(&self.dir).and_then(|()| std::fs::write(&path, url))
+        {
+            log::error!("Failed to save image URL {:?}: {:?}", path, err);
+            return url.to_owned();
+        }
+        let forgotten = {
+            let mut index = self.index.lock().unwrap();
+            index.known.insert(
+                hash.to_owned(),
+                Known {
+                    url: url.to_owned(),
+                    used: SystemTime::now(),
+                },
+            );
+            index.forget_oldest()
+        };
+        remove_files(
+            forgotten
+                .iter()
+                .map(|hash| self.dir.join(format!("{}.url", hash)))
+                .collect(),
+        );
+        format!("/img/{}", hash)
+    }
+
+    /// The image `hash` stands for, fetching it if it isn't cached.
+    pub async fn get(self: &Arc<Self>, hash: &str) -> Result<Image, ImageError> {
+        if !is_hash(hash) {
+            return Err(ImageError::NotFound);
+        }
+        if let Some(image) = self.cached(hash).await? {
+            return Ok(image);
+        }
+
+        let fetch = {
+            let mut fetching = self.fetching.lock().unwrap();
+            match fetching.get(hash) {
+                Some(fetch) => fetch.clone(),
+                None => {
+                    let url = self
+                        .index
+                        .lock()
+                        .unwrap()
+                        .known
+                        .get(hash)
+                        .map(|known| known.url.to_owned())
+                        .ok_or(ImageError::NotFound)?;
+                    let (done, fetch) = oneshot::channel();
+                    let fetch = fetch.shared();
+                    fetching.insert(hash.to_owned(), fetch.clone());
+
+                    // Runs to the end even if whoever asked goes away, so the image
+                    // is still stored for the next one.
+                    let cache = self.clone();
+                    let hash = hash.to_owned();
+                    actix_web::rt::spawn(async move {
+                        let result = cache.fetch_and_store(&hash, &url).await;
+                        cache.fetching.lock().unwrap().remove(&hash);
+                        let _ = done.send(result);
+                    });
+                    fetch
+                }
+            }
+        };
+        fetch
+            .await
+            .unwrap_or_else(|_| Err(ImageError::Upstream("The fetch was cut off.".to_owned())))
+    }
+
+    async fn cached(&self, hash: &str) -> Result<Option<Image>, ImageError> {
+        let Some(ext) = self
+            .index
+            .lock()
+            .unwrap()
+            .cached
+            .get(hash)
+            .map(|entry| entry.ext)
+        else {
+            return Ok(None);
+        };
+        let path = self.dir.join(format!("{}.{}", hash, ext));
+        let used = SystemTime::now();
+        let read = web::block(move || {
+            let body = std::fs::read(&path)?;
+            // Keep the order across restarts.
+            if let Ok(file) = File::options().write(true).open(&path) {
+                let _ = file.set_modified(used);
+            }
+            Ok::<_, std::io::Error>(body)
+        })
+        .await?;
+
+        let mut index = self.index.lock().unwrap();
+        match read {
+            Ok(body) => {
+                if let Some(entry) = index.cached.get_mut(hash) {
+                    entry.used = used;
+                }
+                Ok(Some(Image {
+                    body: body.into(),
+                    content_type: content_type(ext).unwrap_or("application/octet-stream"),
+                }))
+            }
+            // Removed behind our back, so fetch it again.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                if let Some(entry) = index.cached.remove(hash) {
+                    index.total -= entry.size;
+                }
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn fetch_and_store(&self, hash: &str, url: &str) -> Result<Image, ImageError> {
+        let (ext, body) = self.fetch(url).await.map_err(|err| {
+            log::warn!("Failed to fetch image {}: {}", url, err);
+            err
+        })?;
+        let body = web::Bytes::from(body);
+        self.store(hash, ext, body.clone()).await?;
+        Ok(Image {
+            body,
+            content_type: content_type(ext).unwrap_or("application/octet-stream"),
+        })
+    }
+
+    async fn fetch(&self, url: &str) -> Result<(&'static str, Vec<u8>), ImageError> {
+        let settings = config::get().images.to_owned();
+        let max_size = settings.max_size * 1024;
+
+        let mut response = self
+            .client
+            .get(url)
+            .timeout(Duration::from_secs(settings.timeout))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let declared = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if !TYPES.iter().any(|(_, t)| *t == declared) {
+            return Err(ImageError::Upstream(format!(
+                "Not an image type we serve: {:?}",
+                declared
+            )));
+        }
+        if response.content_length().is_some_and(|len| len > max_size) {
+            return Err(ImageError::Upstream(format!(
+                "Larger than {} KiB.",
+                settings.max_size
+            )));
+        }
+
+        // Content-Length can be missing or wrong, so count as it comes in too.
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() as u64 + chunk.len() as u64 > max_size {
+                return Err(ImageError::Upstream(format!(
+                    "Larger than {} KiB.",
+                    settings.max_size
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let ext = sniff(&body).ok_or_else(|| {
+            ImageError::Upstream(format!("Sent as {} but is not an image.", declared))
+        })?;
+        Ok((ext, body))
+    }
+
+    /// Writes an image to the cache, then makes room for it.
+    async fn store(
+        &self,
+        hash: &str,
+        ext: &'static str,
+        body: web::Bytes,
+    ) -> Result<(), ImageError> {
+        let size = body.len() as u64;
+        let dir = self.dir.clone();
+        // Written aside first, so an overlay never gets half an image.
+        let temp = dir.join(format!("{}.{}.tmp", hash, uuid::Uuid::new_v4()));
+        let path = dir.join(format!("{}.{}", hash, ext));
+        web::block(move || {
+            std::fs::create_dir_all(&dir)?;
+            let mut file = File::create(&temp)?;
+            file.write_all(&body)?;
+            drop(file);
+            std::fs::rename(&temp, &path)
+        })
+        .await??;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            if let Some(old) = index.cached.insert(
+                hash.to_owned(),
+                Entry {
+                    ext,
+                    size,
+                    used: SystemTime::now(),
+                },
+            ) {
+                index.total -= old.size;
+            }
+            index.total += size;
+
+            let limit = config::get().images.cache_size * 1024 * 1024;
+            let mut evicted = Vec::new();
+            while index.total > limit {
+                let Some(oldest) = index
+                    .cached
+                    .iter()
+                    .filter(|(other, _)| other.as_str() != hash)
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(other, _)| other.to_owned())
+                else {
+                    break;
+                };
+                let entry = index
+                    .cached
+                    .remove(&oldest)
+                    .expect("Oldest image is cached.");
+                index.total -= entry.size;
+                evicted.push(self.dir.join(format!("{}.{}", oldest, entry.ext)));
+            }
+            evicted
+        };
+        if !evicted.is_empty() {
+            web::block(move || remove_files(evicted)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "../examples/mock_image_cdn.rs"]
+#[allow(dead_code)]
+mod mock_image_cdn;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpServer};
+
+    /// Starts the mock CDN on a free port, returning its address.
+    fn mock_cdn() -> String {
+        let fixture = web::Data::new(include_bytes!("../fixtures/images/avatar.png").to_vec());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(fixture.clone())
+                .default_service(web::to(mock_image_cdn::handle))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", address)
+    }
+
+    /// An empty cache in a directory of its own.
+    fn cache() -> Arc<ImageCache> {
+        config::init_for_tests();
+        let dir = std::env::temp_dir().join(format!("stream-nexus-img-{}", uuid::Uuid::new_v4()));
+        Arc::new(ImageCache::open(dir))
+    }
+
+    fn hash(proxied: &str) -> &str {
+        proxied.strip_prefix("/img/").expect("URL was proxied.")
+    }
+
+    #[actix_web::test]
+    async fn fetches_and_caches_png() {
+        let cdn = mock_cdn();
+        let cache = cache();
+        let proxied = cache.proxy_url(&format!("{}/avatar.png", cdn));
+
+        let (first, second) = futures::join!(cache.get(hash(&proxied)), cache.get(hash(&proxied)));
+        let image = first.unwrap();
+        assert_eq!(image.content_type, "image/png");
+        assert_eq!(
+            &image.body[..],
+            include_bytes!("../fixtures/images/avatar.png")
+        );
+        assert_eq!(second.unwrap().body, image.body);
+        assert!(cache
+            .index
+            .lock()
+            .unwrap()
+            .cached
+            .contains_key(hash(&proxied)));
+        assert!(cache.fetching.lock().unwrap().is_empty());
+
+        let again = cache.get(hash(&proxied)).await.unwrap();
+        assert_eq!(again.body, image.body);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn rejects_what_is_not_a_small_image() {
+        let cdn = mock_cdn();
+        let cache = cache();
+        // Too big by Content-Length, too big without one, HTML claiming to be a PNG
+        // and something that doesn't claim to be an image at all.
+        for path in [
+            "/huge.png",
+            "/streamed.png",
+            "/page.png",
+            "/text",
+            "/missing.png",
+        ] {
+            let proxied = cache.proxy_url(&format!("{}{}", cdn, path));
+            assert!(
+                matches!(
+                    cache.get(hash(&proxied)).await,
+                    Err(ImageError::Upstream(_))
+                ),
+                "{} was not rejected",
+                path
+            );
+        }
+        assert!(cache.index.lock().unwrap().cached.is_empty());
+        assert!(matches!(
+            cache.get("0123456789abcdef0123456789abcdef").await,
+            Err(ImageError::NotFound)
+        ));
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn evicts_least_recently_used_at_the_cap() {
+        let cdn = mock_cdn();
+        let cache = cache();
+        // Each is 400 KiB, so only two fit in the 1 MiB test cache.
+        let proxied: Vec<String> = (0..3)
+            .map(|n| cache.proxy_url(&format!("{}/large.png?{}", cdn, n)))
+            .collect();
+
+        cache.get(hash(&proxied[0])).await.unwrap();
+        cache.get(hash(&proxied[1])).await.unwrap();
+        // Using the first again makes the second the oldest.
+        cache.get(hash(&proxied[0])).await.unwrap();
+        cache.get(hash(&proxied[2])).await.unwrap();
+
+        let index = cache.index.lock().unwrap();
+        assert!(index.cached.contains_key(hash(&proxied[0])));
+        assert!(!index.cached.contains_key(hash(&proxied[1])));
+        assert!(index.cached.contains_key(hash(&proxied[2])));
+        assert_eq!(index.total, 2 * 400 * 1024);
+        assert!(!cache
+            .dir
+            .join(format!("{}.png", hash(&proxied[1])))
+            .exists());
+        drop(index);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+//! Append-only record of every donation, kept for accounting.
+//!
+//! Unlike `super_chats.json`, entries keep the original amount and currency and the
+//! rate used to convert them. Edits and voids append a new entry for the same id;
+//! the last one wins.
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use uuid::Uuid;
+
+use crate::config;
+use crate::message::Message;
+use crate::text::csv_field;
+
+const LEDGER: &str = "donations.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+    /// Server session the donation was first received in.
+    pub session: String,
+    pub id: Uuid,
+    pub platform: String,
+    pub username: String,
+    pub original_amount: f64,
+    pub original_currency: String,
+    /// USD per unit of the original currency at the time of conversion.
+    pub rate: f64,
+    /// Converted amount in USD.
+    pub amount: f64,
+    /// Unix timestamp in milliseconds.
+    pub sent_at: i64,
+    /// Unix timestamp in milliseconds.
+    pub recorded_at: i64,
+    #[serde(default)]
+    pub voided: bool,
+}
+
+impl LedgerEntry {
+    /// Records `msg` once it has been converted to USD.
+    pub fn new(
+        session: &str,
+        msg: &Message,
+        original_amount: f64,
+        original_currency: &str,
+    ) -> Self {
+        Self {
+            session: session.to_owned(),
+            id: msg.id,
+            platform: msg.platform.to_owned(),
+            username: msg.username.to_owned(),
+            original_amount,
+            original_currency: original_currency.to_owned(),
+            rate: if original_amount > 0.0 {
+                msg.amount / original_amount
+            } else {
+                0.0
+            },
+            amount: msg.amount,
+            sent_at: msg.sent_at,
+            recorded_at: chrono::Utc::now().timestamp_millis(),
+            voided: false,
+        }
+    }
+}
+
+pub fn append(entry: &LedgerEntry) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::get().paths.data_file(LEDGER))?;
+    writeln!(f, "{}", serde_json::to_string(entry)?)
+}
+
+/// Every entry in the ledger, oldest first. Unreadable lines are skipped.
+pub fn entries() -> Vec<LedgerEntry> {
+    let f = match std::fs::File::open(config::get().paths.data_file(LEDGER)) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+
+    BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                log::warn!("Skipping unreadable ledger entry: {:?}", err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The current state of each donation in `ids` that was ever recorded.
+pub fn latest(ids: &HashSet<Uuid>) -> HashMap<Uuid, LedgerEntry> {
+    entries()
+        .into_iter()
+        .filter(|entry| ids.contains(&entry.id))
+        .map(|entry| (entry.id, entry))
+        .collect()
+}
+
+/// Parses a date or RFC 3339 timestamp into Unix milliseconds.
+/// A bare date is the start of that day in UTC, or the start of the next one if `end_of_day`.
+pub fn parse_time(value: &str, end_of_day: bool) -> Option<i64> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.timestamp_millis());
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let date = if end_of_day { date.succ_opt()? } else { date };
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis())
+}
+
+/// Which donations to export.
+#[derive(Debug, Default)]
+pub struct LedgerFilter {
+    pub session: Option<String>,
+    /// Inclusive lower bound on `sent_at`.
+    pub from: Option<i64>,
+    /// Exclusive upper bound on `sent_at`.
+    pub to: Option<i64>,
+}
+
+impl LedgerFilter {
+    fn matches(&self, entry: &LedgerEntry) -> bool {
+        self.session.as_ref().is_none_or(|s| *s == entry.session)
+            && self.from.is_none_or(|from| entry.sent_at >= from)
+            && self.to.is_none_or(|to| entry.sent_at < to)
+    }
+}
+
+/// Current state of every donation matching `filter`, oldest first. Voided donations are left out.
+pub fn donations(filter: &LedgerFilter) -> Vec<LedgerEntry> {
+    let mut latest: HashMap<Uuid, LedgerEntry> = HashMap::new();
+    for entry in entries() {
+        latest.insert(entry.id, entry);
+    }
+
+    let mut donations: Vec<LedgerEntry> = latest
+        .into_values()
+        .filter(|entry| !entry.voided && filter.matches(entry))
+        .collect();
+    donations.sort_by_key(|entry| (entry.sent_at, entry.recorded_at));
+    donations
+}
+
+/// Sessions in the ledger with their first and last donation, oldest first.
+pub fn sessions() -> Vec<Session> {
+    let mut sessions: Vec<Session> = Vec::new();
+    for entry in donations(&LedgerFilter::default()) {
+        match sessions.iter_mut().find(|s| s.session == entry.session) {
+            Some(session) => {
+                session.count += 1;
+                session.first_at = session.first_at.min(entry.sent_at);
+                session.last_at = session.last_at.max(entry.sent_at);
+            }
+            None => sessions.push(Session {
+                session: entry.session,
+                count: 1,
+                first_at: entry.sent_at,
+                last_at: entry.sent_at,
+            }),
+        }
+    }
+    sessions.sort_by_key(|s| s.first_at);
+    sessions
+}
+
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub session: String,
+    pub count: usize,
+    pub first_at: i64,
+    pub last_at: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Subtotal {
+    pub platform: String,
+    pub count: usize,
+    /// Sum in USD.
+    pub amount: f64,
+}
+
+/// Per-platform totals, largest first.
+pub fn subtotals(donations: &[LedgerEntry]) -> Vec<Subtotal> {
+    let mut subtotals: Vec<Subtotal> = Vec::new();
+    for entry in donations {
+        match subtotals.iter_mut().find(|s| s.platform == entry.platform) {
+            Some(subtotal) => {
+                subtotal.count += 1;
+                subtotal.amount += entry.amount;
+            }
+            None => subtotals.push(Subtotal {
+                platform: entry.platform.to_owned(),
+                count: 1,
+                amount: entry.amount,
+            }),
+        }
+    }
+    subtotals.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+    subtotals
+}
+
+/// Donations with their totals, as exported in JSON.
+#[derive(Serialize, Debug)]
+pub struct Export {
+    pub donations: Vec<LedgerEntry>,
+    pub subtotals: Vec<Subtotal>,
+    pub count: usize,
+    /// Sum in USD.
+    pub total: f64,
+}
+
+impl Export {
+    pub fn new(donations: Vec<LedgerEntry>, subtotals: Vec<Subtotal>) -> Self {
+        Self {
+            count: donations.len(),
+            total: donations.iter().map(|entry| entry.amount).sum(),
+            donations,
+            subtotals,
+        }
+    }
+}
+
+/// Donations as CSV, followed by a blank line and the per-platform subtotals.
+pub fn to_csv(donations: &[LedgerEntry], subtotals: &[Subtotal]) -> String {
+    let mut csv = String::from(
+        "session,id,sent_at,platform,username,original_amount,original_currency,rate,amount_usd\n",
+    );
+    for entry in donations {
+        let sent_at = chrono::DateTime::from_timestamp_millis(entry.sent_at)
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{:.6},{:.2}\n",
+            csv_field(&entry.session),
+            entry.id,
+            sent_at,
+            csv_field(&entry.platform),
+            csv_field(&entry.username),
+            entry.original_amount,
+            csv_field(&entry.original_currency),
+            entry.rate,
+            entry.amount
+        ));
+    }
+
+    csv.push_str("\nplatform,count,amount_usd\n");
+    for subtotal in subtotals {
+        csv.push_str(&format!(
+            "{},{},{:.2}\n",
+            csv_field(&subtotal.platform),
+            subtotal.count,
+            subtotal.amount
+        ));
+    }
+    csv.push_str(&format!(
+        "total,{},{:.2}\n",
+        donations.len(),
+        donations.iter().map(|entry| entry.amount).sum::<f64>()
+    ));
+    csv
+}
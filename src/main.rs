@@ -1,48 +1,173 @@
+mod analytics;
+mod archive;
+mod audit;
+mod cli;
+mod colors;
+mod config;
+mod connector;
+mod emotes;
 mod exchange;
+mod images;
+mod ledger;
+mod marker;
 mod message;
+mod platform;
 mod sneed_env; // naming it "env" can be confusing.
+mod text;
+mod twitch;
+mod viewers;
 mod web;
+mod webhook;
 
+use crate::cli::{Cli, Command};
+use crate::config::{Config, Severity};
 use crate::web::ChatServer;
 
 use actix::Actor;
 use actix_web::{App, HttpServer};
 use anyhow::Result;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 
 #[actix_web::main]
-async fn main() -> Result<(), std::io::Error> {
-    sneed_env::get_env();
-    env_logger::init();
-
-    let chat = ChatServer::new(
-        exchange::fetch_exchange_rates()
-            .await
-            .expect("Failed to fetch exchange rates."),
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // Other commands print what they did, so only the server logs as it goes.
+    let log_level = match cli.command {
+        None | Some(Command::Serve) => "info",
+        Some(_) => "warn",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+    sneed_env::load_dotenv();
+    // A config asked for by name has to exist.
+    let required = cli.config.is_some();
+    let path = cli
+        .config
+        .unwrap_or_else(|| PathBuf::from(config::DEFAULT_PATH));
+    let config = match Config::load(&path, required) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, &path, required).await.map(|()| true),
+        command => {
+            config::init(&path, required, config.clone());
+            cli::run(command, &config, &path).await
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            log::error!("{:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Reloads the config whenever the process gets SIGHUP.
+#[cfg(unix)]
+fn reload_on_hangup(chat: actix::Addr<ChatServer>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            log::info!("Got SIGHUP, reloading the config.");
+            if let Err(err) = web::reload_config(&chat).await {
+                log::error!("{:#}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn serve(config: Config, path: &Path, required: bool) -> Result<()> {
+    let problems = config.check();
+    for problem in &problems {
+        match problem.severity {
+            Severity::Error => log::error!("{}", problem.message),
+            Severity::Warning => log::warn!("{}", problem.message),
+        }
+    }
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        anyhow::bail!("Fix the config and try again, or run `stream-nexus config check`.");
+    }
+    config::init(path, required, config.clone());
+
+    let exchange_rates = exchange::fetch_exchange_rates(
+        &config.exchange.url,
+        &config.paths.data_file(exchange::BACKUP),
     )
-    .start();
+    .await?
+    .with_overrides(&exchange::overrides(
+        &config.paths.data_file(exchange::OVERRIDES),
+    )?);
+    let images = Arc::new(images::ImageCache::new());
+    let chat = ChatServer::new(exchange_rates, &config, images.clone()).start();
+    #[cfg(unix)]
+    reload_on_hangup(chat.clone())?;
     let chat_for_server = chat.clone();
+    let connectors = connector::ConnectorSupervisor::create(|ctx| {
+        let mut supervisor = connector::ConnectorSupervisor::new(chat.clone());
+        supervisor.start_connectors(&config.connectors, ctx);
+        supervisor
+    });
+    let connectors_for_server = connectors.clone();
 
-    HttpServer::new(move || {
+    let result = HttpServer::new(move || {
         App::new()
             .app_data(chat_for_server.clone())
+            .app_data(connectors_for_server.clone())
+            .app_data(images.clone())
             .service(web::javascript)
             .service(web::dashboard_javascript)
             .service(web::stylesheet)
             .service(web::dashboard_stylesheet)
             .service(web::colors)
+            .service(web::custom_stylesheet)
+            .service(web::layout_stylesheet)
+            .service(web::theme_stylesheet)
             .service(web::chat)
             .service(web::dashboard)
             .service(web::overlay)
+            .service(web::overlay_profile)
             .service(web::websocket)
+            .service(web::events)
+            .service(web::api::scope())
             .service(web::logo)
+            .service(web::badge)
+            .service(web::platform_stylesheet)
+            .service(web::image)
+            .service(web::emote_image)
     })
     //.workers(1)
-    .bind(format!(
-        "{}:{}",
-        dotenvy::var("SERVER_IP").expect("SERVER_IP not defined."),
-        dotenvy::var("SERVER_PORT").expect("SERVER_PORT not defined.")
-    ))
-    .expect("Could not bind requested address.")
+    .bind((config.server.ip.as_str(), config.server.port))
+    .map_err(|err| {
+        anyhow::anyhow!(
+            "Could not bind {}:{}: {}",
+            config.server.ip,
+            config.server.port,
+            err
+        )
+    })?
     .run()
-    .await
+    .await;
+
+    // Keep a report of the session's chat once the server has stopped.
+    match chat.send(web::ChatAnalytics).await {
+        Ok(report) => match analytics::save_report(&report) {
+            Ok(path) => log::info!("Saved chat analytics to {}.", path),
+            Err(err) => log::error!("Failed to save chat analytics: {:?}", err),
+        },
+        Err(err) => log::error!("Failed to get chat analytics: {:?}", err),
+    }
+
+    Ok(result?)
 }
@@ -0,0 +1,340 @@
+//! Timestamped stream markers, for finding highlights in the VOD.
+//!
+//! Markers are added by hand from the dashboard or detected from chat: bursts of
+//! messages well above the usual rate, floods of one emote and big donations.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use uuid::Uuid;
+
+use crate::config::{self, MarkersConfig};
+use crate::message::Message;
+use crate::text::{csv_field, one_line};
+
+const MARKER_LOG: &str = "markers.jsonl";
+/// Seconds of chat compared against the baseline.
+const SPIKE_WINDOW: usize = 10;
+/// Seconds the baseline rate is averaged over.
+const BASELINE_SECONDS: f64 = 300.0;
+/// Seconds of chat needed for a baseline before spikes are marked.
+const WARM_UP_SECS: i64 = 60;
+/// Seconds before another marker of the same kind.
+const COOLDOWN_SECS: i64 = 60;
+/// YouTube ignores chapters shorter than this.
+const MIN_CHAPTER_SECS: i64 = 10;
+/// Frame rate of EDL timecodes.
+const EDL_FPS: i64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerKind {
+    /// Chat moving much faster than usual.
+    Spike,
+    /// Lots of one emote at once.
+    EmoteFlood,
+    /// A donation at or above the configured amount.
+    Superchat,
+    Manual,
+}
+
+impl MarkerKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Spike => "spike",
+            Self::EmoteFlood => "emote_flood",
+            Self::Superchat => "superchat",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Marker {
+    pub session: String,
+    pub id: Uuid,
+    pub kind: MarkerKind,
+    /// Unix timestamp in milliseconds.
+    pub at: i64,
+    /// Milliseconds since the session started.
+    pub offset: i64,
+    pub label: String,
+    /// The chat message that caused the marker, for superchats.
+    pub message_id: Option<Uuid>,
+}
+
+impl Marker {
+    pub fn new(session: &str, started_at: i64, kind: MarkerKind, at: i64, label: String) -> Self {
+        Self {
+            session: session.to_owned(),
+            id: Uuid::new_v4(),
+            kind,
+            at,
+            offset: (at - started_at).max(0),
+            label,
+            message_id: None,
+        }
+    }
+}
+
+pub fn append(marker: &Marker) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::get().paths.data_file(MARKER_LOG))?;
+    writeln!(f, "{}", serde_json::to_string(marker)?)
+}
+
+/// Markers from `session`, in stream order. Unreadable lines are skipped.
+pub fn markers(session: &str) -> Vec<Marker> {
+    let f = match std::fs::File::open(config::get().paths.data_file(MARKER_LOG)) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut markers: Vec<Marker> = BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str::<Marker>(&line) {
+            Ok(marker) => Some(marker),
+            Err(err) => {
+                log::warn!("Skipping unreadable marker: {:?}", err);
+                None
+            }
+        })
+        .filter(|marker| marker.session == session)
+        .collect();
+    markers.sort_by_key(|marker| marker.offset);
+    markers
+}
+
+/// Chat activity in one second.
+#[derive(Default)]
+struct Second {
+    messages: usize,
+    emotes: HashMap<String, usize>,
+}
+
+/// Watches chat for spikes and emote floods.
+pub struct Detector {
+    config: MarkersConfig,
+    /// The last `SPIKE_WINDOW` seconds, newest last, with the current second.
+    seconds: VecDeque<(i64, Second)>,
+    /// Messages per second, as a moving average.
+    baseline: f64,
+    /// Seconds averaged into `baseline` so far, up to `BASELINE_SECONDS`.
+    baseline_seconds: f64,
+    /// Unix timestamp in milliseconds.
+    started_at: i64,
+    last_spike: i64,
+    last_flood: i64,
+}
+
+impl Detector {
+    pub fn new(config: MarkersConfig) -> Self {
+        Self {
+            config,
+            seconds: VecDeque::with_capacity(SPIKE_WINDOW + 1),
+            baseline: 0.0,
+            baseline_seconds: 0.0,
+            started_at: chrono::Utc::now().timestamp_millis(),
+            last_spike: 0,
+            last_flood: 0,
+        }
+    }
+
+    /// Applies new thresholds, keeping the chat seen so far.
+    pub fn set_config(&mut self, config: MarkersConfig) {
+        self.config = config;
+    }
+
+    fn current(&mut self, now: i64) -> &mut Second {
+        let second = now / 1000;
+        if self.seconds.back().is_none_or(|(at, _)| *at != second) {
+            self.seconds.push_back((second, Second::default()));
+        }
+        &mut self.seconds.back_mut().expect("Second was just added.").1
+    }
+
+    /// Counts a chat message and its emotes.
+    pub fn record(&mut self, msg: &Message) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let second = self.current(now);
+        second.messages += 1;
+        for (find, _, name) in &msg.emojis {
+            let count = msg.message.matches(find.as_str()).count();
+            if count > 0 {
+                *second.emotes.entry(name.to_owned()).or_default() += count;
+            }
+        }
+    }
+
+    /// A label for `msg` if it is big enough to mark. `msg.amount` must be in USD.
+    pub fn superchat(&self, msg: &Message) -> Option<String> {
+        (self.config.big_donation > 0.0 && msg.amount >= self.config.big_donation)
+            .then(|| format!("${:.2} from {}", msg.amount, msg.username))
+    }
+
+    /// Call once a second. Returns markers for a spike or emote flood that just began.
+    pub fn tick(&mut self, now: i64) -> Vec<(MarkerKind, i64, String)> {
+        self.current(now);
+        let oldest = now / 1000 - SPIKE_WINDOW as i64;
+        while self.seconds.front().is_some_and(|(at, _)| *at <= oldest) {
+            self.seconds.pop_front();
+        }
+
+        // The second that just ended moves the baseline.
+        let last = self
+            .seconds
+            .iter()
+            .rev()
+            .nth(1)
+            .map_or(0, |(_, second)| second.messages);
+        // A plain mean until there are enough seconds for the moving average, so the
+        // baseline is the rate seen so far rather than climbing from zero.
+        let baseline = self.baseline;
+        self.baseline_seconds = (self.baseline_seconds + 1.0).min(BASELINE_SECONDS);
+        self.baseline += (last as f64 - self.baseline) / self.baseline_seconds;
+
+        let mut markers = Vec::new();
+        let window_start = now - SPIKE_WINDOW as i64 * 1000;
+        let rate = self
+            .seconds
+            .iter()
+            .map(|(_, second)| second.messages)
+            .sum::<usize>() as f64
+            / SPIKE_WINDOW as f64;
+
+        if self.config.spike_factor > 0.0
+            && rate >= self.config.spike_min_rate
+            && rate >= baseline * self.config.spike_factor
+            && now - self.started_at >= WARM_UP_SECS * 1000
+            && now - self.last_spike >= COOLDOWN_SECS * 1000
+        {
+            self.last_spike = now;
+            markers.push((
+                MarkerKind::Spike,
+                window_start,
+                format!("Chat spike, {:.1} messages a second", rate),
+            ));
+        }
+
+        let mut emotes: HashMap<&str, usize> = HashMap::new();
+        for (_, second) in &self.seconds {
+            for (name, count) in &second.emotes {
+                *emotes.entry(name).or_default() += count;
+            }
+        }
+        if let Some((name, count)) = emotes.into_iter().max_by_key(|(_, count)| *count) {
+            if self.config.emote_flood > 0
+                && count >= self.config.emote_flood
+                && now - self.last_flood >= COOLDOWN_SECS * 1000
+            {
+                self.last_flood = now;
+                markers.push((
+                    MarkerKind::EmoteFlood,
+                    window_start,
+                    format!("{} flood, {} in {}s", name, count, SPIKE_WINDOW),
+                ));
+            }
+        }
+
+        markers
+    }
+}
+
+/// `M:SS`, or `H:MM:SS` past the first hour, as YouTube chapters want it.
+fn chapter_time(offset: i64) -> String {
+    let secs = offset / 1000;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Frames since the session started, at `EDL_FPS`.
+fn frames(offset: i64) -> i64 {
+    offset * EDL_FPS / 1000
+}
+
+/// `HH:MM:SS:FF` for a frame count.
+fn timecode(frames: i64) -> String {
+    let secs = frames / EDL_FPS;
+    format!(
+        "{:02}:{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        frames % EDL_FPS
+    )
+}
+
+/// Chapter list for a YouTube description. YouTube needs the first chapter at 0:00
+/// and at least ten seconds between chapters, so markers too close together are dropped.
+pub fn to_youtube_chapters(markers: &[Marker]) -> String {
+    let mut text = String::from("0:00 Start\n");
+    let mut last = 0;
+    for marker in markers {
+        if marker.offset / 1000 - last < MIN_CHAPTER_SECS {
+            continue;
+        }
+        last = marker.offset / 1000;
+        text.push_str(&format!(
+            "{} {}\n",
+            chapter_time(marker.offset),
+            one_line(&marker.label)
+        ));
+    }
+    text
+}
+
+/// CMX 3600 edit decision list with one locator per marker, for importing into an editor.
+pub fn to_edl(session: &str, markers: &[Marker]) -> String {
+    let mut edl = format!("TITLE: {}\nFCM: NON-DROP FRAME\n\n", session);
+    for (i, marker) in markers.iter().enumerate() {
+        let start = timecode(frames(marker.offset));
+        let end = timecode(frames(marker.offset) + 1);
+        edl.push_str(&format!(
+            "{:03}  AX       V     C        {} {} {} {}\n* LOC: {} {} {}\n\n",
+            i + 1,
+            start,
+            end,
+            start,
+            end,
+            start,
+            match marker.kind {
+                MarkerKind::Manual => "BLUE",
+                MarkerKind::Superchat => "GREEN",
+                MarkerKind::Spike | MarkerKind::EmoteFlood => "RED",
+            },
+            one_line(&marker.label)
+        ));
+    }
+    edl
+}
+
+pub fn to_csv(markers: &[Marker]) -> String {
+    let mut csv = String::from("session,id,kind,at,offset,timecode,label,message_id\n");
+    for marker in markers {
+        let at = chrono::DateTime::from_timestamp_millis(marker.at)
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            csv_field(&marker.session),
+            marker.id,
+            marker.kind.as_str(),
+            at,
+            marker.offset,
+            timecode(frames(marker.offset)),
+            csv_field(&marker.label),
+            marker
+                .message_id
+                .map(|id| id.to_string())
+                .unwrap_or_default()
+        ));
+    }
+    csv
+}
@@ -4,6 +4,9 @@ use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 use uuid::Uuid;
 
+use crate::colors;
+use crate::platform::{self, Badge, Platform};
+
 #[derive(Template)]
 #[template(path = "message.html")]
 struct MessageTemplate<'a> {
@@ -16,11 +19,25 @@ pub struct Message {
     pub id: Uuid,
     pub platform: String,
     pub message: String,
+    /// `message` as sent, before it was rendered to HTML. Kept for keyword filters
+    /// and never sent to clients.
+    #[serde(skip)]
+    pub text: String,
     pub emojis: Vec<(String, String, String)>,
     pub sent_at: i64,     // Display timestamp
     pub received_at: i64, // Our system received timestamp
     pub username: String,
+    /// Author's account id on the platform. Set by connectors, which read it from
+    /// the platform itself, so bans can be matched to the author.
+    #[serde(skip)]
+    pub user_id: Option<String>,
+    /// Channel a connector read the message from. Never set for feeders or ingest.
+    #[serde(skip)]
+    pub channel: Option<String>,
     pub avatar: String, // URL
+    /// Username color chosen on the platform, e.g. `#FF4500`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
     // Superchat
     pub amount: f64,
     pub currency: String,
@@ -30,6 +47,10 @@ pub struct Message {
     pub is_mod: bool,
     pub is_owner: bool,
     pub is_staff: bool,
+    /// Sent by `send` or `replay` to try out overlays. Shown like any other message,
+    /// but kept out of the archive, ledger, saved superchats and markers.
+    #[serde(default)]
+    pub is_test: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, ActixMessage, Clone)]
@@ -47,6 +68,12 @@ pub struct CommandFeatureMessage {
     pub feature_message: Option<Uuid>,
 }
 
+#[derive(Serialize, Deserialize, Debug, ActixMessage, Clone)]
+#[rtype(result = "()")]
+pub struct CommandResume {
+    pub resume: u64,
+}
+
 #[derive(Debug, Serialize)]
 struct JsonWrapper<'a> {
     #[serde(flatten)]
@@ -65,12 +92,16 @@ impl Default for Message {
             id: Uuid::new_v4(),
             platform: "NONE".to_string(),
             message: "DEFAULT_MESSAGE".to_string(),
+            text: String::new(),
             emojis: Vec::new(),
             sent_at: time,
             received_at: time,
             username: "NO_USERNAME".to_string(),
+            user_id: None,
+            channel: None,
             avatar: "data:image/gif;base64,R0lGODlhAQABAAAAACH5BAEKAAEALAAAAAABAAEAAAICTAEAOw=="
                 .to_string(),
+            color: None,
             amount: 0.0,
             currency: "ZWL".to_string(),
             is_verified: false,
@@ -78,6 +109,7 @@ impl Default for Message {
             is_mod: false,
             is_owner: false,
             is_staff: false,
+            is_test: false,
         }
     }
 }
@@ -101,13 +133,35 @@ impl Message {
             badges.push("staff");
         }
 
-        if badges.len() == 0 {
+        if badges.is_empty() {
             return "".to_string();
         }
 
         format!("msg--b-{}", badges.join(" msg--b-"))
     }
 
+    /// Badges the author has.
+    pub fn badges(&self) -> Vec<Badge> {
+        Badge::ALL
+            .into_iter()
+            .filter(|badge| badge.is_on(self))
+            .collect()
+    }
+
+    pub fn get_platform(&self) -> &'static Platform {
+        platform::get(&self.platform)
+    }
+
+    /// Name and icon URL of each badge the author has that their platform gives.
+    pub fn get_badge_icons(&self) -> Vec<(&'static str, String)> {
+        let platform = self.get_platform();
+        self.badges()
+            .into_iter()
+            .filter(|badge| platform.badges.contains(badge))
+            .map(|badge| (badge.name(), platform.badge_url(badge)))
+            .collect()
+    }
+
     pub fn is_premium(&self) -> bool {
         self.amount > 0.0
     }
@@ -118,7 +172,7 @@ impl Message {
 
     pub fn get_paid_readable_amount(&self) -> String {
         if self.is_premium() {
-            format!("{} {}", format!("{:.2}", self.amount), self.currency)
+            format!("{:.2} {}", self.amount, self.currency)
         } else {
             String::new()
         }
@@ -156,6 +210,22 @@ impl Message {
         }
     }
 
+    /// The username color for a style attribute, unless a palette class covers it.
+    pub fn get_username_color(&self) -> Option<&str> {
+        self.color
+            .as_deref()
+            .filter(|color| colors::is_hex(color) && colors::palette_index(color).is_none())
+    }
+
+    /// `msg-username--c{n}` if the username color is from the palette.
+    pub fn get_username_class(&self) -> String {
+        self.color
+            .as_deref()
+            .and_then(colors::palette_index)
+            .map(|index| format!("msg-username--c{}", index))
+            .unwrap_or_default()
+    }
+
     pub fn get_platform_string(&self) -> String {
         format!("msg--p-{}", self.platform)
     }
@@ -172,7 +242,7 @@ impl Message {
     }
 
     pub fn to_html(&self) -> String {
-        MessageTemplate { message: &self }
+        MessageTemplate { message: self }
             .render()
             .expect("Failed to render chat message.")
     }
@@ -0,0 +1,247 @@
+//! Platforms chat and donations come from, with how each one is shown.
+//!
+//! Messages name their platform as the userscripts and connectors send it, e.g.
+//! `YouTube`, so lookups ignore case and accept a few aliases. Unknown platforms get
+//! `UNKNOWN` rather than failing.
+
+use serde::{Deserialize, Serialize};
+
+use crate::message::Message;
+
+/// A chat author flag a platform can give.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Badge {
+    Owner,
+    Staff,
+    Mod,
+    Verified,
+    Sub,
+}
+
+impl Badge {
+    pub const ALL: [Badge; 5] = [
+        Badge::Owner,
+        Badge::Staff,
+        Badge::Mod,
+        Badge::Verified,
+        Badge::Sub,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Badge::Owner => "owner",
+            Badge::Staff => "staff",
+            Badge::Mod => "mod",
+            Badge::Verified => "verified",
+            Badge::Sub => "sub",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Badge> {
+        Badge::ALL
+            .into_iter()
+            .find(|badge| badge.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_on(&self, msg: &Message) -> bool {
+        match self {
+            Badge::Owner => msg.is_owner,
+            Badge::Staff => msg.is_staff,
+            Badge::Mod => msg.is_mod,
+            Badge::Verified => msg.is_verified,
+            Badge::Sub => msg.is_sub,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Platform {
+    /// Lowercase id used in asset paths, e.g. `youtube`.
+    pub id: &'static str,
+    /// Shown to viewers and sent by the userscripts, e.g. `YouTube`.
+    pub name: &'static str,
+    /// Other names messages may use.
+    #[serde(skip)]
+    pub aliases: &'static [&'static str],
+    /// Logo in `public/logo/`.
+    pub logo: &'static str,
+    /// Brand color, as a CSS hex color.
+    pub color: &'static str,
+    /// Currency donations are in when they don't say.
+    pub currency: &'static str,
+    /// Badges the platform gives chatters.
+    pub badges: &'static [Badge],
+}
+
+pub const PLATFORMS: &[Platform] = &[
+    Platform {
+        id: "kick",
+        name: "Kick",
+        aliases: &[],
+        logo: "kick.svg",
+        color: "#85c742",
+        currency: "USD",
+        badges: &Badge::ALL,
+    },
+    Platform {
+        id: "odysee",
+        name: "Odysee",
+        aliases: &[],
+        logo: "odysee.svg",
+        color: "#a60a43",
+        currency: "USD",
+        badges: &[Badge::Owner, Badge::Mod],
+    },
+    Platform {
+        id: "rumble",
+        name: "Rumble",
+        aliases: &[],
+        logo: "rumble.svg",
+        color: "#85c742",
+        currency: "USD",
+        badges: &Badge::ALL,
+    },
+    Platform {
+        id: "twitch",
+        name: "Twitch",
+        aliases: &[],
+        logo: "twitch.svg",
+        color: "#9146ff",
+        currency: "USD",
+        badges: &Badge::ALL,
+    },
+    Platform {
+        id: "vk",
+        name: "VK",
+        aliases: &["vkontakte"],
+        logo: "vk.svg",
+        color: "#ff2b42",
+        currency: "RUB",
+        badges: &[Badge::Owner, Badge::Staff, Badge::Mod, Badge::Sub],
+    },
+    Platform {
+        id: "x",
+        name: "X",
+        aliases: &["twitter"],
+        logo: "x.svg",
+        color: "#000000",
+        currency: "USD",
+        badges: &[Badge::Owner, Badge::Verified],
+    },
+    Platform {
+        id: "youtube",
+        name: "YouTube",
+        aliases: &["yt"],
+        logo: "youtube.svg",
+        color: "#ff0000",
+        currency: "USD",
+        badges: &Badge::ALL,
+    },
+    Platform {
+        id: "kofi",
+        name: "Ko-fi",
+        aliases: &["ko-fi"],
+        logo: "kofi.svg",
+        color: "#29abe0",
+        currency: "USD",
+        badges: &[],
+    },
+    Platform {
+        id: "patreon",
+        name: "Patreon",
+        aliases: &[],
+        logo: "patreon.svg",
+        color: "#ff424d",
+        currency: "USD",
+        badges: &[],
+    },
+    Platform {
+        id: "streamelements",
+        name: "StreamElements",
+        aliases: &[],
+        logo: "streamelements.svg",
+        color: "#181d3a",
+        currency: "USD",
+        badges: &[],
+    },
+    Platform {
+        id: "streamlabs",
+        name: "Streamlabs",
+        aliases: &[],
+        logo: "streamlabs.svg",
+        color: "#80f5d2",
+        currency: "USD",
+        badges: &[],
+    },
+    Platform {
+        id: "mail",
+        name: "Mail",
+        aliases: &["usps"],
+        logo: "United_States_Postal_Service_(emblem).png",
+        color: "#004b87",
+        currency: "USD",
+        badges: &[],
+    },
+];
+
+/// Stands in for any platform not in `PLATFORMS`.
+pub const UNKNOWN: Platform = Platform {
+    id: "unknown",
+    name: "Unknown",
+    aliases: &[],
+    logo: "unknown.svg",
+    color: "#808080",
+    currency: "USD",
+    badges: &Badge::ALL,
+};
+
+/// The platform called `name`, by id, name or alias.
+pub fn find(name: &str) -> Option<&'static Platform> {
+    let name = name.trim();
+    PLATFORMS.iter().find(|platform| {
+        platform.id.eq_ignore_ascii_case(name)
+            || platform.name.eq_ignore_ascii_case(name)
+            || platform
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+/// The platform called `name`, or `UNKNOWN`.
+pub fn get(name: &str) -> &'static Platform {
+    find(name).unwrap_or(&UNKNOWN)
+}
+
+impl Platform {
+    pub fn logo_url(&self) -> String {
+        format!("/logo/{}", self.logo)
+    }
+
+    pub fn badge_url(&self, badge: Badge) -> String {
+        format!("/badge/{}/{}.svg", self.id, badge.name())
+    }
+}
+
+/// Brand colors for `.msg--p-{platform}` messages, served as `platforms.css`.
+/// Setting `--{id}-brand` in a custom stylesheet replaces one.
+pub fn stylesheet() -> String {
+    let mut css = String::new();
+    for platform in PLATFORMS {
+        let mut names = vec![platform.name, platform.id];
+        names.extend(platform.aliases);
+        names.dedup();
+        let selectors: Vec<String> = names
+            .iter()
+            .map(|name| format!(".msg--p-{} .msg-avatar-border", name))
+            .collect();
+        css.push_str(&format!(
+            "{} {{\n    --brand-color: var(--{}-brand, {});\n}}\n\n",
+            selectors.join(",\n"),
+            platform.id,
+            platform.color
+        ));
+    }
+    css
+}
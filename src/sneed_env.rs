@@ -1,39 +1,93 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::Write;
+//! Environment variables that override `config.toml`.
+//!
+//! Any setting in a section can be set with `SNEED_<SECTION>_<KEY>`, e.g.
+//! `SNEED_SERVER_PORT=8080`. The variables older versions kept in `.env` still work,
+//! but are ignored when blank.
+//! Variables are also read from `.env` in the working directory.
 
-pub fn get_env() {
-    let env_map = HashMap::from([
-        ("RUST_DEBUG", "1"),
-        ("RUST_BACKTRACE", "1"),
-        ("RUST_LOG", "debug"),
-        ("SERVER_IP", "127.0.0.1"),
-        ("SERVER_PORT", "1350"),
-    ]);
+/// Variables from before `config.toml`, and the setting each one overrides.
+const LEGACY_VARS: &[(&str, &str, &str)] = &[
+    ("SERVER_IP", "server", "ip"),
+    ("SERVER_PORT", "server", "port"),
+    ("SLOW_CONSUMER_POLICY", "server", "slow_consumer"),
+    ("SLOW_CONSUMER_MAX_LAG", "server", "slow_consumer_max_lag"),
+    ("INGEST_TOKEN", "ingest", "token"),
+    (
+        "KOFI_VERIFICATION_TOKEN",
+        "webhooks",
+        "kofi_verification_token",
+    ),
+    ("PATREON_WEBHOOK_SECRET", "webhooks", "patreon_secret"),
+    ("PATREON_CURRENCY", "webhooks", "patreon_currency"),
+    (
+        "STREAMELEMENTS_WEBHOOK_SECRET",
+        "webhooks",
+        "streamelements_secret",
+    ),
+    ("STREAMLABS_WEBHOOK_SECRET", "webhooks", "streamlabs_secret"),
+];
 
-    let mut f = OpenOptions::new()
-        // Create .env if it doesn't exist.
-        .create(true)
-        // append(true) implies write(true) as of rust 1.8.0
-        .append(true)
-        .open(".env")
-        .expect("Failed to open .env file for writing.");
+/// Loads `.env` into the environment, if there is one.
+pub fn load_dotenv() {
+    match dotenvy::dotenv() {
+        Ok(path) => log::debug!("Loaded {}.", path.display()),
+        Err(err) if err.not_found() => (),
+        Err(err) => log::warn!("Failed to read .env: {}", err),
+    }
+}
+
+/// Parses `raw` as the same type as the value it replaces.
+fn parse_like(current: &toml::Value, var: &str, raw: &str) -> anyhow::Result<toml::Value> {
+    let invalid = |expected: &str| anyhow::anyhow!("{}={:?} is not {}.", var, raw, expected);
+    Ok(match current {
+        toml::Value::Integer(_) => {
+            toml::Value::Integer(raw.trim().parse().map_err(|_| invalid("a whole number"))?)
+        }
+        toml::Value::Float(_) => {
+            toml::Value::Float(raw.trim().parse().map_err(|_| invalid("a number"))?)
+        }
+        toml::Value::Boolean(_) => {
+            toml::Value::Boolean(raw.trim().parse().map_err(|_| invalid("true or false"))?)
+        }
+        toml::Value::String(_) => toml::Value::String(raw.to_owned()),
+        _ => anyhow::bail!("{} overrides a setting that is not a single value.", var),
+    })
+}
 
-    // Append any missing var definitions to .env file.
-    let mut modified = false;
-    for (k, v) in env_map.into_iter() {
-        match dotenvy::var(k) {
-            Ok(_) => (),
-            Err(_) => {
-                eprintln!(".env file is missing definition of {k}. Appending new default.");
-                writeln!(f, "{k}={v}").expect("Failed to write new pair to .env file.");
-                modified = true;
+/// Sets every overridden value in `table`, the config as parsed from the file.
+/// Returns the names of the variables that were applied.
+pub fn apply_overrides(table: &mut toml::Table) -> anyhow::Result<Vec<String>> {
+    let mut overrides: Vec<(String, String, String)> = LEGACY_VARS
+        .iter()
+        .map(|(var, section, key)| (var.to_string(), section.to_string(), key.to_string()))
+        .collect();
+    for (section, value) in table.iter() {
+        if let toml::Value::Table(settings) = value {
+            for key in settings.keys() {
+                let var = format!("SNEED_{}_{}", section, key).to_uppercase();
+                overrides.push((var, section.to_owned(), key.to_owned()));
             }
         }
     }
 
-    // Open .env again if modified above.
-    if modified {
-        dotenvy::dotenv().expect("Failed to re-read .env file");
+    let mut applied = Vec::new();
+    for (var, section, key) in overrides {
+        let Ok(raw) = std::env::var(&var) else {
+            continue;
+        };
+        // Old `.env` files list every variable, often blank.
+        if raw.is_empty() && !var.starts_with("SNEED_") {
+            continue;
+        }
+        let Some(current) = table
+            .get_mut(&section)
+            .and_then(|settings| settings.as_table_mut())
+            .and_then(|settings| settings.get_mut(&key))
+        else {
+            continue;
+        };
+        *current = parse_like(current, &var, &raw)?;
+        applied.push(var);
     }
+    Ok(applied)
 }
@@ -0,0 +1,233 @@
+//! Just enough IRCv3 to read Twitch chat.
+//! https://ircv3.net/specs/extensions/message-tags and https://dev.twitch.tv/docs/irc/tags/
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct IrcMessage<'a> {
+    pub tags: HashMap<&'a str, String>,
+    /// `nick!user@host` or a server name.
+    pub prefix: Option<&'a str>,
+    pub command: &'a str,
+    pub params: Vec<&'a str>,
+    /// The final parameter, which may contain spaces.
+    pub trailing: Option<&'a str>,
+}
+
+/// Reverses the escaping applied to tag values.
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => (),
+        }
+    }
+    out
+}
+
+impl<'a> IrcMessage<'a> {
+    /// Parses a single line, without its trailing CRLF.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+
+        let mut tags = HashMap::new();
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw, remainder) = stripped.split_once(' ')?;
+            for pair in raw.split(';') {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                tags.insert(key, unescape(value));
+            }
+            rest = remainder.trim_start();
+        }
+
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (raw, remainder) = stripped.split_once(' ')?;
+            prefix = Some(raw);
+            rest = remainder.trim_start();
+        }
+
+        let (rest, trailing) = match rest.split_once(" :") {
+            Some((rest, trailing)) => (rest, Some(trailing)),
+            None => (rest, None),
+        };
+
+        let mut words = rest.split_whitespace();
+        let command = words.next()?;
+
+        Some(Self {
+            tags,
+            prefix,
+            command,
+            params: words.collect(),
+            trailing,
+        })
+    }
+
+    /// A tag's value, if it is present and not empty.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Nickname from the prefix.
+    pub fn nick(&self) -> Option<&'a str> {
+        self.prefix?.split_once('!').map(|(nick, _)| nick)
+    }
+
+    /// Channel the message was sent to, without the leading `#`.
+    pub fn channel(&self) -> Option<&'a str> {
+        self.params
+            .first()
+            .and_then(|param| param.strip_prefix('#'))
+    }
+
+    /// Badge names and versions from the `badges` tag, e.g. `("subscriber", "12")`.
+    pub fn badges(&self) -> Vec<(&str, &str)> {
+        self.tag("badges")
+            .map(|badges| {
+                badges
+                    .split(',')
+                    .filter_map(|badge| badge.split_once('/'))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Emote ids and the char ranges they cover, from the `emotes` tag.
+    /// `25:0-4,12-16/1902:6-10` gives `("25", 0, 4)`, `("25", 12, 16)` and `("1902", 6, 10)`.
+    pub fn emotes(&self) -> Vec<(&str, usize, usize)> {
+        let mut emotes = Vec::new();
+        if let Some(tag) = self.tag("emotes") {
+            for emote in tag.split('/') {
+                let Some((id, ranges)) = emote.split_once(':') else {
+                    continue;
+                };
+                for range in ranges.split(',') {
+                    if let Some((start, end)) = range.split_once('-') {
+                        if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                            emotes.push((id, start, end));
+                        }
+                    }
+                }
+            }
+        }
+        emotes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../fixtures/twitch/chat.irc");
+
+    /// The fixture line with `command`, skipping the first `skip` of them.
+    fn line(command: &str, skip: usize) -> IrcMessage<'static> {
+        FIXTURE
+            .lines()
+            .filter_map(IrcMessage::parse)
+            .filter(|irc| irc.command == command)
+            .nth(skip)
+            .expect("Fixture has the line.")
+    }
+
+    #[test]
+    fn unescapes_tag_values() {
+        assert_eq!(unescape(r"a\sb\:c\\d\re\nf"), "a b;c\\d\re\nf");
+        // Unknown escapes drop the backslash, and a trailing one is dropped.
+        assert_eq!(unescape(r"\xy\"), "xy");
+
+        let irc = IrcMessage::parse(r"@system-msg=Six\smonths\:\sthanks;empty= PING").unwrap();
+        assert_eq!(irc.tag("system-msg"), Some("Six months; thanks"));
+        assert_eq!(irc.tag("empty"), None);
+        assert_eq!(irc.tag("missing"), None);
+    }
+
+    #[test]
+    fn parses_privmsg() {
+        let irc = line("PRIVMSG", 0);
+        assert_eq!(
+            irc.prefix,
+            Some("mockchannel!mockchannel@mockchannel.tmi.twitch.tv")
+        );
+        assert_eq!(irc.nick(), Some("mockchannel"));
+        assert_eq!(irc.channel(), Some("mockchannel"));
+        assert_eq!(irc.params, ["#mockchannel"]);
+        assert_eq!(irc.trailing, Some("Hello Kappa chat Kappa"));
+        assert_eq!(irc.tag("display-name"), Some("MockChannel"));
+        assert_eq!(irc.tag("user-id"), Some("1000"));
+    }
+
+    #[test]
+    fn parses_badges() {
+        assert_eq!(
+            line("PRIVMSG", 0).badges(),
+            [("broadcaster", "1"), ("subscriber", "12")]
+        );
+        assert_eq!(line("PRIVMSG", 1).badges(), [("moderator", "1")]);
+        assert!(line("PRIVMSG", 3).badges().is_empty());
+    }
+
+    #[test]
+    fn parses_emote_ranges() {
+        assert_eq!(line("PRIVMSG", 0).emotes(), [("25", 6, 10), ("25", 18, 22)]);
+
+        let irc =
+            IrcMessage::parse("@emotes=25:0-4,12-16/1902:6-10;id=x :a!a@a PRIVMSG #c :x").unwrap();
+        assert_eq!(
+            irc.emotes(),
+            [("25", 0, 4), ("25", 12, 16), ("1902", 6, 10)]
+        );
+
+        // Malformed ranges are skipped rather than guessed at.
+        let irc = IrcMessage::parse("@emotes=25:0-x,2-3/bad PRIVMSG #c :x").unwrap();
+        assert_eq!(irc.emotes(), [("25", 2, 3)]);
+    }
+
+    #[test]
+    fn parses_clearchat() {
+        let ban = line("CLEARCHAT", 0);
+        assert_eq!(ban.channel(), Some("mockchannel"));
+        assert_eq!(ban.tag("target-user-id"), Some("1004"));
+        assert_eq!(ban.trailing, Some("spammer"));
+        assert_eq!(ban.nick(), None);
+
+        let clear = IrcMessage::parse(":tmi.twitch.tv CLEARCHAT #mockchannel").unwrap();
+        assert_eq!(clear.command, "CLEARCHAT");
+        assert_eq!(clear.channel(), Some("mockchannel"));
+        assert_eq!(clear.trailing, None);
+        assert_eq!(clear.tag("target-user-id"), None);
+    }
+
+    #[test]
+    fn parses_clearmsg() {
+        let irc = line("CLEARMSG", 0);
+        assert_eq!(
+            irc.tag("target-msg-id"),
+            Some("6e9c2b3a-0b7a-4c1e-9a55-0f1d2c3b4a07")
+        );
+        assert_eq!(irc.tag("login"), Some("typo"));
+        assert_eq!(irc.trailing, Some("something I regret"));
+    }
+
+    #[test]
+    fn rejects_incomplete_lines() {
+        assert!(IrcMessage::parse("").is_none());
+        assert!(IrcMessage::parse("@tags-without-command").is_none());
+        assert!(IrcMessage::parse(":prefix-only").is_none());
+        assert_eq!(IrcMessage::parse("PING\r\n").unwrap().command, "PING");
+    }
+}
@@ -0,0 +1,466 @@
+//! Reads Twitch chat straight from its IRC websocket, without a browser tab running seed.js.
+
+mod irc;
+
+use actix::prelude::*;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+use uuid::Uuid;
+
+use self::irc::IrcMessage;
+use crate::config::ConnectorConfig;
+use crate::connector::{Connector, Reporter};
+use crate::message::{LivestreamUpdate, Message};
+use crate::web::{ChatServer, DeleteMessages, Deletion};
+
+const PLATFORM: &str = "Twitch";
+/// Same namespace as the Twitch seed in seed.js, so both give a message the same id.
+const TWITCH_NAMESPACE: Uuid = uuid::uuid!("4a342b79-e302-403a-99be-669b5f27b152");
+const EMOTE_URL: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+const IRC_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+
+pub struct TwitchChat {
+    server: Addr<ChatServer>,
+    reporter: Reporter,
+    url: String,
+    /// Lowercase channel name, without the leading `#`.
+    channel: String,
+    /// Anonymous login, which can read but not send.
+    nick: String,
+    /// Frames waiting to be written to the socket.
+    outbound: Option<mpsc::UnboundedSender<WsMessage>>,
+    /// Reader for the current socket.
+    stream: Option<SpawnHandle>,
+}
+
+impl Connector for TwitchChat {
+    const KIND: &'static str = "twitch";
+    const PLATFORM: &'static str = PLATFORM;
+
+    fn new(
+        config: &ConnectorConfig,
+        server: Addr<ChatServer>,
+        reporter: Reporter,
+    ) -> anyhow::Result<Self> {
+        let channel = config.channel.trim().trim_start_matches('#').to_lowercase();
+        if channel.is_empty() || channel.contains([' ', ',']) {
+            anyhow::bail!("Invalid Twitch channel {:?}.", config.channel);
+        }
+
+        Ok(Self {
+            server,
+            reporter,
+            url: config.url.to_owned().unwrap_or_else(|| IRC_URL.to_owned()),
+            channel,
+            nick: format!("justinfan{}", rand::random::<u32>() % 100_000),
+            outbound: None,
+            stream: None,
+        })
+    }
+}
+
+impl TwitchChat {
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        log::info!("Connecting to Twitch chat at {}.", self.url);
+        self.reporter.connecting();
+        tokio_tungstenite::connect_async(self.url.to_owned())
+            .into_actor(self)
+            .map(|res, act, ctx| match res {
+                Ok((socket, _)) => {
+                    let (sink, stream) = socket.split();
+                    let (tx, rx) = mpsc::unbounded();
+                    actix::spawn(async move {
+                        if let Err(err) = rx.map(Ok).forward(sink).await {
+                            log::warn!("Failed to write to Twitch chat: {}", err);
+                        }
+                    });
+                    act.outbound = Some(tx);
+                    act.stream = Some(ctx.add_stream(stream));
+                    act.login();
+                }
+                Err(err) => {
+                    act.reporter
+                        .error(format!("Failed to connect to {}: {}", act.url, err));
+                    ctx.stop();
+                }
+            })
+            .wait(ctx);
+    }
+
+    fn disconnect(&mut self, ctx: &mut Context<Self>) {
+        // Dropping the sender ends the writer, which closes the socket.
+        self.outbound = None;
+        if let Some(stream) = self.stream.take() {
+            ctx.cancel_future(stream);
+        }
+    }
+
+    fn login(&self) {
+        self.send("CAP REQ :twitch.tv/tags twitch.tv/commands".to_owned());
+        self.send("PASS SCHMOOPIIE".to_owned());
+        self.send(format!("NICK {}", self.nick));
+        self.send(format!("JOIN #{}", self.channel));
+    }
+
+    fn send(&self, line: String) {
+        if let Some(outbound) = &self.outbound {
+            if outbound.unbounded_send(WsMessage::Text(line)).is_err() {
+                log::warn!("Twitch chat writer has stopped.");
+            }
+        }
+    }
+
+    fn handle_irc(&mut self, irc: IrcMessage, ctx: &mut Context<Self>) {
+        match irc.command {
+            "PING" => self.send(format!("PONG :{}", irc.trailing.unwrap_or("tmi.twitch.tv"))),
+            "PRIVMSG" | "USERNOTICE" => {
+                if let Some(chat_message) = chat_message(&irc, &self.channel) {
+                    self.reporter.messages(1);
+                    self.server.do_send(LivestreamUpdate {
+                        platform: PLATFORM.to_owned(),
+                        channel: Some(self.channel.to_owned()),
+                        messages: Some(vec![chat_message]),
+                        viewers: None,
+                    });
+                }
+            }
+            "CLEARMSG" => {
+                if let Some(id) = irc.tag("target-msg-id") {
+                    self.server
+                        .do_send(DeleteMessages(Deletion::Message(message_id(Some(id)))));
+                }
+            }
+            "CLEARCHAT" => {
+                let deletion = match (irc.tag("target-user-id"), irc.trailing) {
+                    // Ban or timeout.
+                    (Some(user_id), _) => Deletion::User {
+                        platform: PLATFORM.to_owned(),
+                        channel: self.channel.to_owned(),
+                        user_id: user_id.to_owned(),
+                    },
+                    (None, Some(login)) => {
+                        log::warn!("Twitch chat cleared {} without a user id.", login);
+                        return;
+                    }
+                    // Chat cleared by a moderator.
+                    (None, None) => Deletion::Channel {
+                        platform: PLATFORM.to_owned(),
+                        channel: self.channel.to_owned(),
+                    },
+                };
+                self.server.do_send(DeleteMessages(deletion));
+            }
+            "JOIN" if irc.nick() == Some(self.nick.as_str()) => {
+                log::info!("Joined Twitch chat #{}.", irc.channel().unwrap_or_default());
+                self.reporter.connected();
+            }
+            "NOTICE" => log::warn!("Twitch chat notice: {}", irc.trailing.unwrap_or_default()),
+            "RECONNECT" => {
+                log::info!("Twitch chat asked us to reconnect.");
+                self.disconnect(ctx);
+                self.connect(ctx);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Id for a Twitch message, from its `id` tag.
+fn message_id(tag: Option<&str>) -> Uuid {
+    match tag {
+        Some(id) => Uuid::new_v5(&TWITCH_NAMESPACE, id.as_bytes()),
+        None => Uuid::new_v4(),
+    }
+}
+
+/// Converts a PRIVMSG or USERNOTICE read from `channel` into a chat message.
+fn chat_message(irc: &IrcMessage, channel: &str) -> Option<Message> {
+    let text = irc.trailing.unwrap_or_default();
+    // /me messages are wrapped in CTCP ACTION.
+    let text = text
+        .strip_prefix("\u{1}ACTION ")
+        .map(|text| text.trim_end_matches('\u{1}'))
+        .unwrap_or(text);
+
+    // Emote positions count chars, not bytes.
+    let chars: Vec<char> = text.chars().collect();
+    let mut emojis: Vec<(String, String, String)> = Vec::new();
+    for (id, start, end) in irc.emotes() {
+        let Some(name) = chars.get(start..=end) else {
+            continue;
+        };
+        let name: String = name.iter().collect();
+        if emojis.iter().any(|(find, _, _)| *find == name) {
+            continue;
+        }
+        let url = format!("{}/{}/default/dark/1.0", EMOTE_URL, id);
+        emojis.push((name.to_owned(), url, name));
+    }
+
+    let message = match (irc.command, irc.tag("system-msg")) {
+        // Subs, resubs, gifts and raids. The user's own message is optional.
+        ("USERNOTICE", Some(system)) if text.is_empty() => system.to_owned(),
+        ("USERNOTICE", Some(system)) => format!("{} {}", system, text),
+        (_, _) if text.is_empty() => return None,
+        (_, _) => text.to_owned(),
+    };
+
+    let username = irc
+        .tag("display-name")
+        .or(irc.tag("login"))
+        .or(irc.nick())?
+        .to_owned();
+
+    let badges = irc.badges();
+    let has_badge = |names: &[&str]| badges.iter().any(|(name, _)| names.contains(name));
+
+    let mut chat_message = Message {
+        id: message_id(irc.tag("id")),
+        platform: PLATFORM.to_owned(),
+        message,
+        emojis,
+        username,
+        user_id: irc.tag("user-id").map(str::to_owned),
+        channel: Some(channel.to_owned()),
+        color: irc.tag("color").map(str::to_owned),
+        is_verified: has_badge(&["partner"]),
+        is_sub: has_badge(&["subscriber", "founder"]) || irc.tag("subscriber") == Some("1"),
+        is_mod: has_badge(&["moderator"]) || irc.tag("mod") == Some("1"),
+        is_owner: has_badge(&["broadcaster"]),
+        is_staff: has_badge(&["staff", "admin", "global_mod"]),
+        ..Default::default()
+    };
+
+    if let Some(sent_at) = irc.tag("tmi-sent-ts").and_then(|ts| ts.parse().ok()) {
+        chat_message.sent_at = sent_at;
+    }
+
+    // Streamers receive a cent per bit.
+    if let Some(bits) = irc.tag("bits").and_then(|bits| bits.parse::<u64>().ok()) {
+        chat_message.amount = bits as f64 / 100.0;
+        chat_message.currency = "USD".to_owned();
+    }
+
+    Some(chat_message)
+}
+
+impl Actor for TwitchChat {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.connect(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.reporter.stopped();
+    }
+}
+
+impl StreamHandler<Result<WsMessage, tungstenite::Error>> for TwitchChat {
+    fn handle(&mut self, msg: Result<WsMessage, tungstenite::Error>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(WsMessage::Text(text)) => {
+                for line in text.lines() {
+                    match IrcMessage::parse(line) {
+                        Some(irc) => self.handle_irc(irc, ctx),
+                        None => log::debug!("Unparseable Twitch chat line: {:?}", line),
+                    }
+                }
+            }
+            Ok(WsMessage::Close(reason)) => {
+                log::info!("Twitch chat closed the connection: {:?}", reason);
+            }
+            Ok(_) => (),
+            Err(err) => self.reporter.error(err),
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        log::warn!("Disconnected from Twitch chat #{}.", self.channel);
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+#[path = "../../examples/mock_twitch_irc.rs"]
+#[allow(dead_code)]
+mod mock_twitch_irc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, ConnectorConfig};
+    use crate::connector::ConnectorSupervisor;
+    use crate::images::ImageCache;
+    use crate::web::RecentMessages;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const FIXTURE: &str = include_str!("../../fixtures/twitch/chat.irc");
+
+    /// The fixture's chat message from line `n`, counting from zero.
+    fn fixture_message(n: usize) -> Message {
+        let line = FIXTURE
+            .replace("\\u0001", "\u{1}")
+            .lines()
+            .nth(n)
+            .unwrap()
+            .to_owned();
+        chat_message(&IrcMessage::parse(&line).unwrap(), "mockchannel").unwrap()
+    }
+
+    #[test]
+    fn maps_privmsg() {
+        let message = fixture_message(0);
+        assert_eq!(
+            message.id,
+            message_id(Some("6e9c2b3a-0b7a-4c1e-9a55-0f1d2c3b4a01"))
+        );
+        assert_eq!(message.username, "MockChannel");
+        assert_eq!(message.user_id.as_deref(), Some("1000"));
+        assert_eq!(message.channel.as_deref(), Some("mockchannel"));
+        assert_eq!(message.message, "Hello Kappa chat Kappa");
+        assert_eq!(message.sent_at, 1710000000000);
+        assert!(message.is_owner && message.is_sub && !message.is_mod);
+        // Both uses of an emote share one entry.
+        assert_eq!(message.emojis.len(), 1);
+        assert_eq!(message.emojis[0].0, "Kappa");
+        assert_eq!(
+            message.emojis[0].1,
+            format!("{}/25/default/dark/1.0", EMOTE_URL)
+        );
+    }
+
+    #[test]
+    fn maps_emotes_after_multibyte_text() {
+        let line = "@emotes=25:8-12;user-id=7 :a!a@a.tmi.twitch.tv PRIVMSG #c :héllo 🙂 Kappa";
+        let message = chat_message(&IrcMessage::parse(line).unwrap(), "c").unwrap();
+        assert_eq!(message.emojis.len(), 1);
+        assert_eq!(message.emojis[0].0, "Kappa");
+
+        // Ranges past the end of the text are ignored.
+        let line = "@emotes=25:8-40 :a!a@a.tmi.twitch.tv PRIVMSG #c :héllo 🙂 Kappa";
+        let message = chat_message(&IrcMessage::parse(line).unwrap(), "c").unwrap();
+        assert!(message.emojis.is_empty());
+    }
+
+    #[test]
+    fn strips_action() {
+        let message = fixture_message(1);
+        assert_eq!(message.message, "waves at everyone");
+        assert!(message.is_mod);
+    }
+
+    #[test]
+    fn maps_bits_to_cents() {
+        let message = fixture_message(2);
+        assert_eq!(message.amount, 5.0);
+        assert_eq!(message.currency, "USD");
+    }
+
+    #[test]
+    fn prefixes_usernotice_with_system_message() {
+        let message = fixture_message(3);
+        assert_eq!(
+            message.message,
+            "Resubber subscribed at Tier 1. They've subscribed for 6 months! Six months already"
+        );
+        assert_eq!(message.user_id.as_deref(), Some("1003"));
+        assert!(message.is_sub);
+    }
+
+    /// Replays the fixture from the mock IRC server through a connector into a chat server.
+    /// The ban must remove the spammer's messages from this channel only, and the CLEARMSG the typo.
+    #[actix_web::test]
+    async fn replays_mock_chat() {
+        config::init_for_tests();
+        let config = config::get();
+        let rates = config.paths.data_file("rates.xml");
+        std::fs::write(&rates, r#"<Cube><Cube currency="USD" rate="1.08"/></Cube>"#).unwrap();
+        let server = ChatServer::new(
+            crate::exchange::load_backup(&rates).unwrap(),
+            &config,
+            Arc::new(ImageCache::new()),
+        )
+        .start();
+
+        // The same spammer, seen by a browser feeder and in another channel.
+        let spammer = |channel: &str| LivestreamUpdate {
+            platform: PLATFORM.to_owned(),
+            channel: Some(channel.to_owned()),
+            messages: Some(vec![Message {
+                platform: PLATFORM.to_owned(),
+                message: "followers".to_owned(),
+                username: "Spammer".to_owned(),
+                ..Default::default()
+            }]),
+            viewers: None,
+        };
+        server.send(spammer("mockchannel")).await.unwrap();
+        server.send(spammer("otherchannel")).await.unwrap();
+
+        // Paid messages are never deleted, so the cheer is left out. A last line
+        // marks the end, as the server handles the connector's messages in order.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let fixture: String = FIXTURE
+            .replace("\\u0001", "\u{1}")
+            .lines()
+            .filter(|line| !line.contains("bits="))
+            .chain(["@id=end;user-id=1 :done!done@done.tmi.twitch.tv PRIVMSG #mockchannel :done"])
+            .map(|line| format!("{}\n", line))
+            .collect();
+        actix_web::rt::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            mock_twitch_irc::serve(stream, &fixture, Duration::from_millis(1))
+                .await
+                .unwrap();
+        });
+
+        let connector = ConnectorConfig {
+            kind: TwitchChat::KIND.to_owned(),
+            channel: "#MockChannel".to_owned(),
+            enabled: true,
+            url: Some(url),
+        };
+        let supervisor = ConnectorSupervisor::new(server.clone()).start();
+        let reporter = Reporter::new(
+            TwitchChat::KIND,
+            PLATFORM,
+            &connector.channel,
+            supervisor.recipient(),
+        );
+        let _twitch = TwitchChat::new(&connector, server.clone(), reporter)
+            .unwrap()
+            .start();
+
+        let end = message_id(Some("end"));
+        let mut messages = Vec::new();
+        for _ in 0..500 {
+            messages = server.send(RecentMessages).await.unwrap();
+            if messages.iter().any(|msg| msg.id == end) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut names: Vec<&str> = messages.iter().map(|msg| msg.username.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "MockChannel",
+                "ModPerson",
+                "Resubber",
+                "Spammer",
+                "Spammer",
+                "done"
+            ]
+        );
+        assert!(messages
+            .iter()
+            .filter(|msg| msg.username == "Spammer")
+            .all(|msg| msg.user_id.is_none()));
+    }
+}
@@ -0,0 +1,154 @@
+//! Viewer counts sampled over the course of each session, for reports and charts.
+
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+use crate::config;
+
+const VIEWER_LOG: &str = "viewers.jsonl";
+/// How often viewer counts are sampled while the stream is live.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a viewer count is kept without an update, for feeders and connectors
+/// that stop sending counts without disconnecting.
+pub const MAX_AGE: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelViewers {
+    pub platform: String,
+    /// Empty if the feeder did not say which channel it was watching.
+    pub channel: String,
+    pub viewers: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewerSample {
+    pub session: String,
+    /// Unix timestamp in milliseconds.
+    pub at: i64,
+    /// Viewers across every channel.
+    pub total: usize,
+    pub channels: Vec<ChannelViewers>,
+}
+
+pub fn append(sample: &ViewerSample) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::get().paths.data_file(VIEWER_LOG))?;
+    writeln!(f, "{}", serde_json::to_string(sample)?)
+}
+
+/// Samples taken during `session`, oldest first. Unreadable lines are skipped.
+pub fn samples(session: &str) -> Vec<ViewerSample> {
+    let f = match std::fs::File::open(config::get().paths.data_file(VIEWER_LOG)) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+
+    BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str::<ViewerSample>(&line) {
+            Ok(sample) => Some(sample),
+            Err(err) => {
+                log::warn!("Skipping unreadable viewer sample: {:?}", err);
+                None
+            }
+        })
+        .filter(|sample| sample.session == session)
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChannelReport {
+    pub platform: String,
+    pub channel: String,
+    pub peak: usize,
+    /// Unix timestamp in milliseconds.
+    pub peak_at: i64,
+    /// Mean over the samples the channel appears in.
+    pub average: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ViewerReport {
+    pub session: String,
+    pub samples: usize,
+    /// Unix timestamp in milliseconds.
+    pub first_at: Option<i64>,
+    /// Unix timestamp in milliseconds.
+    pub last_at: Option<i64>,
+    pub peak: usize,
+    /// Unix timestamp in milliseconds.
+    pub peak_at: Option<i64>,
+    /// Mean of the total over every sample.
+    pub average: f64,
+    pub channels: Vec<ChannelReport>,
+}
+
+/// Peak and average viewers for a session, overall and per channel.
+pub fn report(session: &str, samples: &[ViewerSample]) -> ViewerReport {
+    // The first sample to reach the peak.
+    let peak = samples
+        .iter()
+        .min_by_key(|sample| std::cmp::Reverse(sample.total));
+    let mut channels: Vec<(ChannelReport, usize)> = Vec::new();
+
+    for sample in samples {
+        for viewers in &sample.channels {
+            let found = channels.iter_mut().find(|(report, _)| {
+                report.platform == viewers.platform && report.channel == viewers.channel
+            });
+            match found {
+                Some((report, count)) => {
+                    if viewers.viewers > report.peak {
+                        report.peak = viewers.viewers;
+                        report.peak_at = sample.at;
+                    }
+                    report.average += viewers.viewers as f64;
+                    *count += 1;
+                }
+                None => channels.push((
+                    ChannelReport {
+                        platform: viewers.platform.to_owned(),
+                        channel: viewers.channel.to_owned(),
+                        peak: viewers.viewers,
+                        peak_at: sample.at,
+                        average: viewers.viewers as f64,
+                    },
+                    1,
+                )),
+            }
+        }
+    }
+
+    let mut channels: Vec<ChannelReport> = channels
+        .into_iter()
+        .map(|(mut report, count)| {
+            report.average /= count as f64;
+            report
+        })
+        .collect();
+    channels.sort_by_key(|report| std::cmp::Reverse(report.peak));
+
+    ViewerReport {
+        session: session.to_owned(),
+        samples: samples.len(),
+        first_at: samples.first().map(|sample| sample.at),
+        last_at: samples.last().map(|sample| sample.at),
+        peak: peak.map_or(0, |sample| sample.total),
+        peak_at: peak.map(|sample| sample.at),
+        average: if samples.is_empty() {
+            0.0
+        } else {
+            samples
+                .iter()
+                .map(|sample| sample.total as f64)
+                .sum::<f64>()
+                / samples.len() as f64
+        },
+        channels,
+    }
+}
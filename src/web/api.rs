@@ -0,0 +1,252 @@
+//! JSON API under `/api/v1`, described by `openapi.json`.
+
+use actix::{Addr, MailboxError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Scope};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use super::donation;
+use super::emote;
+use super::ingest;
+use super::marker;
+use super::message;
+use super::webhook;
+use super::ChatServer;
+use crate::connector::{ConnectorSupervisor, ListConnectors};
+use crate::platform::{self, Platform};
+use crate::viewers;
+
+const OPENAPI: &str = include_str!("openapi.json");
+
+/// Body of every API error.
+#[derive(Serialize)]
+pub struct ApiError {
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn response(code: StatusCode, error: impl Into<String>) -> HttpResponse {
+        HttpResponse::build(code).json(ApiError {
+            error: error.into(),
+        })
+    }
+
+    /// The chat server didn't answer.
+    pub fn unavailable(err: MailboxError) -> HttpResponse {
+        Self::response(StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+    }
+}
+
+pub fn scope() -> Scope {
+    let scope = donation::services(web::scope("/api/v1"));
+    let scope = emote::services(scope);
+    marker::services(scope)
+        .service(openapi)
+        .service(list_messages)
+        .service(get_message)
+        .service(list_superchats)
+        .service(get_viewers)
+        .service(get_channel_viewers)
+        .service(get_viewer_history)
+        .service(get_viewer_report)
+        .service(get_analytics)
+        .service(get_connectors)
+        .service(get_sources)
+        .service(get_status)
+        .service(list_platforms)
+        .service(get_platform)
+        .service(reload_config)
+        .service(ingest::resource())
+        .service(webhook::resource())
+}
+
+pub fn chat_server(req: &HttpRequest) -> Addr<ChatServer> {
+    req.app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone()
+}
+
+/// Outcome of a config reload that was applied.
+#[derive(Serialize)]
+struct ConfigReload {
+    warnings: Vec<String>,
+    /// Settings that changed but only apply after a restart.
+    needs_restart: Vec<&'static str>,
+}
+
+#[post("/config/reload")]
+async fn reload_config(req: HttpRequest) -> impl Responder {
+    match super::reload_config(&chat_server(&req)).await {
+        Ok(reloaded) => HttpResponse::Ok().json(ConfigReload {
+            warnings: reloaded
+                .warnings
+                .into_iter()
+                .map(|problem| problem.message)
+                .collect(),
+            needs_restart: reloaded.needs_restart,
+        }),
+        Err(err) => {
+            log::error!("{:#}", err);
+            ApiError::response(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", err))
+        }
+    }
+}
+
+#[get("/openapi.json")]
+async fn openapi() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .body(OPENAPI)
+}
+
+#[get("/messages")]
+async fn list_messages(
+    req: HttpRequest,
+    query: web::Query<message::QueryMessages>,
+) -> impl Responder {
+    match chat_server(&req).send(query.into_inner()).await {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/messages/{id}")]
+async fn get_message(req: HttpRequest, id: web::Path<Uuid>) -> impl Responder {
+    match chat_server(&req)
+        .send(message::GetMessage(id.into_inner()))
+        .await
+    {
+        Ok(Some(msg)) => HttpResponse::Ok().json(msg),
+        Ok(None) => ApiError::response(StatusCode::NOT_FOUND, "No such message."),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/superchats")]
+async fn list_superchats(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::PaidMessages).await {
+        Ok(super_chats) => HttpResponse::Ok().json(super_chats),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/viewers")]
+async fn get_viewers(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::ViewerCounts).await {
+        Ok(viewers) => HttpResponse::Ok().json(viewers),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/viewers/channels")]
+async fn get_channel_viewers(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::ChannelViewerCounts).await {
+        Ok(viewers) => HttpResponse::Ok().json(viewers),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    /// Defaults to the current session.
+    session: Option<String>,
+}
+
+/// The session asked for, or the current one.
+pub async fn session(req: &HttpRequest, session: Option<String>) -> Result<String, MailboxError> {
+    match session {
+        Some(session) => Ok(session),
+        None => chat_server(req).send(message::CurrentSession).await,
+    }
+}
+
+#[get("/viewers/history")]
+async fn get_viewer_history(req: HttpRequest, query: web::Query<SessionQuery>) -> HttpResponse {
+    match session(&req, query.into_inner().session).await {
+        Ok(session) => HttpResponse::Ok().json(viewers::samples(&session)),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/viewers/report")]
+async fn get_viewer_report(req: HttpRequest, query: web::Query<SessionQuery>) -> HttpResponse {
+    match session(&req, query.into_inner().session).await {
+        Ok(session) => {
+            let samples = viewers::samples(&session);
+            HttpResponse::Ok().json(viewers::report(&session, &samples))
+        }
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/analytics")]
+async fn get_analytics(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::ChatAnalytics).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/sources")]
+async fn get_sources(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::ListSources).await {
+        Ok(sources) => HttpResponse::Ok().json(sources),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/connectors")]
+async fn get_connectors(req: HttpRequest) -> impl Responder {
+    let connectors = req
+        .app_data::<Addr<ConnectorSupervisor>>()
+        .expect("ConnectorSupervisor missing in app data!");
+    match connectors.send(ListConnectors).await {
+        Ok(connectors) => HttpResponse::Ok().json(connectors),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/status")]
+async fn get_status(req: HttpRequest) -> impl Responder {
+    match chat_server(&req).send(message::Status).await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+/// A platform with the URLs of its assets.
+#[derive(Serialize)]
+struct PlatformInfo {
+    #[serde(flatten)]
+    platform: &'static Platform,
+    logo_url: String,
+    badge_urls: BTreeMap<&'static str, String>,
+}
+
+impl From<&'static Platform> for PlatformInfo {
+    fn from(platform: &'static Platform) -> Self {
+        Self {
+            platform,
+            logo_url: platform.logo_url(),
+            badge_urls: platform
+                .badges
+                .iter()
+                .map(|badge| (badge.name(), platform.badge_url(*badge)))
+                .collect(),
+        }
+    }
+}
+
+#[get("/platforms")]
+async fn list_platforms() -> impl Responder {
+    let platforms: Vec<PlatformInfo> = platform::PLATFORMS.iter().map(PlatformInfo::from).collect();
+    HttpResponse::Ok().json(platforms)
+}
+
+/// Any name resolves, to the `unknown` platform if nothing else.
+#[get("/platforms/{platform}")]
+async fn get_platform(name: web::Path<String>) -> impl Responder {
+    HttpResponse::Ok().json(PlatformInfo::from(platform::get(&name)))
+}
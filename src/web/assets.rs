@@ -0,0 +1,146 @@
+//! Scripts, stylesheets and logos served to overlays and the dashboard.
+//!
+//! `public/` is embedded in release builds, so the server can run from anywhere. Debug
+//! builds read it from the source tree on every request instead, so edits show up on
+//! reload. A file in `paths.overrides` takes the place of the one in `public/`.
+
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use rust_embed::RustEmbed;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+
+#[derive(RustEmbed)]
+#[folder = "public/"]
+struct Public;
+
+/// A file ready to be served.
+pub struct Asset {
+    pub body: Cow<'static, [u8]>,
+    pub content_type: &'static str,
+    pub etag: EntityTag,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Whether `path` is a plain relative path that can't leave the directory.
+fn is_safe(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Whether `path` is in `overrides` or `public/`.
+pub fn exists(overrides: &Path, path: &str) -> bool {
+    is_safe(path) && (overrides.join(path).is_file() || Public::get(path).is_some())
+}
+
+/// `path` relative to `public/`, from the override directory if it is there.
+pub fn get(path: &str) -> Option<Asset> {
+    if !is_safe(path) {
+        return None;
+    }
+    let content_type = content_type(path);
+
+    let overridden = config::get().paths.overrides.join(path);
+    if let Ok(body) = std::fs::read(&overridden) {
+        let last_modified = std::fs::metadata(&overridden)
+            .and_then(|meta| meta.modified())
+            .ok();
+        return Some(Asset {
+            etag: etag(&Sha256::digest(&body)),
+            body: Cow::Owned(body),
+            content_type,
+            last_modified,
+        });
+    }
+
+    let file = Public::get(path)?;
+    Some(Asset {
+        etag: etag(&file.metadata.sha256_hash()),
+        last_modified: file
+            .metadata
+            .last_modified()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        body: file.data,
+        content_type,
+    })
+}
+
+fn etag(hash: &[u8]) -> EntityTag {
+    EntityTag::new_strong(hex::encode(&hash[..16]))
+}
+
+fn content_type(path: &str) -> &'static str {
+    let ext = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    match ext.to_ascii_lowercase().as_str() {
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "html" => "text/html; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether the client's copy, as described by its conditional headers, is current.
+fn is_fresh(req: &HttpRequest, asset: &Asset) -> bool {
+    // If-Modified-Since only counts without If-None-Match.
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(&asset.etag)),
+        };
+    }
+    match (req.get_header::<IfModifiedSince>(), asset.last_modified) {
+        (Some(IfModifiedSince(since)), Some(modified)) => {
+            // HTTP dates have whole seconds.
+            let modified = modified
+                .duration_since(UNIX_EPOCH)
+                .map(|d| UNIX_EPOCH + Duration::from_secs(d.as_secs()))
+                .unwrap_or(modified);
+            modified <= SystemTime::from(since)
+        }
+        _ => false,
+    }
+}
+
+/// Responds with `path`, or 304 if the client already has it.
+pub fn serve(req: &HttpRequest, path: &str) -> HttpResponse {
+    let Some(asset) = get(path) else {
+        return HttpResponse::NotFound().body(format!("{} not found", path));
+    };
+
+    let fresh = is_fresh(req, &asset);
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    // Files can change on disk or with an override, so always check back.
+    response
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .insert_header(header::ETag(asset.etag));
+    if let Some(modified) = asset.last_modified {
+        response.insert_header(header::LastModified(HttpDate::from(modified)));
+    }
+    if fresh {
+        return response.finish();
+    }
+    response
+        .insert_header((header::CONTENT_TYPE, asset.content_type))
+        .body(asset.body.into_owned())
+}
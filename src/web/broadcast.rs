@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use super::message::ReplyInner;
+use crate::config::ServerConfig;
+use crate::message::Message as ChatMessage;
+use crate::platform::Badge;
+
+/// Facts about a chat message, kept so events can be filtered without parsing them.
+#[derive(Debug, Clone)]
+pub struct MessageMeta {
+    pub platform: String,
+    /// Paid tier, if the message is premium.
+    pub paid_tier: Option<u8>,
+    /// Badges the author has.
+    pub badges: Vec<Badge>,
+    /// Message text as sent, in lowercase, for keyword filters.
+    pub text: String,
+}
+
+impl From<&ChatMessage> for MessageMeta {
+    fn from(msg: &ChatMessage) -> Self {
+        Self {
+            platform: msg.platform.to_owned(),
+            paid_tier: msg.is_premium().then(|| msg.get_paid_tier()),
+            badges: msg.badges(),
+            text: msg.text.to_lowercase(),
+        }
+    }
+}
+
+/// A broadcast event, serialized once and shared by every recipient.
+#[derive(Debug)]
+pub struct Event {
+    pub seq: u64,
+    pub tag: String,
+    /// Present on events carrying a chat message.
+    pub meta: Option<MessageMeta>,
+    /// Serialized `ReplyInner`, sent to clients as is.
+    pub payload: String,
+}
+
+impl Event {
+    pub fn new(seq: u64, tag: &str, message: String) -> Self {
+        let payload = serde_json::to_string(&ReplyInner {
+            seq: Some(seq),
+            tag: tag.to_owned(),
+            message,
+            highlight: false,
+        })
+        .expect("Failed to serialize ReplyInner.");
+
+        Self {
+            seq,
+            tag: tag.to_owned(),
+            meta: None,
+            payload,
+        }
+    }
+
+    /// This event marked as highlighted for one client, with its own payload.
+    pub fn highlighted(&self) -> Self {
+        let mut reply: ReplyInner =
+            serde_json::from_str(&self.payload).expect("Event payload is a ReplyInner.");
+        reply.highlight = true;
+        Self {
+            seq: self.seq,
+            tag: self.tag.to_owned(),
+            meta: self.meta.clone(),
+            payload: serde_json::to_string(&reply).expect("Failed to serialize ReplyInner."),
+        }
+    }
+
+    /// An event carrying a chat message, such as `chat_message` or `chat_message_edit`.
+    pub fn for_message(seq: u64, tag: &str, msg: &ChatMessage) -> Self {
+        Self {
+            meta: Some(MessageMeta::from(msg)),
+            ..Self::new(seq, tag, msg.to_json())
+        }
+    }
+}
+
+/// What to do with a client whose mailbox is full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowConsumerPolicy {
+    /// Skip events for the client until it catches up.
+    Drop,
+    /// Disconnect the client once it has missed `max_lag` events in a row.
+    Disconnect,
+}
+
+/// How the chat server treats clients that can't keep up.
+#[derive(Debug, Clone, Copy)]
+pub struct BroadcastPolicy {
+    pub slow_consumer: SlowConsumerPolicy,
+    /// Consecutive events a client may miss before the policy kicks in.
+    pub max_lag: usize,
+}
+
+impl BroadcastPolicy {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            slow_consumer: config.slow_consumer,
+            max_lag: config.slow_consumer_max_lag,
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use actix::*;
 use actix_web_actors::ws;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use super::filter::{EventFilter, FilterCommand};
+use super::limit::{ClientLimits, Violation, MAX_MESSAGES_PER_UPDATE};
 use super::message;
 use super::ChatMessage;
 use super::ChatServer;
-use super::CLIENT_TIMEOUT;
-use super::HEARTBEAT_INTERVAL;
-use crate::message::{CommandFeatureMessage, LivestreamUpdate};
+use crate::config;
+use crate::connector::{ConnectorSupervisor, Reporter};
+use crate::message::{CommandFeatureMessage, CommandResume, LivestreamUpdate};
 
 pub struct ChatClient {
     /// Connection ID
@@ -15,10 +17,20 @@ pub struct ChatClient {
     /// Chat server
     pub server: Addr<ChatServer>,
     /// Last Heartbeat
-    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT), otherwise we drop connection.
+    /// Client must send ping at least once per `client_timeout` seconds, otherwise we drop connection.
     pub last_heartbeat_at: Instant,
-    /// Last command (any) sent
-    pub last_command_at: Instant,
+    /// Flood protection for frames, chat messages and commands.
+    pub limits: ClientLimits,
+    /// Last sequence number seen before reconnecting, if any.
+    pub since: Option<u64>,
+    /// Recent chat messages to send on connect, if any.
+    pub backlog: Option<usize>,
+    /// Events to send, from the query string until the client subscribes.
+    pub filter: EventFilter,
+    /// Where browser feeders report their health.
+    pub connectors: Addr<ConnectorSupervisor>,
+    /// Set once this client sends its first livestream update, marking it as a feeder.
+    pub reporter: Option<Reporter>,
 }
 
 impl ChatClient {
@@ -26,23 +38,28 @@ impl ChatClient {
     ///
     /// also this method checks heartbeats from client
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            // check client heartbeats
-            if Instant::now().duration_since(act.last_heartbeat_at) > CLIENT_TIMEOUT {
-                // heartbeat timed out
+        let server = &config::get().server;
+        let timeout = Duration::from_secs(server.client_timeout);
+        ctx.run_interval(
+            Duration::from_secs(server.heartbeat_interval),
+            move |act, ctx| {
+                // check client heartbeats
+                if Instant::now().duration_since(act.last_heartbeat_at) > timeout {
+                    // heartbeat timed out
 
-                // notify chat server
-                act.send_or_reply(ctx, message::Disconnect { id: act.id });
+                    // notify chat server
+                    act.send_or_reply(ctx, message::Disconnect { id: act.id });
 
-                // stop actor
-                ctx.stop();
+                    // stop actor
+                    ctx.stop();
 
-                // don't try to send a ping
-                return;
-            }
+                    // don't try to send a ping
+                    return;
+                }
 
-            ctx.ping(b"");
-        });
+                ctx.ping(b"");
+            },
+        );
     }
 
     /// Try to send message
@@ -60,6 +77,96 @@ impl ChatClient {
         }
     }
 
+    /// Tells the client why its frame was refused, and drops it once it has used up
+    /// its strikes. Callers stop handling the frame either way.
+    fn refuse(&mut self, ctx: &mut ws::WebsocketContext<Self>, violation: Violation) {
+        let banned = self.limits.strike();
+        log::warn!(
+            "Client {} violated {} (strike {}).",
+            self.id,
+            violation.code(),
+            self.limits.strikes
+        );
+
+        ctx.text(
+            serde_json::to_string(&message::ReplyInner {
+                seq: None,
+                tag: "error".to_owned(),
+                message: serde_json::to_string(&message::ErrorReply {
+                    code: violation.code().to_owned(),
+                    reason: violation.reason(),
+                    strikes: self.limits.strikes,
+                })
+                .expect("Failed to serialize error reply."),
+                highlight: false,
+            })
+            .expect("Failed to serialize error ReplyInner."),
+        );
+
+        if banned {
+            log::warn!("Client {} dropped for flooding.", self.id);
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: Some("Rate limit exceeded.".to_owned()),
+            }));
+            ctx.stop();
+        }
+    }
+
+    /// Handles any text frame that isn't a livestream update.
+    fn handle_command(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.limits.commands.try_take(1.0) {
+            self.refuse(ctx, Violation::Commands);
+            return;
+        }
+
+        // Checked first, as any object without a feature_message would unfeature.
+        if let Ok(command) = serde_json::from_str::<CommandResume>(text) {
+            self.send_or_reply(
+                ctx,
+                message::Resume {
+                    id: self.id,
+                    since: command.resume,
+                },
+            );
+            return;
+        }
+        // Also ahead of feature_message, even when the filter in it is invalid.
+        let subscribe = serde_json::from_str::<serde_json::Value>(text)
+            .ok()
+            .and_then(|mut command| command.get_mut("subscribe").map(serde_json::Value::take));
+        if let Some(subscribe) = subscribe {
+            match serde_json::from_value::<FilterCommand>(subscribe) {
+                Ok(filter) => {
+                    self.filter = EventFilter::from(filter);
+                    self.send_or_reply(
+                        ctx,
+                        message::Subscribe {
+                            id: self.id,
+                            filter: self.filter.clone(),
+                        },
+                    );
+                }
+                Err(err) => log::warn!("Invalid subscribe command: {}", err),
+            }
+            return;
+        }
+
+        match serde_json::from_str::<CommandFeatureMessage>(text) {
+            Ok(message) => {
+                self.send_or_reply(
+                    ctx,
+                    message::FeatureMessage {
+                        id: message.feature_message,
+                    },
+                );
+            }
+            Err(err) => {
+                log::warn!("Error parsing client message: {:?}", err);
+            }
+        };
+    }
+
     fn start_heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
         // start heartbeat process on session start.
         self.heartbeat(ctx);
@@ -72,11 +179,20 @@ impl ChatClient {
         self.server
             .send(message::Connect {
                 recipient: ctx.address().recipient(),
+                evict: ctx.address().recipient(),
+                since: self.since,
+                backlog: self.backlog,
+                filter: self.filter.clone(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => {
+                        act.id = res.id;
+                        for reply in res.catch_up {
+                            ctx.text(reply.0.payload.as_str());
+                        }
+                    }
                     Err(err) => {
                         // something is wrong with chat server
                         log::warn!("Failed to assign conection id: {:?}", err);
@@ -95,12 +211,16 @@ impl Actor for ChatClient {
     /// Method is called on actor start.
     /// We register ws session with ChatServer
     fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(config::get().server.client_mailbox);
         self.start_heartbeat(ctx);
     }
 
     fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
         // notify chat server
         self.send_or_reply(ctx, message::Disconnect { id: self.id });
+        if let Some(reporter) = &self.reporter {
+            reporter.stopped();
+        }
         Running::Stop
     }
 }
@@ -110,7 +230,20 @@ impl Handler<message::Reply> for ChatClient {
     type Result = ();
 
     fn handle(&mut self, msg: message::Reply, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+        ctx.text(msg.0.payload.as_str());
+    }
+}
+
+/// Handle being dropped by the chat server for falling behind.
+impl Handler<message::Evict> for ChatClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Evict, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Again,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
     }
 }
 
@@ -134,15 +267,54 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatClient {
                 self.last_heartbeat_at = Instant::now();
             }
             ws::Message::Text(text) => {
+                if !self.limits.frames.try_take(1.0) {
+                    self.refuse(ctx, Violation::Frames);
+                    return;
+                }
+
                 match serde_json::from_str::<LivestreamUpdate>(&text) {
                     Ok(update) => {
+                        // Check the whole update before forwarding any of it.
+                        if let Some(messages) = &update.messages {
+                            if messages.len() > MAX_MESSAGES_PER_UPDATE {
+                                self.refuse(ctx, Violation::OversizedUpdate);
+                                return;
+                            }
+                            if !self.limits.messages.try_take(messages.len() as f64) {
+                                self.refuse(ctx, Violation::Messages);
+                                return;
+                            }
+                        }
+
+                        let reporter = self.reporter.get_or_insert_with(|| {
+                            let reporter = Reporter::new(
+                                "browser",
+                                &update.platform,
+                                update.channel.as_deref().unwrap_or_default(),
+                                self.connectors.clone().recipient(),
+                            );
+                            reporter.connected();
+                            reporter
+                        });
+                        let count = update.messages.as_ref().map_or(0, Vec::len);
+                        reporter.messages(count);
+                        self.send_or_reply(
+                            ctx,
+                            message::Activity {
+                                feeder: Some(self.id),
+                                platform: update.platform.to_owned(),
+                                channel: update.channel.to_owned(),
+                                messages: count,
+                            },
+                        );
+
                         // Send Viewer Counts
                         if let Some(viewers) = update.viewers {
                             self.send_or_reply(
                                 ctx,
                                 message::ViewCount {
                                     platform: update.platform,
-                                    //channel: update.channel.unwrap_or_default(),
+                                    channel: update.channel,
                                     viewers,
                                 },
                             );
@@ -159,24 +331,16 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatClient {
                             }
                         }
                     }
-                    Err(_) => {
-                        match serde_json::from_str::<CommandFeatureMessage>(&text) {
-                            Ok(message) => {
-                                self.send_or_reply(
-                                    ctx,
-                                    message::FeatureMessage {
-                                        id: message.feature_message,
-                                    },
-                                );
-                            }
-                            Err(err) => {
-                                log::warn!("Error parsing client message: {:?}", err);
-                            }
-                        };
-                    }
+                    Err(_) => self.handle_command(&text, ctx),
                 };
             }
-            ws::Message::Binary(_) => log::warn!("Unexpected ChatClient binary."),
+            ws::Message::Binary(_) => {
+                if !self.limits.frames.try_take(1.0) {
+                    self.refuse(ctx, Violation::Frames);
+                    return;
+                }
+                log::warn!("Unexpected ChatClient binary.");
+            }
             ws::Message::Close(reason) => {
                 log::debug!("Client {} disconnecting with reason: {:?}", self.id, reason);
                 ctx.close(reason);
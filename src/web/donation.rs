@@ -0,0 +1,136 @@
+//! Manual donations entered on the dashboard, under `/api/v1/donations`.
+
+use actix_web::http::StatusCode;
+use actix_web::{get, patch, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::api::{chat_server, ApiError};
+use super::message::{self, DonationError};
+use crate::audit;
+use crate::ledger::{self, Export, LedgerFilter};
+
+pub fn services(scope: actix_web::Scope) -> actix_web::Scope {
+    scope
+        .service(audit_log)
+        .service(export)
+        .service(sessions)
+        .service(create)
+        .service(edit)
+        .service(void)
+}
+
+fn error_response(err: DonationError) -> HttpResponse {
+    let code = match err {
+        DonationError::NotFound => StatusCode::NOT_FOUND,
+        DonationError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        DonationError::Audit(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    ApiError::response(code, err.to_string())
+}
+
+#[get("/donations/audit")]
+async fn audit_log() -> impl Responder {
+    HttpResponse::Ok().json(audit::entries())
+}
+
+#[post("/donations")]
+async fn create(req: HttpRequest, body: web::Json<message::CreateDonation>) -> HttpResponse {
+    match chat_server(&req).send(body.into_inner()).await {
+        Ok(Ok(donation)) => HttpResponse::Created().json(donation),
+        Ok(Err(err)) => error_response(err),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[patch("/donations/{id}")]
+async fn edit(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    body: web::Json<message::EditDonation>,
+) -> HttpResponse {
+    let mut edit = body.into_inner();
+    edit.id = id.into_inner();
+    match chat_server(&req).send(edit).await {
+        Ok(Ok(donation)) => HttpResponse::Ok().json(donation),
+        Ok(Err(err)) => error_response(err),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[post("/donations/{id}/void")]
+async fn void(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    body: Option<web::Json<message::VoidDonation>>,
+) -> HttpResponse {
+    let mut void = body.map(|body| body.into_inner()).unwrap_or_default();
+    void.id = id.into_inner();
+    match chat_server(&req).send(void).await {
+        Ok(Ok(())) => HttpResponse::NoContent().finish(),
+        Ok(Err(err)) => error_response(err),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    session: Option<String>,
+    /// A date (`2024-03-01`) or RFC 3339 timestamp.
+    from: Option<String>,
+    /// A date, which includes the whole day, or RFC 3339 timestamp.
+    to: Option<String>,
+}
+
+#[get("/donations/export")]
+async fn export(query: web::Query<ExportQuery>) -> HttpResponse {
+    let mut filter = LedgerFilter {
+        session: query.session.to_owned(),
+        ..Default::default()
+    };
+    for (value, bound, end_of_day) in [
+        (&query.from, &mut filter.from, false),
+        (&query.to, &mut filter.to, true),
+    ] {
+        if let Some(value) = value {
+            match ledger::parse_time(value, end_of_day) {
+                Some(time) => *bound = Some(time),
+                None => {
+                    return ApiError::response(
+                        StatusCode::BAD_REQUEST,
+                        format!("Expected a date or RFC 3339 timestamp, got {:?}.", value),
+                    )
+                }
+            }
+        }
+    }
+
+    let donations = ledger::donations(&filter);
+    let subtotals = ledger::subtotals(&donations);
+
+    match query.format {
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"donations.csv\"",
+            ))
+            .body(ledger::to_csv(&donations, &subtotals)),
+        ExportFormat::Json => HttpResponse::Ok().json(Export::new(donations, subtotals)),
+    }
+}
+
+#[get("/donations/sessions")]
+async fn sessions() -> impl Responder {
+    HttpResponse::Ok().json(ledger::sessions())
+}
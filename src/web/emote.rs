@@ -0,0 +1,168 @@
+//! Emote packs managed from the dashboard, under `/api/v1/emotes`.
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+
+use super::api::{chat_server, ApiError};
+use super::message;
+use crate::config;
+use crate::emotes::{self, EmotePack, PackSettings};
+use crate::images;
+
+pub fn services(scope: actix_web::Scope) -> actix_web::Scope {
+    scope
+        .service(list)
+        .service(save_pack)
+        .service(delete_pack)
+        .service(add_emote)
+        .service(remove_emote)
+}
+
+/// An emote hosted elsewhere, sent as JSON instead of an image.
+#[derive(Deserialize, Debug)]
+struct RemoteEmote {
+    url: String,
+}
+
+fn check_names(pack: &str, code: Option<&str>) -> Result<(), HttpResponse> {
+    if !emotes::is_pack_name(pack) {
+        return Err(ApiError::response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Pack names must be letters, digits, - and _.",
+        ));
+    }
+    if code.is_some_and(|code| !emotes::is_code(code)) {
+        return Err(ApiError::response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "Emote codes must be up to {} letters, digits and _-!?().",
+                emotes::MAX_CODE_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Tells the chat server the packs changed, and responds with `pack`.
+async fn reloaded(req: &HttpRequest, pack: anyhow::Result<EmotePack>) -> HttpResponse {
+    let pack = match pack {
+        Ok(pack) => pack,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return ApiError::response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", err));
+        }
+    };
+    match chat_server(req).send(message::ReloadEmotes).await {
+        Ok(_) => HttpResponse::Ok().json(pack),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
+
+#[get("/emotes")]
+async fn list() -> HttpResponse {
+    HttpResponse::Ok().json(emotes::load())
+}
+
+#[put("/emotes/{pack}")]
+async fn save_pack(
+    req: HttpRequest,
+    pack: web::Path<String>,
+    body: web::Json<PackSettings>,
+) -> HttpResponse {
+    if let Err(response) = check_names(&pack, None) {
+        return response;
+    }
+    reloaded(&req, emotes::save_pack(&pack, body.into_inner())).await
+}
+
+#[delete("/emotes/{pack}")]
+async fn delete_pack(req: HttpRequest, pack: web::Path<String>) -> HttpResponse {
+    if let Err(response) = check_names(&pack, None) {
+        return response;
+    }
+    match emotes::delete_pack(&pack) {
+        Ok(true) => match chat_server(&req).send(message::ReloadEmotes).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(err) => ApiError::unavailable(err),
+        },
+        Ok(false) => ApiError::response(StatusCode::NOT_FOUND, "No emote pack with that name."),
+        Err(err) => {
+            log::error!("{:#}", err);
+            ApiError::response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", err))
+        }
+    }
+}
+
+/// Adds or replaces an emote. The body is either the image itself, or JSON with the
+/// `url` of one hosted elsewhere.
+#[put("/emotes/{pack}/{code}")]
+async fn add_emote(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    mut payload: web::Payload,
+) -> HttpResponse {
+    let (pack, code) = path.into_inner();
+    if let Err(response) = check_names(&pack, Some(&code)) {
+        return response;
+    }
+
+    let max_size = config::get().images.max_size * 1024;
+    let mut body = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => return ApiError::response(StatusCode::BAD_REQUEST, err.to_string()),
+        };
+        if body.len() as u64 + chunk.len() as u64 > max_size {
+            return ApiError::response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Emotes can be up to {} KiB.", max_size / 1024),
+            );
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if is_json {
+        let remote: RemoteEmote = match serde_json::from_slice(&body) {
+            Ok(remote) => remote,
+            Err(err) => {
+                return ApiError::response(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+            }
+        };
+        if !(remote.url.starts_with("https://") || remote.url.starts_with("http://")) {
+            return ApiError::response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Emote URLs must be http or https.",
+            );
+        }
+        return reloaded(&req, emotes::add_url(&pack, &code, &remote.url)).await;
+    }
+
+    let Some(ext) = images::sniff(&body) else {
+        return ApiError::response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Emotes must be PNG, JPEG, GIF, WebP, AVIF or SVG images.",
+        );
+    };
+    reloaded(&req, emotes::add_image(&pack, &code, ext, &body)).await
+}
+
+#[delete("/emotes/{pack}/{code}")]
+async fn remove_emote(req: HttpRequest, path: web::Path<(String, String)>) -> HttpResponse {
+    let (pack, code) = path.into_inner();
+    if let Err(response) = check_names(&pack, Some(&code)) {
+        return response;
+    }
+    match emotes::remove(&pack, &code) {
+        Ok(Some(pack)) => reloaded(&req, Ok(pack)).await,
+        Ok(None) => ApiError::response(StatusCode::NOT_FOUND, "No such emote in that pack."),
+        Err(err) => reloaded(&req, Err(err)).await,
+    }
+}
@@ -0,0 +1,148 @@
+use serde::Deserialize;
+
+use super::broadcast::Event;
+use crate::platform::Badge;
+
+/// Subscription filter as given in a query string. Lists are comma separated.
+#[derive(Deserialize, Debug, Default)]
+pub struct FilterQuery {
+    pub tags: Option<String>,
+    pub platforms: Option<String>,
+    pub badges: Option<String>,
+    #[serde(default)]
+    pub paid_only: bool,
+    pub min_tier: Option<u8>,
+    pub keywords: Option<String>,
+    pub highlight: Option<String>,
+}
+
+/// Subscription filter as sent in a `{"subscribe": {...}}` websocket command.
+/// It replaces the client's filter, so anything left out lets everything through.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterCommand {
+    pub tags: Vec<String>,
+    pub platforms: Vec<String>,
+    pub badges: Vec<Badge>,
+    pub paid_only: bool,
+    pub min_tier: Option<u8>,
+    pub keywords: Vec<String>,
+    pub highlight: Vec<String>,
+}
+
+/// Which broadcast events a client wants. The default lets everything through.
+///
+/// Tags apply to every event. The remaining fields only apply to events
+/// carrying a chat message; viewer counts and the like always pass them.
+#[derive(Debug, Default, Clone)]
+pub struct EventFilter {
+    pub tags: Vec<String>,
+    pub platforms: Vec<String>,
+    /// Only chat from authors with any of these badges.
+    pub badges: Vec<Badge>,
+    pub paid_only: bool,
+    pub min_tier: Option<u8>,
+    /// Only chat containing any of these words.
+    pub keywords: Vec<String>,
+    /// Chat containing any of these words is sent marked as highlighted.
+    pub highlight: Vec<String>,
+}
+
+fn split_list(list: &Option<String>) -> Vec<String> {
+    list.as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Whether `word` appears in `text` on its own rather than inside a longer word.
+/// `word` may span several words, such as `"good game"`.
+fn contains_word(text: &str, word: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).any(|(start, _)| {
+        !text[..start].chars().next_back().is_some_and(is_word)
+            && !text[start + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_word)
+    })
+}
+
+fn lowercase(list: Vec<String>) -> Vec<String> {
+    list.into_iter()
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl From<&FilterQuery> for EventFilter {
+    fn from(query: &FilterQuery) -> Self {
+        Self {
+            tags: split_list(&query.tags),
+            platforms: split_list(&query.platforms),
+            // Unknown badges are ignored rather than failing the connection.
+            badges: split_list(&query.badges)
+                .iter()
+                .filter_map(|name| Badge::from_name(name))
+                .collect(),
+            paid_only: query.paid_only,
+            min_tier: query.min_tier,
+            keywords: split_list(&query.keywords),
+            highlight: split_list(&query.highlight),
+        }
+    }
+}
+
+impl From<FilterCommand> for EventFilter {
+    fn from(command: FilterCommand) -> Self {
+        Self {
+            tags: lowercase(command.tags),
+            platforms: lowercase(command.platforms),
+            badges: command.badges,
+            paid_only: command.paid_only,
+            min_tier: command.min_tier,
+            keywords: lowercase(command.keywords),
+            highlight: lowercase(command.highlight),
+        }
+    }
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if !self.tags.is_empty() && !self.tags.contains(&event.tag) {
+            return false;
+        }
+
+        let meta = match &event.meta {
+            Some(meta) => meta,
+            None => return true,
+        };
+
+        if !self.platforms.is_empty() && !self.platforms.contains(&meta.platform.to_lowercase()) {
+            return false;
+        }
+        if !self.badges.is_empty() && !self.badges.iter().any(|b| meta.badges.contains(b)) {
+            return false;
+        }
+        if !self.keywords.is_empty() && !self.keywords.iter().any(|k| contains_word(&meta.text, k))
+        {
+            return false;
+        }
+
+        match (meta.paid_tier, self.min_tier) {
+            (None, _) if self.paid_only || self.min_tier.is_some() => false,
+            (Some(tier), Some(min_tier)) => tier >= min_tier,
+            _ => true,
+        }
+    }
+
+    /// Whether a matching event should be marked as highlighted.
+    pub fn highlights(&self, event: &Event) -> bool {
+        event
+            .meta
+            .as_ref()
+            .is_some_and(|meta| self.highlight.iter().any(|k| contains_word(&meta.text, k)))
+    }
+}
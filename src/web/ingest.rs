@@ -0,0 +1,132 @@
+//! `POST /api/v1/ingest` for feeders that can't hold a websocket open.
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Resource};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::api::{chat_server, ApiError};
+use super::limit::MAX_MESSAGES_PER_UPDATE;
+use super::message;
+use super::ChatMessage;
+use crate::config;
+use crate::message::LivestreamUpdate;
+
+/// Largest request body accepted, after decompression.
+const MAX_INGEST_BYTES: usize = 4 * 1024 * 1024;
+/// Most chat messages accepted across every update in one request.
+const MAX_INGEST_MESSAGES: usize = 1000;
+
+/// Either a single update, as the websocket accepts, or a batch of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum IngestBody {
+    One(LivestreamUpdate),
+    Many(Vec<LivestreamUpdate>),
+}
+
+/// Acknowledgement of a request, so a feeder knows what it can drop from its queue.
+#[derive(Serialize, Deserialize, Default)]
+pub struct IngestAck {
+    /// Messages that were new.
+    pub accepted: Vec<Uuid>,
+    /// Messages the server already had. Safe to consider delivered.
+    pub duplicates: Vec<Uuid>,
+    /// Viewer counts applied.
+    pub viewer_updates: usize,
+}
+
+pub fn resource() -> Resource {
+    web::resource("/ingest")
+        .app_data(web::JsonConfig::default().limit(MAX_INGEST_BYTES))
+        .route(web::post().to(ingest))
+}
+
+/// Checks the bearer token against `ingest.token` in constant time. An empty token
+/// disables auth.
+fn authorized(req: &HttpRequest) -> bool {
+    let token = config::get().ingest.token.to_owned();
+    if token.is_empty() {
+        return true;
+    }
+
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| bool::from(given.trim().as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Gzip bodies are decompressed by the JSON extractor when sent with `Content-Encoding: gzip`.
+async fn ingest(req: HttpRequest, body: web::Json<IngestBody>) -> HttpResponse {
+    if !authorized(&req) {
+        return ApiError::response(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token.");
+    }
+
+    let updates = match body.into_inner() {
+        IngestBody::One(update) => vec![update],
+        IngestBody::Many(updates) => updates,
+    };
+
+    let mut total = 0;
+    for update in &updates {
+        let count = update.messages.as_ref().map_or(0, Vec::len);
+        if count > MAX_MESSAGES_PER_UPDATE {
+            return ApiError::response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Updates may contain at most {} messages.",
+                    MAX_MESSAGES_PER_UPDATE
+                ),
+            );
+        }
+        total += count;
+    }
+    if total > MAX_INGEST_MESSAGES {
+        return ApiError::response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Requests may contain at most {} messages.",
+                MAX_INGEST_MESSAGES
+            ),
+        );
+    }
+
+    let server = chat_server(&req);
+    let mut ack = IngestAck::default();
+
+    for update in updates {
+        server.do_send(message::Activity {
+            feeder: None,
+            platform: update.platform.to_owned(),
+            channel: update.channel.to_owned(),
+            messages: update.messages.as_ref().map_or(0, Vec::len),
+        });
+
+        if let Some(viewers) = update.viewers {
+            if let Err(err) = server
+                .send(message::ViewCount {
+                    platform: update.platform,
+                    channel: update.channel,
+                    viewers,
+                })
+                .await
+            {
+                return ApiError::unavailable(err);
+            }
+            ack.viewer_updates += 1;
+        }
+
+        for chat_message in update.messages.unwrap_or_default() {
+            let id = chat_message.id;
+            match server.send(ChatMessage { chat_message }).await {
+                Ok(true) => ack.accepted.push(id),
+                Ok(false) => ack.duplicates.push(id),
+                Err(err) => return ApiError::unavailable(err),
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(ack)
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+
+/// Largest websocket frame we accept from a client.
+pub const MAX_FRAME_SIZE: usize = 256 * 1024;
+/// Largest `messages` array accepted in a single `LivestreamUpdate`.
+pub const MAX_MESSAGES_PER_UPDATE: usize = 250;
+/// Violations tolerated before the connection is dropped.
+pub const MAX_STRIKES: usize = 5;
+/// Strikes are forgiven after this long without a new violation.
+pub const STRIKE_WINDOW: Duration = Duration::from_secs(30);
+
+/// Token bucket refilled continuously at `refill_per_sec` up to `capacity`.
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            last_refill_at: Instant::now(),
+        }
+    }
+
+    /// Takes `amount` tokens if they are available.
+    pub fn try_take(&mut self, amount: f64) -> bool {
+        self.take_at(amount, Instant::now())
+    }
+
+    fn take_at(&mut self, amount: f64, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill_at = now;
+
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// What a client tried to do too much of.
+#[derive(Debug, Clone, Copy)]
+pub enum Violation {
+    Frames,
+    Messages,
+    OversizedUpdate,
+    Commands,
+}
+
+impl Violation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Violation::Frames => "frame_rate",
+            Violation::Messages => "message_rate",
+            Violation::OversizedUpdate => "update_too_large",
+            Violation::Commands => "command_rate",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            Violation::Frames => "Too many frames sent.".to_owned(),
+            Violation::Messages => "Too many chat messages sent.".to_owned(),
+            Violation::OversizedUpdate => format!(
+                "Updates may contain at most {} messages.",
+                MAX_MESSAGES_PER_UPDATE
+            ),
+            Violation::Commands => "Too many commands sent.".to_owned(),
+        }
+    }
+}
+
+/// Per-connection flood protection.
+pub struct ClientLimits {
+    /// Every text or binary frame costs one token.
+    pub frames: TokenBucket,
+    /// Every chat message in an update costs one token.
+    pub messages: TokenBucket,
+    /// Every feature command costs one token.
+    pub commands: TokenBucket,
+    /// Violations since the strike window last lapsed.
+    pub strikes: usize,
+    pub last_strike_at: Instant,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            frames: TokenBucket::new(60.0, 20.0),
+            // Feeders flush their whole backlog when they (re)connect.
+            messages: TokenBucket::new(500.0, 50.0),
+            commands: TokenBucket::new(5.0, 1.0),
+            strikes: 0,
+            last_strike_at: Instant::now(),
+        }
+    }
+}
+
+impl ClientLimits {
+    /// Records a violation. Returns true if the client has run out of strikes.
+    pub fn strike(&mut self) -> bool {
+        self.strike_at(Instant::now())
+    }
+
+    fn strike_at(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_strike_at) > STRIKE_WINDOW {
+            self.strikes = 0;
+        }
+        self.last_strike_at = now;
+        self.strikes += 1;
+        self.strikes >= MAX_STRIKES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_up_to_capacity() {
+        let mut bucket = TokenBucket::new(3.0, 1.0);
+        let start = bucket.last_refill_at;
+        assert!(bucket.take_at(2.0, start));
+        assert!(bucket.take_at(1.0, start));
+        assert!(!bucket.take_at(1.0, start));
+        // A refused take costs nothing.
+        assert!(!bucket.take_at(4.0, start));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(3.0, 2.0);
+        let start = bucket.last_refill_at;
+        assert!(bucket.take_at(3.0, start));
+
+        let later = start + Duration::from_millis(750);
+        assert!(bucket.take_at(1.5, later));
+        assert!(!bucket.take_at(0.5, later));
+    }
+
+    #[test]
+    fn bucket_refills_no_further_than_capacity() {
+        let mut bucket = TokenBucket::new(3.0, 2.0);
+        let start = bucket.last_refill_at;
+        assert!(bucket.take_at(3.0, start));
+
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.take_at(3.0, later));
+        assert!(!bucket.take_at(1.0, later));
+    }
+
+    #[test]
+    fn default_limits_take_a_full_update() {
+        let mut limits = ClientLimits::default();
+        assert!(limits.messages.try_take(MAX_MESSAGES_PER_UPDATE as f64));
+    }
+
+    #[test]
+    fn strikes_out_after_max_strikes() {
+        let mut limits = ClientLimits::default();
+        let start = limits.last_strike_at;
+        for n in 1..MAX_STRIKES {
+            assert!(!limits.strike_at(start + Duration::from_secs(n as u64)));
+        }
+        assert!(limits.strike_at(start + Duration::from_secs(MAX_STRIKES as u64)));
+        assert_eq!(limits.strikes, MAX_STRIKES);
+    }
+
+    #[test]
+    fn strikes_lapse_after_the_window() {
+        let mut limits = ClientLimits::default();
+        let mut at = limits.last_strike_at;
+        for _ in 1..MAX_STRIKES {
+            assert!(!limits.strike_at(at));
+        }
+        // Each strike restarts the window, so only a quiet spell forgives them.
+        at += STRIKE_WINDOW;
+        assert!(limits.strike_at(at));
+
+        let mut limits = ClientLimits::default();
+        let mut at = limits.last_strike_at;
+        for _ in 1..MAX_STRIKES {
+            assert!(!limits.strike_at(at));
+        }
+        at += STRIKE_WINDOW + Duration::from_secs(1);
+        assert!(!limits.strike_at(at));
+        assert_eq!(limits.strikes, 1);
+    }
+}
@@ -0,0 +1,75 @@
+//! Stream markers under `/api/v1/markers`.
+
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use super::api::{self, chat_server, ApiError};
+use super::message;
+use crate::marker;
+
+pub fn services(scope: actix_web::Scope) -> actix_web::Scope {
+    scope.service(list).service(create)
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum MarkerFormat {
+    #[default]
+    Json,
+    /// Chapter list for a YouTube description.
+    Youtube,
+    Edl,
+    Csv,
+}
+
+#[derive(Deserialize, Debug)]
+struct MarkerQuery {
+    #[serde(default)]
+    format: MarkerFormat,
+    /// Defaults to the current session.
+    session: Option<String>,
+}
+
+#[get("/markers")]
+async fn list(req: HttpRequest, query: web::Query<MarkerQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let session = match api::session(&req, query.session).await {
+        Ok(session) => session,
+        Err(err) => return ApiError::unavailable(err),
+    };
+    let markers = marker::markers(&session);
+
+    let attachment = |extension: &str| {
+        (
+            "Content-Disposition",
+            format!("attachment; filename=\"markers-{}.{}\"", session, extension),
+        )
+    };
+    match query.format {
+        MarkerFormat::Json => HttpResponse::Ok().json(markers),
+        MarkerFormat::Youtube => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(marker::to_youtube_chapters(&markers)),
+        MarkerFormat::Edl => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .insert_header(attachment("edl"))
+            .body(marker::to_edl(&session, &markers)),
+        MarkerFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(attachment("csv"))
+            .body(marker::to_csv(&markers)),
+    }
+}
+
+#[post("/markers")]
+async fn create(req: HttpRequest, body: web::Json<message::AddMarker>) -> HttpResponse {
+    let body = body.into_inner();
+    if body.label.trim().is_empty() {
+        return ApiError::response(StatusCode::UNPROCESSABLE_ENTITY, "A label is required.");
+    }
+    match chat_server(&req).send(body).await {
+        Ok(marker) => HttpResponse::Created().json(marker),
+        Err(err) => ApiError::unavailable(err),
+    }
+}
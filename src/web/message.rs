@@ -1,14 +1,50 @@
+use super::broadcast::Event;
+use super::filter::EventFilter;
+use super::source::Source;
+use crate::analytics::AnalyticsReport;
+use crate::config::{BrandingConfig, Reloaded};
+use crate::emotes::EmotePack;
+use crate::marker::Marker;
 use crate::message::Message as ChatMessage;
+use crate::viewers::ChannelViewers;
 use actix::{Message, Recipient};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use uuid::Uuid;
 
 /// Client hello message.
 pub struct Connect {
     pub recipient: Recipient<Reply>,
+    pub evict: Recipient<Evict>,
+    /// Replay every event after this sequence number.
+    pub since: Option<u64>,
+    /// Send this many recent chat messages and the current state.
+    pub backlog: Option<usize>,
+    /// Only send events matching this filter.
+    pub filter: EventFilter,
 }
 
 impl Message for Connect {
-    type Result = usize;
+    type Result = Connected;
+}
+
+/// A new client's id, with the events it missed.
+pub struct Connected {
+    pub id: usize,
+    /// Returned rather than sent, so a backlog doesn't count against the client's
+    /// mailbox or buffer.
+    pub catch_up: Vec<Reply>,
+}
+
+/// Client replacing its subscription filter.
+pub struct Subscribe {
+    pub id: usize,
+    pub filter: EventFilter,
+}
+
+impl Message for Subscribe {
+    type Result = ();
 }
 
 /// Announce disconnect
@@ -21,17 +57,40 @@ impl Message for Disconnect {
 }
 
 /// Server response to clients listening to the WebSocket.
-/// Usually a serialized JSON string.
-pub struct Reply(pub String);
+/// A serialized event shared by every recipient of a broadcast.
+pub struct Reply(pub Arc<Event>);
 
 impl Message for Reply {
     type Result = ();
 }
 
+/// Server dropping a client that can't keep up.
+pub struct Evict {
+    pub reason: String,
+}
+
+impl Message for Evict {
+    type Result = ();
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ReplyInner {
+    /// Position in the broadcast stream. Absent for replies to a single client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
     pub tag: String,
     pub message: String,
+    /// Set on chat matching one of the client's highlight keywords.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub highlight: bool,
+}
+
+/// Sent to a client when one of its frames was refused.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ErrorReply {
+    pub code: String,
+    pub reason: String,
+    pub strikes: usize,
 }
 
 /// Content message.
@@ -40,7 +99,8 @@ pub struct Content {
 }
 
 impl Message for Content {
-    type Result = ();
+    /// False if the message was a duplicate.
+    type Result = bool;
 }
 
 /// Feature/Unfeature message.
@@ -52,6 +112,16 @@ impl Message for FeatureMessage {
     type Result = ();
 }
 
+/// Client asking for every event it missed while reconnecting.
+pub struct Resume {
+    pub id: usize,
+    pub since: u64,
+}
+
+impl Message for Resume {
+    type Result = ();
+}
+
 /// Request for recent chat messages.
 pub struct RecentMessages;
 
@@ -70,10 +140,272 @@ impl Message for PaidMessages {
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ViewCount {
     pub platform: String,
-    //pub channel: String,
+    #[serde(default)]
+    pub channel: Option<String>,
     pub viewers: usize,
 }
 
 impl Message for ViewCount {
     type Result = ();
 }
+
+/// Messages per page when the client doesn't ask for a size.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+/// Largest page a client may ask for.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Request for a page of stored chat messages, oldest first.
+/// Doubles as the query string of `GET /api/v1/messages`.
+#[derive(Deserialize, Debug, Default)]
+pub struct QueryMessages {
+    pub platform: Option<String>,
+    /// Username, case insensitive.
+    pub user: Option<String>,
+    /// Only messages received at or after this unix timestamp in milliseconds.
+    pub since: Option<i64>,
+    /// Only paid (true) or only unpaid (false) messages.
+    pub paid: Option<bool>,
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl QueryMessages {
+    pub fn matches(&self, msg: &ChatMessage) -> bool {
+        self.platform
+            .as_ref()
+            .is_none_or(|platform| msg.platform.eq_ignore_ascii_case(platform))
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| msg.username.eq_ignore_ascii_case(user))
+            && self.since.is_none_or(|since| msg.received_at >= since)
+            && self.paid.is_none_or(|paid| msg.is_premium() == paid)
+    }
+}
+
+impl Message for QueryMessages {
+    type Result = MessagePage;
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessagePage {
+    /// Messages matching the query across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Request for a single stored chat message.
+pub struct GetMessage(pub Uuid);
+
+impl Message for GetMessage {
+    type Result = Option<ChatMessage>;
+}
+
+/// Request for current viewer counts by platform.
+pub struct ViewerCounts;
+
+impl Message for ViewerCounts {
+    type Result = HashMap<String, usize>;
+}
+
+/// Request for view counts by platform and channel.
+pub struct ChannelViewerCounts;
+
+impl Message for ChannelViewerCounts {
+    type Result = Vec<ChannelViewers>;
+}
+
+/// Request for this session's chat statistics.
+pub struct ChatAnalytics;
+
+impl Message for ChatAnalytics {
+    type Result = AnalyticsReport;
+}
+
+/// A marker added by hand.
+#[derive(Deserialize, Debug)]
+pub struct AddMarker {
+    pub label: String,
+    /// Unix timestamp in milliseconds. Defaults to now.
+    pub at: Option<i64>,
+}
+
+impl Message for AddMarker {
+    type Result = Marker;
+}
+
+/// Request for the id of the current session.
+pub struct CurrentSession;
+
+impl Message for CurrentSession {
+    type Result = String;
+}
+
+/// Loads the emote packs again after they were changed, returning them.
+pub struct ReloadEmotes;
+
+impl Message for ReloadEmotes {
+    type Result = Vec<EmotePack>;
+}
+
+/// Reloads the config file and applies it without dropping clients.
+pub struct Reconfigure;
+
+impl Message for Reconfigure {
+    type Result = anyhow::Result<Reloaded>;
+}
+
+/// Settings overlays apply live, sent to clients when the config is reloaded.
+#[derive(Serialize, Debug)]
+pub struct ClientConfig {
+    pub branding: BrandingConfig,
+    /// Branding of each overlay profile, which may differ from `branding`.
+    pub overlays: BTreeMap<String, BrandingConfig>,
+}
+
+/// Request for server status.
+pub struct Status;
+
+impl Message for Status {
+    type Result = ServerStatus;
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServerStatus {
+    pub version: &'static str,
+    /// Unix timestamp in milliseconds.
+    pub started_at: i64,
+    /// Connected websocket and event stream clients.
+    pub clients: usize,
+    pub messages: usize,
+    pub paid_messages: usize,
+    /// Total viewers across platforms.
+    pub viewers: usize,
+    pub featured_message: Option<Uuid>,
+    /// Sequence number of the last broadcast event.
+    pub seq: u64,
+    /// Session donations received by this run are recorded under.
+    pub session: String,
+}
+
+/// A feeder, connector or ingest client sent an update.
+pub struct Activity {
+    /// Websocket client id, for feeders.
+    pub feeder: Option<usize>,
+    pub platform: String,
+    pub channel: Option<String>,
+    /// Chat messages in the update.
+    pub messages: usize,
+}
+
+impl Message for Activity {
+    type Result = ();
+}
+
+pub struct ListSources;
+
+impl Message for ListSources {
+    type Result = Vec<Source>;
+}
+
+/// Chat removed by a platform's moderators.
+#[derive(Debug, Clone)]
+pub enum Deletion {
+    Message(Uuid),
+    /// Everything a user said in a connector's channel, after a ban or timeout.
+    /// Matched on the platform's user id, which only connectors record.
+    User {
+        platform: String,
+        channel: String,
+        user_id: String,
+    },
+    /// Everything a connector read from a channel, after the chat was cleared.
+    Channel {
+        platform: String,
+        channel: String,
+    },
+}
+
+/// Removes chat messages. Paid messages are kept, as donations are voided instead.
+/// Returns how many messages were removed.
+pub struct DeleteMessages(pub Deletion);
+
+impl Message for DeleteMessages {
+    type Result = usize;
+}
+
+/// Default platform for donations entered by hand.
+fn manual_platform() -> String {
+    "mail".to_owned()
+}
+
+fn anonymous() -> String {
+    "Anonymous".to_owned()
+}
+
+/// A donation entered by hand on the dashboard.
+#[derive(Deserialize, Debug)]
+pub struct CreateDonation {
+    #[serde(default = "manual_platform")]
+    pub platform: String,
+    #[serde(default = "anonymous")]
+    pub username: String,
+    pub amount: f64,
+    pub currency: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl Message for CreateDonation {
+    type Result = Result<ChatMessage, DonationError>;
+}
+
+/// Corrections to a manual donation. Omitted fields are left alone.
+#[derive(Deserialize, Debug, Default)]
+pub struct EditDonation {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub username: Option<String>,
+    pub amount: Option<f64>,
+    /// Currency of `amount`. Defaults to the currency the donation was made in.
+    pub currency: Option<String>,
+    pub message: Option<String>,
+}
+
+impl Message for EditDonation {
+    type Result = Result<ChatMessage, DonationError>;
+}
+
+/// Removes a manual donation from the superchats and totals.
+#[derive(Deserialize, Debug, Default)]
+pub struct VoidDonation {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub reason: Option<String>,
+}
+
+impl Message for VoidDonation {
+    type Result = Result<(), DonationError>;
+}
+
+#[derive(Debug)]
+pub enum DonationError {
+    /// No manual donation has this id.
+    NotFound,
+    Invalid(String),
+    /// The change couldn't be written to the audit log, so it wasn't made.
+    Audit(std::io::Error),
+}
+
+impl std::fmt::Display for DonationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DonationError::NotFound => write!(f, "No manual donation with that id."),
+            DonationError::Invalid(reason) => write!(f, "{}", reason),
+            DonationError::Audit(err) => write!(f, "Failed to write audit log: {}", err),
+        }
+    }
+}
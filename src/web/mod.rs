@@ -1,37 +1,122 @@
+pub mod api;
+pub mod assets;
+mod broadcast;
 mod client;
+mod donation;
+mod emote;
+mod filter;
+mod ingest;
+mod limit;
+mod marker;
 mod message;
 mod server;
+mod source;
+mod sse;
+mod webhook;
 
+pub use broadcast::SlowConsumerPolicy;
 pub use client::ChatClient;
+pub use ingest::IngestAck;
 pub use message::Content as ChatMessage;
-pub use message::PaidMessages;
+#[cfg(test)]
+pub use message::RecentMessages;
+pub use message::{ChatAnalytics, DeleteMessages, Deletion, PaidMessages};
 pub use server::ChatServer;
 
-use actix::Addr;
-use actix_web::HttpResponseBuilder;
+use actix::{Actor, Addr};
 use actix_web::{http::header, web, Error, HttpRequest, HttpResponse, Responder};
 use actix_web_actors::ws;
 use askama_actix::Template;
-use askama_actix::TemplateToResponse;
-use std::time::{Duration, Instant};
+use filter::{EventFilter, FilterQuery};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use limit::{ClientLimits, MAX_FRAME_SIZE};
+use serde::Deserialize;
+use sse::{SseClient, SSE_BUFFER};
+use std::sync::Arc;
+use std::time::Instant;
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+use crate::config::{self, BrandingConfig, OverlayLayout, OverlayProfile};
+use crate::connector::ConnectorSupervisor;
+use crate::emotes;
+use crate::images::{self, ImageCache, ImageError};
+use crate::platform::{self, Badge};
 
 #[derive(Template)]
 #[template(path = "chat.html")]
-struct ChatTemplate {}
+struct ChatTemplate {
+    branding: BrandingConfig,
+}
 
 #[derive(Template)]
 #[template(path = "dashboard.html")]
 struct DashboardTemplate {
+    branding: BrandingConfig,
     super_chats: Vec<crate::message::Message>,
 }
 
 #[derive(Template)]
 #[template(path = "overlay.html")]
 struct OverlayTemplate {
-    super_chats: Vec<crate::message::Message>,
+    branding: BrandingConfig,
+    /// Profile name, for picking its branding out of reloaded configs.
+    profile: String,
+    layout: OverlayLayout,
+    theme: Option<String>,
+    max_messages: usize,
+    /// Subscription filter for the chat websocket.
+    query: String,
+}
+
+impl OverlayTemplate {
+    fn new(name: &str, profile: OverlayProfile) -> Self {
+        let mut query = Vec::new();
+        for (key, list) in [
+            ("platforms", &profile.platforms),
+            ("badges", &profile.badges),
+            ("keywords", &profile.keywords),
+            ("highlight", &profile.highlight),
+        ] {
+            if !list.is_empty() {
+                query.push((key, list.join(",")));
+            }
+        }
+        if profile.layout == OverlayLayout::Superchats {
+            query.push(("paid_only", "true".to_owned()));
+        }
+        Self {
+            branding: profile.branding(&config::get().branding),
+            profile: name.to_owned(),
+            layout: profile.layout,
+            theme: profile.theme,
+            max_messages: profile.max_messages,
+            query: serde_urlencoded::to_string(query).unwrap_or_default(),
+        }
+    }
+
+    /// Whether the live badge, attribution and featured message are shown.
+    fn has_scene(&self) -> bool {
+        matches!(
+            self.layout,
+            OverlayLayout::Horizontal | OverlayLayout::Vertical
+        )
+    }
+}
+
+/// Reads the config file again and applies it to the chat server and connected clients.
+/// The config in use is kept if the new one has errors.
+pub async fn reload_config(server: &Addr<ChatServer>) -> anyhow::Result<config::Reloaded> {
+    let reloaded = server.send(message::Reconfigure).await??;
+    for warning in &reloaded.warnings {
+        log::warn!("{}", warning.message);
+    }
+    if !reloaded.needs_restart.is_empty() {
+        log::warn!(
+            "Changes to {} apply after a restart.",
+            reloaded.needs_restart.join(", ")
+        );
+    }
+    Ok(reloaded)
 }
 
 #[actix_web::get("/chat")]
@@ -39,9 +124,19 @@ pub async fn chat() -> impl Responder {
     HttpResponse::Ok()
         .insert_header((
             header::CONTENT_SECURITY_POLICY,
-            "default-src 'self'; img-src * 'self' data:; font-src *; style-src * 'unsafe-inline';",
+            // With the image proxy on, chat never loads images from elsewhere.
+            if config::get().images.proxy {
+                "default-src 'self'; img-src 'self' data:; font-src *; style-src * 'unsafe-inline';"
+            } else {
+                "default-src 'self'; img-src * 'self' data:; font-src *; style-src * 'unsafe-inline';"
+            },
         ))
-        .body(ChatTemplate {}.to_string())
+        .body(
+            ChatTemplate {
+                branding: config::get().branding.to_owned(),
+            }
+            .to_string(),
+        )
 }
 
 #[actix_web::get("/dashboard")]
@@ -51,76 +146,195 @@ pub async fn dashboard(req: HttpRequest) -> impl Responder {
         .expect("ChatServer missing in app data!")
         .clone();
     DashboardTemplate {
+        branding: config::get().branding.to_owned(),
         super_chats: chat_server.send(PaidMessages).await.unwrap(),
     }
 }
 
 #[actix_web::get("/overlay")]
-pub async fn overlay(req: HttpRequest) -> impl Responder {
-    let chat_server = req
-        .app_data::<Addr<ChatServer>>()
-        .expect("ChatServer missing in app data!")
-        .clone();
-    OverlayTemplate {
-        super_chats: chat_server.send(PaidMessages).await.unwrap(),
+pub async fn overlay() -> impl Responder {
+    let name = OverlayLayout::Horizontal.name();
+    let profile = config::get()
+        .overlay(name)
+        .unwrap_or(OverlayProfile::new(OverlayLayout::Horizontal));
+    OverlayTemplate::new(name, profile)
+}
+
+#[actix_web::get("/overlay/{profile}")]
+pub async fn overlay_profile(name: web::Path<String>) -> HttpResponse {
+    match config::get().overlay(&name) {
+        Some(profile) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(OverlayTemplate::new(&name, profile).to_string()),
+        None => HttpResponse::NotFound().body(format!("No overlay profile named {:?}", name)),
     }
 }
 
 #[actix_web::get("/dashboard.js")]
-pub async fn dashboard_javascript() -> impl Responder {
-    HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "text/javascript"))
-        .body(std::fs::read_to_string("public/dashboard.js").unwrap())
+pub async fn dashboard_javascript(req: HttpRequest) -> impl Responder {
+    assets::serve(&req, "dashboard.js")
 }
 
 #[actix_web::get("/script.js")]
-pub async fn javascript() -> impl Responder {
-    HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "text/javascript"))
-        .body(std::fs::read_to_string("public/script.js").unwrap())
+pub async fn javascript(req: HttpRequest) -> impl Responder {
+    assets::serve(&req, "script.js")
 }
 
 #[actix_web::get("/style.css")]
-pub async fn stylesheet() -> impl Responder {
-    HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "text/css"))
-        .body(std::fs::read_to_string("public/style.css").unwrap())
+pub async fn stylesheet(req: HttpRequest) -> impl Responder {
+    assets::serve(&req, "style.css")
 }
 
 #[actix_web::get("/dashboard.css")]
-pub async fn dashboard_stylesheet() -> impl Responder {
-    HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "text/css"))
-        .body(std::fs::read_to_string("public/dashboard.css").unwrap())
+pub async fn dashboard_stylesheet(req: HttpRequest) -> impl Responder {
+    assets::serve(&req, "dashboard.css")
 }
 
+/// Username colors from `colors.palette`, unless the override directory has its own.
 #[actix_web::get("/user-colors.css")]
-pub async fn colors() -> impl Responder {
+pub async fn colors(req: HttpRequest) -> impl Responder {
+    if assets::exists(&config::get().paths.overrides, "user-colors.css") {
+        return assets::serve(&req, "user-colors.css");
+    }
     HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "text/css"))
-        .body(std::fs::read_to_string("public/user-colors.css").unwrap())
+        .insert_header((header::CONTENT_TYPE, "text/css; charset=utf-8"))
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoCache]))
+        .body(crate::colors::stylesheet())
+}
+
+/// Empty unless the override directory has one, for restyling without replacing style.css.
+#[actix_web::get("/custom.css")]
+pub async fn custom_stylesheet(req: HttpRequest) -> impl Responder {
+    assets::serve(&req, "custom.css")
 }
 
+#[actix_web::get("/layouts/{layout}.css")]
+pub async fn layout_stylesheet(req: HttpRequest, layout: web::Path<String>) -> impl Responder {
+    assets::serve(&req, &format!("layouts/{}.css", layout))
+}
+
+#[actix_web::get("/themes/{theme}.css")]
+pub async fn theme_stylesheet(req: HttpRequest, theme: web::Path<String>) -> impl Responder {
+    assets::serve(&req, &format!("themes/{}.css", theme))
+}
+
+/// A platform's logo. Platforms without one of their own get the registry's logo,
+/// or the unknown platform's.
 #[actix_web::get("/logo/{platform}.{ext}")]
-pub async fn logo(info: web::Path<(String, String)>) -> impl Responder {
-    let (platform, ext) = info.into_inner();
-    let path = format!("public/logo/{}.{}", platform, ext);
-    match std::fs::read(&path) {
-        Ok(body) => match ext.as_str() {
-            "svg" => HttpResponse::Ok()
-                .append_header((header::CONTENT_TYPE, "image/svg+xml"))
-                .body(body),
-            "png" => HttpResponse::Ok()
-                .append_header((header::CONTENT_TYPE, "image/png"))
-                .body(body),
-            _ => HttpResponse::UnsupportedMediaType().body("Invalid extension"),
-        },
-        Err(e) => HttpResponse::NotFound().body(e.to_string()),
+pub async fn logo(req: HttpRequest, info: web::Path<(String, String)>) -> impl Responder {
+    let (name, ext) = info.into_inner();
+    if ext != "svg" && ext != "png" {
+        return HttpResponse::UnsupportedMediaType().body("Invalid extension");
     }
+    let path = format!("logo/{}.{}", name, ext);
+    if assets::exists(&config::get().paths.overrides, &path) {
+        return assets::serve(&req, &path);
+    }
+    assets::serve(&req, &format!("logo/{}", platform::get(&name).logo))
 }
 
+/// A badge icon, `badge/{platform}/{badge}.svg` if the platform has its own,
+/// otherwise `badge/{badge}.svg`.
+#[actix_web::get("/badge/{platform}/{badge}.svg")]
+pub async fn badge(req: HttpRequest, info: web::Path<(String, String)>) -> impl Responder {
+    let (name, badge) = info.into_inner();
+    let Some(badge) = Badge::from_name(&badge) else {
+        return HttpResponse::NotFound().body("Unknown badge");
+    };
+    let path = format!("badge/{}/{}.svg", platform::get(&name).id, badge.name());
+    if assets::exists(&config::get().paths.overrides, &path) {
+        return assets::serve(&req, &path);
+    }
+    assets::serve(&req, &format!("badge/{}.svg", badge.name()))
+}
+
+/// Brand colors of every platform in the registry.
+#[actix_web::get("/platforms.css")]
+pub async fn platform_stylesheet() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/css; charset=utf-8"))
+        .body(platform::stylesheet())
+}
+
+/// An avatar or emoji through the image proxy.
+#[actix_web::get("/img/{hash}")]
+pub async fn image(req: HttpRequest, hash: web::Path<String>) -> impl Responder {
+    let Some(images) = req.app_data::<Arc<ImageCache>>() else {
+        return HttpResponse::InternalServerError().body("Image cache missing");
+    };
+    match images.get(&hash).await {
+        Ok(image) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, image.content_type))
+            .insert_header(header::CacheControl(vec![
+                header::CacheDirective::Public,
+                header::CacheDirective::MaxAge(86400),
+            ]))
+            .insert_header(header::ETag(header::EntityTag::new_strong(
+                hash.into_inner(),
+            )))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            // SVGs can carry scripts; opened on their own they must not run.
+            .insert_header((
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'; sandbox",
+            ))
+            .body(image.body),
+        Err(err @ ImageError::NotFound) => HttpResponse::NotFound().body(err.to_string()),
+        Err(err @ ImageError::Upstream(_)) => HttpResponse::BadGateway().body(err.to_string()),
+        Err(err @ ImageError::Io(_)) => {
+            log::error!("{}", err);
+            HttpResponse::InternalServerError().body(err.to_string())
+        }
+    }
+}
+
+/// An image from an emote pack.
+#[actix_web::get("/emote/{pack}/{file}")]
+pub async fn emote_image(info: web::Path<(String, String)>) -> impl Responder {
+    let (pack, file) = info.into_inner();
+    let Some(path) = emotes::file_path(&pack, &file) else {
+        return HttpResponse::NotFound().body("No such emote");
+    };
+    let Ok(body) = std::fs::read(&path) else {
+        return HttpResponse::NotFound().body("No such emote");
+    };
+    let ext = file
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default();
+    HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_TYPE,
+            images::content_type(ext).unwrap_or("application/octet-stream"),
+        ))
+        // Packs can change from the dashboard.
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoCache]))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; style-src 'unsafe-inline'; sandbox",
+        ))
+        .body(body)
+}
+
+/// Query string accepted by the chat websocket and event stream.
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// Last sequence number the client saw before reconnecting.
+    pub since: Option<u64>,
+    /// Number of recent chat messages to send on connect.
+    pub backlog: Option<usize>,
+}
+
+/// Chat websocket. Accepts the filters in `FilterQuery`, which the client can replace
+/// later with a `{"subscribe": {...}}` command.
 #[actix_web::get("/chat.ws")]
-async fn websocket(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+async fn websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<StreamQuery>,
+    filter: web::Query<FilterQuery>,
+) -> Result<HttpResponse, Error> {
     let server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
@@ -129,10 +343,60 @@ async fn websocket(req: HttpRequest, stream: web::Payload) -> Result<HttpRespons
         id: rand::random(),
         server,
         last_heartbeat_at: Instant::now(),
-        last_command_at: Instant::now(),
+        limits: ClientLimits::default(),
+        since: query.since,
+        backlog: query.backlog,
+        filter: EventFilter::from(&*filter),
+        connectors: req
+            .app_data::<Addr<ConnectorSupervisor>>()
+            .expect("ConnectorSupervisor missing in app data!")
+            .clone(),
+        reporter: None,
     };
 
-    let resp = ws::start(client, &req, stream);
+    let resp = ws::WsResponseBuilder::new(client, &req, stream)
+        .frame_size(MAX_FRAME_SIZE)
+        .start();
     println!("{:?}", resp);
     resp
 }
+
+/// Server-Sent Events feed of the same broadcasts the websocket receives.
+///
+/// Accepts the filters in `FilterQuery` and resumes from the `Last-Event-ID` header.
+#[actix_web::get("/events")]
+async fn events(
+    req: HttpRequest,
+    query: web::Query<StreamQuery>,
+    filter: web::Query<FilterQuery>,
+) -> impl Responder {
+    let server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+    let since = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .or(query.since);
+
+    let (catch_up, caught_up) = mpsc::unbounded();
+    let (sender, receiver) = mpsc::channel(SSE_BUFFER);
+    SseClient {
+        id: rand::random(),
+        server,
+        catch_up: Some(catch_up),
+        sender,
+        since,
+        backlog: query.backlog,
+        filter: EventFilter::from(&*filter),
+    }
+    .start();
+
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .streaming(caught_up.chain(receiver).map(Ok::<_, Error>))
+}
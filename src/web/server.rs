@@ -1,14 +1,50 @@
-use actix::{Actor, Context, Handler, MessageResult, Recipient};
-use std::collections::HashMap;
+use actix::prelude::SendError;
+use actix::{Actor, AsyncContext, Context, Handler, MessageResult, Recipient};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use super::broadcast::{BroadcastPolicy, Event, SlowConsumerPolicy};
+use super::filter::EventFilter;
 use super::message;
+use super::source::{Sources, SOURCE_CHECK_INTERVAL};
+use crate::analytics::Analytics;
+use crate::archive::{self, Archive};
+use crate::audit::{self, AuditAction, AuditEntry};
+use crate::colors;
+use crate::config::{self, Config, ServerConfig};
+use crate::emotes::{self, EmotePack};
 use crate::exchange::ExchangeRates;
-use crate::message::Message as ChatMessage;
+use crate::images::ImageCache;
+use crate::ledger::{self, LedgerEntry};
+use crate::marker::{self, Detector, Marker, MarkerKind};
+use crate::message::{LivestreamUpdate, Message as ChatMessage};
+use crate::platform;
+use crate::viewers::{self, ChannelViewers, ViewerSample};
+
+/// Paid messages saved in case of a crash, in the data directory.
+const SUPER_CHATS: &str = "super_chats.json";
+
+/// A paid message as saved in `SUPER_CHATS`, with the text it was sent as.
+#[derive(Serialize, Deserialize)]
+struct SavedChat {
+    #[serde(flatten)]
+    message: ChatMessage,
+    /// Missing from files written before it was kept.
+    #[serde(default)]
+    text: String,
+}
 
 pub struct Connection {
     pub id: usize,
     pub recipient: Recipient<message::Reply>,
+    pub evict: Recipient<message::Evict>,
+    /// Events this client subscribed to.
+    pub filter: EventFilter,
+    /// Consecutive broadcasts this client has missed because its mailbox was full.
+    pub lag: usize,
 }
 
 /// Define HTTP actor
@@ -16,59 +52,461 @@ pub struct ChatServer {
     pub clients: HashMap<usize, Connection>,
     pub chat_messages: HashMap<Uuid, ChatMessage>,
     pub paid_messages: Vec<Uuid>,
+    /// Donations entered by hand, which may be edited or voided.
+    pub manual_donations: HashSet<Uuid>,
+    /// Latest ledger entry of each manual donation, so edits and voids don't re-read the ledger.
+    pub manual_entries: HashMap<Uuid, LedgerEntry>,
     pub exchange_rates: ExchangeRates,
-    pub viewer_counts: HashMap<String, usize>,
+    /// Viewers by platform and channel, with when they were last reported. The channel
+    /// is empty if the feeder did not say.
+    pub viewer_counts: HashMap<(String, String), (usize, Instant)>,
+    pub featured_message: Option<Uuid>,
+    pub broadcast_policy: BroadcastPolicy,
+    /// Mailbox, history and backlog sizes.
+    pub config: ServerConfig,
+    /// Sequence number of the last broadcast event.
+    pub seq: u64,
+    /// Most recent broadcast events, oldest first.
+    pub history: VecDeque<Arc<Event>>,
+    /// Unix timestamp in milliseconds.
+    pub started_at: i64,
+    /// Identifies this run of the server in the donation ledger.
+    pub session: String,
+    /// Platforms and channels sending chat.
+    pub sources: Sources,
+    /// How long a source may go without chat, while live, before it is flagged.
+    pub quiet_after: Duration,
+    /// Chat statistics for this session.
+    pub analytics: Analytics,
+    /// Watches chat for moments worth marking.
+    pub markers: Detector,
+    /// Hands out `/img/` URLs for avatars and emojis.
+    pub images: Arc<ImageCache>,
+    /// Emote packs matched in chat from every platform.
+    pub emotes: Vec<EmotePack>,
+    /// Every message as received, for exports and replays.
+    pub archive: Archive,
 }
 
 impl ChatServer {
-    pub fn new(exchange_rates: ExchangeRates) -> Self {
+    pub fn new(exchange_rates: ExchangeRates, config: &Config, images: Arc<ImageCache>) -> Self {
         log::info!("Chat actor starting up.");
 
+        let super_chats =
+            Self::load_super_chats(config.server.superchat_reload_window).unwrap_or_default();
+        let paid_messages: Vec<Uuid> = super_chats.iter().map(|msg| msg.id).collect();
+        let mut chat_messages: HashMap<Uuid, ChatMessage> = HashMap::with_capacity(100);
+        chat_messages.extend(super_chats.into_iter().map(|msg| (msg.id, msg)));
+        let manual_donations = audit::open_donations();
+        let manual_entries = ledger::latest(&manual_donations);
+        let session = chrono::Local::now()
+            .format(archive::SESSION_FORMAT)
+            .to_string();
+
+        Self {
+            clients: HashMap::with_capacity(100),
+            chat_messages,
+            paid_messages,
+            manual_donations,
+            manual_entries,
+            exchange_rates,
+            viewer_counts: HashMap::with_capacity(100),
+            featured_message: None,
+            broadcast_policy: BroadcastPolicy::new(&config.server),
+            config: config.server.to_owned(),
+            seq: 0,
+            history: VecDeque::with_capacity(config.server.history),
+            started_at: chrono::Utc::now().timestamp_millis(),
+            analytics: Analytics::new(&session),
+            session,
+            sources: Sources::default(),
+            quiet_after: Duration::from_secs(config.sources.quiet_after),
+            markers: Detector::new(config.markers.to_owned()),
+            images,
+            emotes: emotes::load(),
+            archive: Archive::default(),
+        }
+    }
+
+    /// Superchats saved by a recent run, if the server is restarting mid-stream.
+    fn load_super_chats(reload_window: u64) -> Option<Vec<ChatMessage>> {
+        let path = config::get().paths.data_file(SUPER_CHATS);
         // get last modified time of superchats.json
-        let super_chats_last_modified = std::fs::metadata("super_chats.json")
+        let super_chats_last_modified = std::fs::metadata(&path)
             .map(|meta| meta.modified().unwrap())
-            .ok();
-
-        // if superchats.json exists and was modified in the last 15 minutes, load it
-        if let Some(super_chats_last_modified) = super_chats_last_modified {
-            let now = std::time::SystemTime::now();
-            let duration = now.duration_since(super_chats_last_modified).unwrap();
-            if duration.as_secs() < 900 {
-                // Load superchats from disk.
-                let super_chats_json = std::fs::read_to_string("super_chats.json");
-                if let Ok(super_chats_json) = super_chats_json {
-                    log::info!("Loading superchats from disk.");
-                    let super_chats: Vec<ChatMessage> =
-                        serde_json::from_str(&super_chats_json).unwrap();
-                    let paid_messages: Vec<Uuid> = super_chats.iter().map(|msg| msg.id).collect();
-
-                    // convert to hashmap
-                    let chat_messages: HashMap<Uuid, ChatMessage> = super_chats
-                        .iter()
-                        .map(|msg| (msg.id, msg.clone()))
-                        .collect();
-                    return Self {
-                        clients: HashMap::with_capacity(100),
-                        chat_messages,
-                        paid_messages,
-                        exchange_rates,
-                        viewer_counts: Default::default(),
-                    };
+            .ok()?;
+
+        // if superchats.json was modified within the reload window, load it
+        let now = std::time::SystemTime::now();
+        let duration = now.duration_since(super_chats_last_modified).unwrap();
+        if duration.as_secs() >= reload_window {
+            return None;
+        }
+
+        // Load superchats from disk.
+        let super_chats_json = std::fs::read_to_string(&path).ok()?;
+        log::info!("Loading superchats from disk.");
+        let saved: Vec<SavedChat> = serde_json::from_str(&super_chats_json).unwrap();
+        Some(
+            saved
+                .into_iter()
+                .map(|saved| ChatMessage {
+                    text: saved.text,
+                    ..saved.message
+                })
+                .collect(),
+        )
+    }
+
+    /// Escapes chat text and swaps emoji codes for images.
+    ///
+    /// `emojis` are (find, replace, name) triples, where replace is an image URL.
+    /// `emotes` are the same from emote packs, but only replace whole words.
+    pub fn render_text(
+        text: &str,
+        emojis: &[(String, String, String)],
+        emotes: &[(String, String, String)],
+    ) -> String {
+        let escaped = text
+            .replace("&", "&amp;")
+            .replace("\"", "&quot")
+            .replace("'", "&#039;")
+            .replace("<", "&lt;")
+            .replace(">", "&gt;");
+
+        let mut replacements: HashMap<usize, String> =
+            HashMap::with_capacity(emojis.len() + emotes.len());
+        let mut replacement_string = escaped;
+        let mut token = |replace: &str, name: &str| {
+            let url = replace
+                .replace("&", "&amp;")
+                .replace("\"", "&quot")
+                .replace("'", "&#039;")
+                .replace("<", "&lt;")
+                .replace(">", "&gt;");
+            let key: usize = rand::random();
+            let value: String = format!(
+                "<img class=\"emoji\" src=\"{}\" data-emoji=\"{}\" alt=\"{}\" />",
+                url, name, name
+            );
+            replacements.insert(key, value);
+            format!("<{}>", key)
+        };
+
+        // First, replace all instances with tokens.
+        for (find, replace, name) in emojis {
+            let key = token(replace, name);
+            replacement_string = replacement_string.replace(find, &key);
+        }
+        // Emote codes have nothing to escape, so they match the escaped words as is.
+        if !emotes.is_empty() {
+            let mut words = String::with_capacity(replacement_string.len());
+            for piece in replacement_string.split_inclusive(char::is_whitespace) {
+                let word = piece.trim_end_matches(char::is_whitespace);
+                match emotes.iter().find(|(find, _, _)| find == word) {
+                    Some((_, replace, name)) => {
+                        words.push_str(&token(replace, name));
+                        words.push_str(&piece[word.len()..]);
+                    }
+                    None => words.push_str(piece),
                 }
             }
+            replacement_string = words;
         }
 
-        Self {
-            clients: HashMap::with_capacity(100),
-            chat_messages: HashMap::with_capacity(100),
-            paid_messages: Vec::with_capacity(100),
-            exchange_rates,
-            viewer_counts: HashMap::with_capacity(100),
+        // Replace tokens with real replacements.
+        // This stops double replacements.
+        for (key, value) in replacements {
+            replacement_string = replacement_string.replace(&format!("<{}>", key), &value);
         }
+
+        replacement_string
+    }
+
+    /// Emote pack emotes in `text`, ready for `render_text`.
+    fn emotes_in(&self, platform: &str, text: &str) -> Vec<(String, String, String)> {
+        emotes::matches(&self.emotes, platform, text)
+            .into_iter()
+            .map(|(find, url, name)| (find, self.images.proxy_url(&url), name))
+            .collect()
+    }
+
+    /// Save all messages with amount > 0 to disk in case of a crash.
+    pub fn save_super_chats(&self) {
+        let mut super_chats: Vec<SavedChat> = self
+            .paid_messages
+            .iter()
+            .filter_map(|id| self.chat_messages.get(id))
+            .filter(|msg| !msg.is_test)
+            .map(|msg| SavedChat {
+                message: msg.to_owned(),
+                text: msg.text.to_owned(),
+            })
+            .collect();
+
+        super_chats.sort_by_key(|saved| saved.message.received_at);
+
+        let super_chats_json = serde_json::to_string(&super_chats).unwrap();
+        std::fs::write(config::get().paths.data_file(SUPER_CHATS), super_chats_json).unwrap();
+    }
+
+    /// Returns up to `limit` of the most recently received chat messages, oldest first.
+    pub fn recent_messages(&self, limit: usize) -> Vec<ChatMessage> {
+        let mut messages: Vec<&ChatMessage> = self.chat_messages.values().collect();
+        messages.sort_by_key(|msg| msg.received_at);
+        let skip = messages.len().saturating_sub(limit);
+        messages.into_iter().skip(skip).cloned().collect()
     }
-}
 
-// conn.recipient.do_send(message::Reply(message.to_owned()));
+    /// Events a connecting client needs to catch up.
+    ///
+    /// A client resuming from a sequence number still in the history gets
+    /// exactly what it missed. Anyone else gets a snapshot of recent chat and
+    /// the current state if they asked for a backlog.
+    pub fn catch_up(
+        &self,
+        filter: &EventFilter,
+        since: Option<u64>,
+        backlog: Option<usize>,
+    ) -> Vec<Arc<Event>> {
+        if let Some(since) = since {
+            let resumable = since <= self.seq
+                && self
+                    .history
+                    .front()
+                    .is_none_or(|oldest| oldest.seq <= since + 1);
+
+            if resumable {
+                return self
+                    .history
+                    .iter()
+                    .filter(|event| event.seq > since && filter.matches(event))
+                    .cloned()
+                    .collect();
+            }
+
+            log::info!(
+                "Cannot resume from event {}, history starts after it. Sending snapshot.",
+                since
+            );
+            return self.snapshot(filter, backlog.unwrap_or(self.config.recent_messages));
+        }
+
+        match backlog {
+            Some(limit) => self.snapshot(filter, limit),
+            None => Vec::new(),
+        }
+    }
+
+    /// Recent chat messages, viewer counts and the featured message.
+    /// Snapshot events carry the current sequence number so the client resumes from here.
+    fn snapshot(&self, filter: &EventFilter, limit: usize) -> Vec<Arc<Event>> {
+        let mut events: Vec<Arc<Event>> = self
+            .recent_messages(limit.min(self.config.recent_messages))
+            .iter()
+            .map(|msg| Arc::new(Event::for_message(self.seq, "chat_message", msg)))
+            .collect();
+
+        events.push(Arc::new(Event::new(
+            self.seq,
+            "viewers",
+            self.viewers_json(),
+        )));
+
+        if !self.sources.is_empty() {
+            events.push(Arc::new(Event::new(
+                self.seq,
+                "sources",
+                self.sources_json(),
+            )));
+        }
+
+        // Sent last so the featured message is already on the page.
+        if self.featured_message.is_some() {
+            events.push(Arc::new(Event::new(
+                self.seq,
+                "feature_message",
+                serde_json::to_string(&self.featured_message)
+                    .expect("Failed to serialize feature string."),
+            )));
+        }
+
+        events.retain(|event| filter.matches(event));
+        events
+    }
+
+    /// Viewers by platform, summed over channels.
+    fn platform_viewers(&self) -> HashMap<String, usize> {
+        let mut viewers: HashMap<String, usize> = HashMap::new();
+        for ((platform, _), (count, _)) in &self.viewer_counts {
+            *viewers.entry(platform.to_owned()).or_default() += count;
+        }
+        viewers
+    }
+
+    fn viewers_json(&self) -> String {
+        serde_json::to_string(&self.platform_viewers()).expect("Failed to serialize viewers.")
+    }
+
+    /// Viewers by platform and channel, sorted.
+    fn channel_viewers(&self) -> Vec<ChannelViewers> {
+        let mut channels: Vec<ChannelViewers> = self
+            .viewer_counts
+            .iter()
+            .map(|((platform, channel), (viewers, _))| ChannelViewers {
+                platform: platform.to_owned(),
+                channel: channel.to_owned(),
+                viewers: *viewers,
+            })
+            .collect();
+        channels.sort_by(|a, b| (&a.platform, &a.channel).cmp(&(&b.platform, &b.channel)));
+        channels
+    }
+
+    /// Records the current viewer counts in this session's history, while live.
+    fn sample_viewers(&self) {
+        if !self.is_live() {
+            return;
+        }
+        let sample = ViewerSample {
+            session: self.session.to_owned(),
+            at: chrono::Utc::now().timestamp_millis(),
+            total: self.total_viewers(),
+            channels: self.channel_viewers(),
+        };
+        if let Err(err) = viewers::append(&sample) {
+            log::error!("Failed to record viewer sample: {:?}", err);
+        }
+    }
+
+    fn total_viewers(&self) -> usize {
+        self.viewer_counts
+            .values()
+            .map(|(viewers, _)| viewers)
+            .sum()
+    }
+
+    /// Drops viewer counts nobody has updated within `viewers::MAX_AGE`.
+    fn expire_viewers(&mut self) {
+        let before = self.viewer_counts.len();
+        self.viewer_counts
+            .retain(|_, (_, updated)| updated.elapsed() < viewers::MAX_AGE);
+        if self.viewer_counts.len() != before {
+            log::info!(
+                "Dropped {} viewer counts that stopped updating.",
+                before - self.viewer_counts.len()
+            );
+            let viewers = self.viewers_json();
+            self.broadcast("viewers", viewers);
+        }
+    }
+
+    /// Records a marker and tells clients about it.
+    fn add_marker(&mut self, marker: Marker) {
+        log::info!("Marker at {}ms: {}", marker.offset, marker.label);
+        if let Err(err) = marker::append(&marker) {
+            log::error!("Failed to record marker: {:?}", err);
+        }
+        self.broadcast(
+            "marker",
+            serde_json::to_string(&marker).expect("Failed to serialize marker."),
+        );
+    }
+
+    fn sources_json(&self) -> String {
+        serde_json::to_string(&self.sources.list()).expect("Failed to serialize sources.")
+    }
+
+    /// Tells dashboards a source has appeared, dropped or gone quiet.
+    fn broadcast_sources(&mut self) {
+        let sources = self.sources_json();
+        self.broadcast("sources", sources);
+    }
+
+    /// The stream counts as live while any platform reports viewers.
+    fn is_live(&self) -> bool {
+        self.viewer_counts.values().any(|(viewers, _)| *viewers > 0)
+    }
+
+    /// Serializes an event once and shares it with every subscribed client.
+    pub fn broadcast(&mut self, tag: &str, message: String) {
+        self.seq += 1;
+        self.publish(Event::new(self.seq, tag, message));
+    }
+
+    /// Broadcasts a chat message, keeping what filters need to know about it.
+    pub fn broadcast_message(&mut self, tag: &str, msg: &ChatMessage) {
+        self.seq += 1;
+        self.publish(Event::for_message(self.seq, tag, msg));
+    }
+
+    /// Records an event in the history and sends it to every client whose filter matches.
+    ///
+    /// Clients whose mailbox is full miss the event. Under the disconnect
+    /// policy they are evicted once they have missed `max_lag` in a row.
+    fn publish(&mut self, event: Event) {
+        let event = Arc::new(event);
+
+        if self.history.len() >= self.config.history {
+            self.history.pop_front();
+        }
+        self.history.push_back(event.clone());
+
+        let policy = self.broadcast_policy;
+        let mut evicted = Vec::new();
+
+        for conn in self.clients.values_mut() {
+            if !conn.filter.matches(&event) {
+                continue;
+            }
+
+            match conn.recipient.try_send(reply_for(&conn.filter, &event)) {
+                Ok(()) => {
+                    if conn.lag > 0 {
+                        log::info!(
+                            "Client {} caught up after missing {} events.",
+                            conn.id,
+                            conn.lag
+                        );
+                        conn.lag = 0;
+                    }
+                }
+                Err(SendError::Full(_)) => {
+                    conn.lag += 1;
+                    if conn.lag == 1 {
+                        log::warn!(
+                            "Client {} is falling behind, dropping {} events.",
+                            conn.id,
+                            event.tag
+                        );
+                    }
+                    if policy.slow_consumer == SlowConsumerPolicy::Disconnect
+                        && conn.lag >= policy.max_lag
+                    {
+                        log::warn!(
+                            "Client {} missed {} events in a row, disconnecting.",
+                            conn.id,
+                            conn.lag
+                        );
+                        evicted.push(conn.id);
+                    }
+                }
+                Err(SendError::Closed(_)) => {
+                    log::debug!("Client {} mailbox closed.", conn.id);
+                    evicted.push(conn.id);
+                }
+            }
+        }
+
+        for id in evicted {
+            if let Some(conn) = self.clients.remove(&id) {
+                conn.evict.do_send(message::Evict {
+                    reason: "Client fell too far behind.".to_owned(),
+                });
+            }
+        }
+    }
+}
 
 /// Make actor from `ChatServer`
 impl Actor for ChatServer {
@@ -76,36 +514,95 @@ impl Actor for ChatServer {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.set_mailbox_capacity(256);
+        ctx.set_mailbox_capacity(self.config.chat_mailbox);
+        ctx.run_interval(SOURCE_CHECK_INTERVAL, |act, _| {
+            act.expire_viewers();
+            if act.sources.refresh(act.is_live(), act.quiet_after) {
+                act.broadcast_sources();
+            }
+        });
+        ctx.run_interval(viewers::SAMPLE_INTERVAL, |act, _| act.sample_viewers());
+        ctx.run_interval(Duration::from_secs(1), |act, _| {
+            if let Err(err) = act.archive.flush() {
+                log::error!("Failed to write the chat archive: {:?}", err);
+            }
+            for (kind, at, label) in act.markers.tick(chrono::Utc::now().timestamp_millis()) {
+                let marker = Marker::new(&act.session, act.started_at, kind, at, label);
+                act.add_marker(marker);
+            }
+        });
+    }
+}
+
+/// The reply carrying `event` to a client with `filter`, marked if it is highlighted.
+fn reply_for(filter: &EventFilter, event: &Arc<Event>) -> message::Reply {
+    if filter.highlights(event) {
+        message::Reply(Arc::new(event.highlighted()))
+    } else {
+        message::Reply(event.clone())
     }
 }
 
 /// Handler for Connect message.
 impl Handler<message::Connect> for ChatServer {
-    type Result = usize;
+    type Result = MessageResult<message::Connect>;
 
     fn handle(&mut self, msg: message::Connect, _: &mut Context<Self>) -> Self::Result {
         log::debug!("New client connected to chat.");
         // random usize
         let id: usize = rand::random();
+
+        let catch_up = self
+            .catch_up(&msg.filter, msg.since, msg.backlog)
+            .iter()
+            .map(|event| reply_for(&msg.filter, event))
+            .collect();
+
         self.clients.insert(
             id,
             Connection {
                 id,
                 recipient: msg.recipient,
+                evict: msg.evict,
+                filter: msg.filter,
+                lag: 0,
             },
         );
-        id
+        MessageResult(message::Connected { id, catch_up })
     }
 }
 
 /// Handler for a new Chat Message from the browser.
+/// Returns false if a message with the same id was already received.
 impl Handler<message::Content> for ChatServer {
-    type Result = ();
+    type Result = bool;
 
     fn handle(&mut self, mut msg: message::Content, _: &mut Context<Self>) -> Self::Result {
+        if self.chat_messages.contains_key(&msg.chat_message.id) {
+            log::debug!(
+                "[ChatServer] Ignoring duplicate message {}",
+                msg.chat_message.id
+            );
+            return false;
+        }
+
         log::debug!("[ChatServer] {}", msg.chat_message.to_console_msg());
+        self.analytics.record(&msg.chat_message);
+        let is_test = msg.chat_message.is_test;
+        if !is_test {
+            self.markers.record(&msg.chat_message);
+            if let Err(err) = self.archive.append(&self.session, &msg.chat_message) {
+                log::error!(
+                    "Failed to archive message {}: {:?}",
+                    msg.chat_message.id,
+                    err
+                );
+            }
+        }
 
+        if msg.chat_message.amount > 0.0 && msg.chat_message.currency.trim().is_empty() {
+            msg.chat_message.currency = msg.chat_message.get_platform().currency.to_owned();
+        }
         let usd = if msg.chat_message.amount > 0.0 {
             self.exchange_rates
                 .get_usd(&msg.chat_message.currency, &msg.chat_message.amount)
@@ -113,63 +610,54 @@ impl Handler<message::Content> for ChatServer {
             0.0
         };
 
-        msg.chat_message.message = msg
-            .chat_message
-            .message
-            .replace("&", "&amp;")
-            .replace("\"", "&quot")
-            .replace("'", "&#039;")
-            .replace("<", "&lt;")
-            .replace(">", "&gt;");
-
-        // emojis = Vec<(String, String, String) where names are (find, replace, name)
-        let mut replacements: HashMap<usize, String> =
-            HashMap::with_capacity(msg.chat_message.emojis.len());
-        let mut replacement_string = msg.chat_message.message.to_owned();
-
-        // First, replace all instances with tokens.
-        for (find, replace, name) in &msg.chat_message.emojis {
-            let url = replace
-                .replace("&", "&amp;")
-                .replace("\"", "&quot")
-                .replace("'", "&#039;")
-                .replace("<", "&lt;")
-                .replace(">", "&gt;");
-            let key: usize = rand::random();
-            let value: String = format!(
-                "<img class=\"emoji\" src=\"{}\" data-emoji=\"{}\" alt=\"{}\" />",
-                url, name, name
-            );
-            replacement_string = replacement_string.replace(find, &format!("<{}>", key));
-            replacements.insert(key, value);
+        msg.chat_message.color = colors::assign(&msg.chat_message);
+        // The archive keeps the original URLs, so images go through the proxy from here.
+        msg.chat_message.avatar = self.images.proxy_url(&msg.chat_message.avatar);
+        for (_, url, _) in msg.chat_message.emojis.iter_mut() {
+            *url = self.images.proxy_url(url);
         }
-
-        // Replace tokens with real replacements.
-        for (key, value) in replacements {
-            replacement_string = replacement_string.replace(&format!("<{}>", key), &value);
-        }
-
-        // Finally, set new string.
-        // This stops double replacements.
-        msg.chat_message.message = replacement_string;
+        let emotes = self.emotes_in(&msg.chat_message.platform, &msg.chat_message.message);
+        msg.chat_message.text = msg.chat_message.message.to_owned();
+        msg.chat_message.message =
+            Self::render_text(&msg.chat_message.message, &msg.chat_message.emojis, &emotes);
 
         let mut chat_msg = msg.chat_message;
         let id = chat_msg.id.to_owned();
+        let original_amount = chat_msg.amount;
+        let original_currency = std::mem::replace(&mut chat_msg.currency, "USD".to_string());
         chat_msg.amount = usd;
-        chat_msg.currency = "USD".to_string();
+
+        if usd > 0.0 && !is_test {
+            let entry = LedgerEntry::new(
+                &self.session,
+                &chat_msg,
+                original_amount,
+                &original_currency,
+            );
+            if let Err(err) = ledger::append(&entry) {
+                log::error!("Failed to record donation {} in ledger: {:?}", id, err);
+            }
+            if self.manual_donations.contains(&id) {
+                self.manual_entries.insert(id, entry);
+            }
+        }
 
         // Send message to all clients.
-        for (_, conn) in &self.clients {
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "chat_message".to_owned(),
-                    message: chat_msg.to_json(),
-                })
-                .expect("Failed to serialize chat message reply_inner."),
-            ));
+        self.broadcast_message("chat_message", &chat_msg);
+
+        if let Some(label) = self.markers.superchat(&chat_msg).filter(|_| !is_test) {
+            let mut marker = Marker::new(
+                &self.session,
+                self.started_at,
+                MarkerKind::Superchat,
+                chrono::Utc::now().timestamp_millis(),
+                label,
+            );
+            marker.message_id = Some(id);
+            self.add_marker(marker);
         }
 
-        if self.chat_messages.len() >= self.chat_messages.capacity() - 1 {
+        if self.chat_messages.len() >= self.chat_messages.capacity().saturating_sub(1) {
             self.chat_messages.reserve(100);
         }
         self.chat_messages.insert(id.to_owned(), chat_msg);
@@ -177,23 +665,16 @@ impl Handler<message::Content> for ChatServer {
         // Backup premium chats to a vector.
         // Performed at the end to avoid having to copy.
         if usd > 0.0 {
-            if self.paid_messages.len() >= self.paid_messages.capacity() - 1 {
+            if self.paid_messages.len() >= self.paid_messages.capacity().saturating_sub(1) {
                 self.paid_messages.reserve(100);
             }
             self.paid_messages.push(id);
-
-            // Save all messages with amount > 0 to disk in case of a crash
-            let mut super_chats: Vec<ChatMessage> = self
-                .paid_messages
-                .iter()
-                .filter_map(|id| self.chat_messages.get(id).cloned())
-                .collect();
-
-            super_chats.sort_by_key(|msg| msg.received_at);
-
-            let super_chats_json = serde_json::to_string(&super_chats).unwrap();
-            std::fs::write("super_chats.json", super_chats_json).unwrap();
+            if !is_test {
+                self.save_super_chats();
+            }
         }
+
+        true
     }
 }
 
@@ -203,46 +684,70 @@ impl Handler<message::Disconnect> for ChatServer {
 
     fn handle(&mut self, msg: message::Disconnect, _: &mut Context<Self>) {
         // Remove Client from HashMap.
-        self.clients.remove(&msg.id);
+        if let Some(conn) = self.clients.remove(&msg.id) {
+            log::debug!("Client {} disconnected from chat.", conn.id);
+        }
+        let dropped = self.sources.disconnect(msg.id);
+        if dropped.is_empty() {
+            return;
+        }
+        log::warn!("Client {} was the last feeder for {:?}.", msg.id, dropped);
+        self.broadcast_sources();
+
+        // Nobody is left to update these counts.
+        let before = self.viewer_counts.len();
+        self.viewer_counts.retain(|key, _| !dropped.contains(key));
+        if self.viewer_counts.len() != before {
+            let viewers = self.viewers_json();
+            self.broadcast("viewers", viewers);
+        }
     }
 }
 
 /// Handler for feature/unfeature message.
-impl<'a> Handler<message::FeatureMessage> for ChatServer {
+impl Handler<message::FeatureMessage> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: message::FeatureMessage, _: &mut Context<Self>) -> Self::Result {
-        for (_, conn) in &self.clients {
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "feature_message".to_owned(),
-                    message: serde_json::to_string(&msg.id)
-                        .expect("Failed to serialize feature string."),
-                })
-                .expect("Failed to serialize feature ReplyInner"),
-            ));
+        self.featured_message = msg.id;
+        self.broadcast(
+            "feature_message",
+            serde_json::to_string(&msg.id).expect("Failed to serialize feature string."),
+        );
+    }
+}
+
+/// Handler for a client catching up after a reconnect.
+impl Handler<message::Resume> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Resume, _: &mut Context<Self>) -> Self::Result {
+        if let Some(conn) = self.clients.get(&msg.id) {
+            for event in self.catch_up(&conn.filter, Some(msg.since), None) {
+                conn.recipient.do_send(reply_for(&conn.filter, &event));
+            }
+        }
+    }
+}
+
+/// Handler for a client changing what it subscribes to.
+impl Handler<message::Subscribe> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Subscribe, _: &mut Context<Self>) -> Self::Result {
+        if let Some(conn) = self.clients.get_mut(&msg.id) {
+            log::debug!("Client {} subscribed to {:?}", conn.id, msg.filter);
+            conn.filter = msg.filter;
         }
     }
 }
 
 /// Handler for recent chat messages.
-impl<'a> Handler<message::RecentMessages> for ChatServer {
+impl Handler<message::RecentMessages> for ChatServer {
     type Result = MessageResult<message::RecentMessages>;
 
     fn handle(&mut self, _: message::RecentMessages, _: &mut Context<Self>) -> Self::Result {
-        const MAX_MESSAGES: usize = 100;
-
-        let mut last_messages: Vec<ChatMessage> = if self.chat_messages.len() >= MAX_MESSAGES {
-            self.chat_messages
-                .keys()
-                .cloned()
-                .skip(self.chat_messages.len() - MAX_MESSAGES)
-                .filter_map(|id| self.chat_messages.get(&id).cloned())
-                .collect()
-        } else {
-            self.chat_messages.values().cloned().collect()
-        };
-        last_messages.sort_by_key(|msg| msg.received_at);
+        let last_messages = self.recent_messages(self.config.recent_messages);
 
         log::debug!("Sending {} recent messages.", last_messages.len());
         MessageResult(last_messages)
@@ -250,7 +755,7 @@ impl<'a> Handler<message::RecentMessages> for ChatServer {
 }
 
 /// Handler for all stored Superchats.
-impl<'a> Handler<message::PaidMessages> for ChatServer {
+impl Handler<message::PaidMessages> for ChatServer {
     type Result = MessageResult<message::PaidMessages>;
 
     fn handle(&mut self, _: message::PaidMessages, _: &mut Context<Self>) -> Self::Result {
@@ -265,27 +770,465 @@ impl<'a> Handler<message::PaidMessages> for ChatServer {
     }
 }
 
+/// Handler for updates from server-side connectors.
+impl Handler<LivestreamUpdate> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, update: LivestreamUpdate, ctx: &mut Context<Self>) -> Self::Result {
+        Handler::<message::Activity>::handle(
+            self,
+            message::Activity {
+                feeder: None,
+                platform: update.platform.to_owned(),
+                channel: update.channel.to_owned(),
+                messages: update.messages.as_ref().map_or(0, Vec::len),
+            },
+            ctx,
+        );
+
+        if let Some(viewers) = update.viewers {
+            Handler::<message::ViewCount>::handle(
+                self,
+                message::ViewCount {
+                    platform: update.platform,
+                    channel: update.channel,
+                    viewers,
+                },
+                ctx,
+            );
+        }
+        for chat_message in update.messages.unwrap_or_default() {
+            Handler::<message::Content>::handle(self, message::Content { chat_message }, ctx);
+        }
+    }
+}
+
+/// Handler for feeder activity.
+impl Handler<message::Activity> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Activity, _: &mut Context<Self>) -> Self::Result {
+        if self.sources.record(
+            msg.feeder,
+            &msg.platform,
+            msg.channel.as_deref(),
+            msg.messages,
+        ) {
+            self.broadcast_sources();
+        }
+    }
+}
+
+impl Handler<message::ListSources> for ChatServer {
+    type Result = MessageResult<message::ListSources>;
+
+    fn handle(&mut self, _: message::ListSources, _: &mut Context<Self>) -> Self::Result {
+        let live = self.is_live();
+        self.sources.refresh(live, self.quiet_after);
+        MessageResult(self.sources.list())
+    }
+}
+
 /// Handler for viewer counts.
 impl Handler<message::ViewCount> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, viewers: message::ViewCount, _: &mut Context<Self>) -> Self::Result {
-        if let Some(old) = self.viewer_counts.insert(viewers.platform, viewers.viewers) {
+        let key = (viewers.platform, viewers.channel.unwrap_or_default());
+        if let Some((old, _)) = self
+            .viewer_counts
+            .insert(key, (viewers.viewers, Instant::now()))
+        {
             if old == viewers.viewers {
                 return;
             }
         }
 
-        for (_, conn) in &self.clients {
-            let new_viewers = self.viewer_counts.clone();
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "viewers".to_owned(),
-                    message: serde_json::to_string(&new_viewers)
-                        .expect("Failed to serialize viewers."),
-                })
-                .expect("Failed to serialize viewers replyinner"),
+        let new_viewers = self.viewers_json();
+        self.broadcast("viewers", new_viewers);
+    }
+}
+
+/// Handler for a page of stored chat messages.
+impl Handler<message::QueryMessages> for ChatServer {
+    type Result = MessageResult<message::QueryMessages>;
+
+    fn handle(&mut self, query: message::QueryMessages, _: &mut Context<Self>) -> Self::Result {
+        let limit = query
+            .limit
+            .unwrap_or(message::DEFAULT_PAGE_SIZE)
+            .min(message::MAX_PAGE_SIZE);
+
+        let mut messages: Vec<&ChatMessage> = self
+            .chat_messages
+            .values()
+            .filter(|msg| query.matches(msg))
+            .collect();
+        messages.sort_by_key(|msg| msg.received_at);
+
+        MessageResult(message::MessagePage {
+            total: messages.len(),
+            offset: query.offset,
+            limit,
+            messages: messages
+                .into_iter()
+                .skip(query.offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Handler for a single stored chat message.
+impl Handler<message::GetMessage> for ChatServer {
+    type Result = Option<ChatMessage>;
+
+    fn handle(&mut self, msg: message::GetMessage, _: &mut Context<Self>) -> Self::Result {
+        self.chat_messages.get(&msg.0).cloned()
+    }
+}
+
+/// Handler for current viewer counts.
+impl Handler<message::ViewerCounts> for ChatServer {
+    type Result = MessageResult<message::ViewerCounts>;
+
+    fn handle(&mut self, _: message::ViewerCounts, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.platform_viewers())
+    }
+}
+
+/// Handler for current viewer counts by channel.
+impl Handler<message::ChannelViewerCounts> for ChatServer {
+    type Result = MessageResult<message::ChannelViewerCounts>;
+
+    fn handle(&mut self, _: message::ChannelViewerCounts, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.channel_viewers())
+    }
+}
+
+/// Handler for a marker added from the dashboard.
+impl Handler<message::AddMarker> for ChatServer {
+    type Result = MessageResult<message::AddMarker>;
+
+    fn handle(&mut self, msg: message::AddMarker, _: &mut Context<Self>) -> Self::Result {
+        let at = msg
+            .at
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+        let marker = Marker::new(
+            &self.session,
+            self.started_at,
+            MarkerKind::Manual,
+            at,
+            msg.label.trim().to_owned(),
+        );
+        self.add_marker(marker.clone());
+        MessageResult(marker)
+    }
+}
+
+/// Handler for this session's chat statistics.
+impl Handler<message::ChatAnalytics> for ChatServer {
+    type Result = MessageResult<message::ChatAnalytics>;
+
+    fn handle(&mut self, _: message::ChatAnalytics, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.analytics.report())
+    }
+}
+
+impl Handler<message::CurrentSession> for ChatServer {
+    type Result = MessageResult<message::CurrentSession>;
+
+    fn handle(&mut self, _: message::CurrentSession, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.session.to_owned())
+    }
+}
+
+/// Handler for emote packs changed from the dashboard.
+impl Handler<message::ReloadEmotes> for ChatServer {
+    type Result = MessageResult<message::ReloadEmotes>;
+
+    fn handle(&mut self, _: message::ReloadEmotes, _: &mut Context<Self>) -> Self::Result {
+        self.emotes = emotes::load();
+        MessageResult(self.emotes.clone())
+    }
+}
+
+/// Handler for reloading the config. Settings read at startup, such as the server address
+/// and connectors, wait for a restart.
+impl Handler<message::Reconfigure> for ChatServer {
+    type Result = anyhow::Result<config::Reloaded>;
+
+    fn handle(&mut self, _: message::Reconfigure, _: &mut Context<Self>) -> Self::Result {
+        // Reloading here rather than in the caller keeps the config in use and the
+        // one applied below the same when reloads overlap.
+        let reloaded = config::reload()?;
+        let config = reloaded.config.clone();
+        self.broadcast_policy = BroadcastPolicy::new(&config.server);
+        self.config = config.server.to_owned();
+        while self.history.len() > self.config.history {
+            self.history.pop_front();
+        }
+        self.quiet_after = Duration::from_secs(config.sources.quiet_after);
+        self.markers.set_config(config.markers.to_owned());
+        // Picks up packs edited on disk.
+        self.emotes = emotes::load();
+
+        let settings = message::ClientConfig {
+            branding: config.branding.to_owned(),
+            overlays: config
+                .overlays()
+                .into_iter()
+                .map(|(name, profile)| (name, profile.branding(&config.branding)))
+                .collect(),
+        };
+        self.broadcast(
+            "config",
+            serde_json::to_string(&settings).expect("Failed to serialize config."),
+        );
+        log::info!("Applied the reloaded config.");
+        Ok(reloaded)
+    }
+}
+
+/// Handler for server status.
+impl Handler<message::Status> for ChatServer {
+    type Result = MessageResult<message::Status>;
+
+    fn handle(&mut self, _: message::Status, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(message::ServerStatus {
+            version: env!("CARGO_PKG_VERSION"),
+            started_at: self.started_at,
+            clients: self.clients.len(),
+            messages: self.chat_messages.len(),
+            paid_messages: self.paid_messages.len(),
+            viewers: self.total_viewers(),
+            featured_message: self.featured_message,
+            seq: self.seq,
+            session: self.session.to_owned(),
+        })
+    }
+}
+
+/// Handler for messages removed by platform moderators.
+impl Handler<message::DeleteMessages> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: message::DeleteMessages, _: &mut Context<Self>) -> Self::Result {
+        let ids: Vec<Uuid> = self
+            .chat_messages
+            .values()
+            .filter(|chat_msg| !chat_msg.is_premium())
+            .filter(|chat_msg| match &msg.0 {
+                message::Deletion::Message(id) => chat_msg.id == *id,
+                message::Deletion::User {
+                    platform,
+                    channel,
+                    user_id,
+                } => {
+                    chat_msg.platform == *platform
+                        && chat_msg.channel.as_ref() == Some(channel)
+                        && chat_msg.user_id.as_ref() == Some(user_id)
+                }
+                message::Deletion::Channel { platform, channel } => {
+                    chat_msg.platform == *platform && chat_msg.channel.as_ref() == Some(channel)
+                }
+            })
+            .map(|chat_msg| chat_msg.id)
+            .collect();
+
+        if ids.is_empty() {
+            return 0;
+        }
+
+        log::debug!("Deleting {} messages: {:?}", ids.len(), msg.0);
+        for id in &ids {
+            self.chat_messages.remove(id);
+        }
+        self.broadcast(
+            "chat_message_delete",
+            serde_json::to_string(&ids).expect("Failed to serialize deleted ids."),
+        );
+
+        if self.featured_message.is_some_and(|id| ids.contains(&id)) {
+            self.featured_message = None;
+            self.broadcast("feature_message", "null".to_owned());
+        }
+        ids.len()
+    }
+}
+
+/// Name shown for a donation entered by hand. Blank names can't be rendered.
+fn donor_name(username: &str) -> String {
+    match username.trim() {
+        "" => "Anonymous".to_owned(),
+        username => username.to_owned(),
+    }
+}
+
+/// Handler for a donation entered by hand.
+impl Handler<message::CreateDonation> for ChatServer {
+    type Result = Result<ChatMessage, message::DonationError>;
+
+    fn handle(&mut self, msg: message::CreateDonation, ctx: &mut Context<Self>) -> Self::Result {
+        if !msg.amount.is_finite() || msg.amount <= 0.0 {
+            return Err(message::DonationError::Invalid(
+                "Amount must be positive.".to_owned(),
             ));
         }
+
+        let currency = msg.currency.trim().to_uppercase();
+        if !self.exchange_rates.knows(&currency) {
+            return Err(message::DonationError::Invalid(format!(
+                "Unknown currency {:?}.",
+                currency
+            )));
+        }
+
+        let mut donation = ChatMessage {
+            platform: msg.platform,
+            username: donor_name(&msg.username),
+            amount: msg.amount,
+            currency,
+            message: msg.message,
+            ..Default::default()
+        };
+        if let Some(platform) = platform::find(&donation.platform) {
+            donation.avatar = platform.logo_url();
+        }
+
+        let id = donation.id;
+        let mut entry = AuditEntry::new(AuditAction::Create, id);
+        entry.after = Some(donation.clone());
+        audit::append(&entry).map_err(message::DonationError::Audit)?;
+
+        self.manual_donations.insert(id);
+        Handler::<message::Content>::handle(
+            self,
+            message::Content {
+                chat_message: donation,
+            },
+            ctx,
+        );
+
+        self.chat_messages
+            .get(&id)
+            .cloned()
+            .ok_or(message::DonationError::NotFound)
+    }
+}
+
+/// Handler for correcting a manual donation. Overlays replace the message in place.
+impl Handler<message::EditDonation> for ChatServer {
+    type Result = Result<ChatMessage, message::DonationError>;
+
+    fn handle(&mut self, msg: message::EditDonation, _: &mut Context<Self>) -> Self::Result {
+        if !self.manual_donations.contains(&msg.id) {
+            return Err(message::DonationError::NotFound);
+        }
+        let old = self
+            .chat_messages
+            .get(&msg.id)
+            .cloned()
+            .ok_or(message::DonationError::NotFound)?;
+        let mut recorded = self
+            .manual_entries
+            .get(&msg.id)
+            .cloned()
+            .unwrap_or_else(|| LedgerEntry::new(&self.session, &old, old.amount, &old.currency));
+
+        let mut edited = old.clone();
+        if let Some(username) = msg.username {
+            edited.username = donor_name(&username);
+        }
+        if let Some(text) = msg.message {
+            let emotes = self.emotes_in(&edited.platform, &text);
+            edited.message = Self::render_text(&text, &[], &emotes);
+            edited.text = text;
+        }
+        if msg.amount.is_some() || msg.currency.is_some() {
+            let amount = msg.amount.unwrap_or(recorded.original_amount);
+            let currency = msg
+                .currency
+                .unwrap_or_else(|| recorded.original_currency.to_owned())
+                .to_uppercase();
+            edited.amount = self.exchange_rates.get_usd(&currency, &amount);
+            edited.currency = "USD".to_owned();
+            recorded.original_amount = amount;
+            recorded.original_currency = currency;
+        }
+        if !edited.amount.is_finite() || edited.amount <= 0.0 {
+            return Err(message::DonationError::Invalid(
+                "Amount must be positive. Void the donation to remove it.".to_owned(),
+            ));
+        }
+
+        let mut entry = AuditEntry::new(AuditAction::Edit, msg.id);
+        entry.before = Some(old);
+        entry.after = Some(edited.clone());
+        audit::append(&entry).map_err(message::DonationError::Audit)?;
+
+        // Keep the session it was first received in.
+        let entry = LedgerEntry::new(
+            &recorded.session,
+            &edited,
+            recorded.original_amount,
+            &recorded.original_currency,
+        );
+        if let Err(err) = ledger::append(&entry) {
+            log::error!("Failed to record edit of {} in ledger: {:?}", msg.id, err);
+        }
+        self.manual_entries.insert(msg.id, entry);
+
+        log::info!("Edited donation {}.", msg.id);
+        self.chat_messages.insert(msg.id, edited.clone());
+        self.save_super_chats();
+        self.broadcast_message("chat_message_edit", &edited);
+        Ok(edited)
+    }
+}
+
+/// Handler for voiding a manual donation. Overlays remove the message.
+impl Handler<message::VoidDonation> for ChatServer {
+    type Result = Result<(), message::DonationError>;
+
+    fn handle(&mut self, msg: message::VoidDonation, _: &mut Context<Self>) -> Self::Result {
+        if !self.manual_donations.contains(&msg.id) {
+            return Err(message::DonationError::NotFound);
+        }
+        let old = self
+            .chat_messages
+            .get(&msg.id)
+            .cloned()
+            .ok_or(message::DonationError::NotFound)?;
+
+        let mut entry = AuditEntry::new(AuditAction::Void, msg.id);
+        entry.before = Some(old);
+        entry.reason = msg.reason;
+        audit::append(&entry).map_err(message::DonationError::Audit)?;
+
+        if let Some(mut entry) = self.manual_entries.remove(&msg.id) {
+            entry.voided = true;
+            entry.recorded_at = chrono::Utc::now().timestamp_millis();
+            if let Err(err) = ledger::append(&entry) {
+                log::error!("Failed to record void of {} in ledger: {:?}", msg.id, err);
+            }
+        }
+
+        log::info!("Voided donation {}.", msg.id);
+        self.manual_donations.remove(&msg.id);
+        self.chat_messages.remove(&msg.id);
+        self.paid_messages.retain(|id| *id != msg.id);
+        self.save_super_chats();
+        self.broadcast(
+            "chat_message_void",
+            serde_json::to_string(&msg.id).expect("Failed to serialize void id."),
+        );
+
+        if self.featured_message == Some(msg.id) {
+            self.featured_message = None;
+            self.broadcast("feature_message", "null".to_owned());
+        }
+        Ok(())
     }
 }
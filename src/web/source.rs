@@ -0,0 +1,153 @@
+//! Feeder health: which platforms and channels are sending chat, and how recently.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// How often sources are checked for going quiet.
+pub const SOURCE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Messages within this window count towards a source's rate.
+const RATE_WINDOW_MS: i64 = 60_000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Source {
+    pub platform: String,
+    pub channel: Option<String>,
+    /// Websocket feeders currently sending this source.
+    pub feeders: usize,
+    #[serde(skip)]
+    feeder_ids: HashSet<usize>,
+    /// False once the last websocket feeder has gone. Connectors and ingest clients
+    /// have no connection to lose, so they only ever go quiet.
+    pub connected: bool,
+    /// Unix timestamp in milliseconds.
+    pub connected_at: i64,
+    /// Unix timestamp in milliseconds.
+    pub last_message_at: Option<i64>,
+    pub messages: u64,
+    /// Messages per minute, over the last minute.
+    pub rate: f64,
+    /// Message counts with the time they arrived, within the rate window.
+    #[serde(skip)]
+    recent: VecDeque<(i64, usize)>,
+    /// No messages for longer than the quiet threshold while the stream is live.
+    pub quiet: bool,
+}
+
+impl Source {
+    fn new(platform: &str, channel: Option<&str>, now: i64) -> Self {
+        Self {
+            platform: platform.to_owned(),
+            channel: channel.map(str::to_owned),
+            feeders: 0,
+            feeder_ids: HashSet::new(),
+            connected: true,
+            connected_at: now,
+            last_message_at: None,
+            messages: 0,
+            rate: 0.0,
+            recent: VecDeque::new(),
+            quiet: false,
+        }
+    }
+
+    fn update_rate(&mut self, now: i64) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(at, _)| now - at > RATE_WINDOW_MS)
+        {
+            self.recent.pop_front();
+        }
+        let count: usize = self.recent.iter().map(|(_, count)| count).sum();
+        self.rate = count as f64 * 60_000.0 / RATE_WINDOW_MS as f64;
+    }
+}
+
+/// Every source seen this session, by platform and channel.
+#[derive(Default)]
+pub struct Sources {
+    sources: BTreeMap<(String, String), Source>,
+}
+
+impl Sources {
+    /// Records an update from a feeder, or from a connector or ingest client if `feeder` is None.
+    /// Returns true if the source is new or has come back.
+    pub fn record(
+        &mut self,
+        feeder: Option<usize>,
+        platform: &str,
+        channel: Option<&str>,
+        messages: usize,
+    ) -> bool {
+        let now = chrono::Utc::now().timestamp_millis();
+        let key = (platform.to_owned(), channel.unwrap_or_default().to_owned());
+        let mut changed = false;
+        let source = self.sources.entry(key).or_insert_with(|| {
+            changed = true;
+            Source::new(platform, channel, now)
+        });
+
+        if let Some(feeder) = feeder {
+            source.feeder_ids.insert(feeder);
+            source.feeders = source.feeder_ids.len();
+        }
+        if !source.connected {
+            source.connected = true;
+            source.connected_at = now;
+            changed = true;
+        }
+        if messages > 0 {
+            source.messages += messages as u64;
+            source.last_message_at = Some(now);
+            source.recent.push_back((now, messages));
+            source.update_rate(now);
+            if source.quiet {
+                source.quiet = false;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Forgets a websocket feeder. Returns the platform and channel of every source
+    /// that lost its last feeder.
+    pub fn disconnect(&mut self, feeder: usize) -> Vec<(String, String)> {
+        let mut dropped = Vec::new();
+        for (key, source) in self.sources.iter_mut() {
+            if source.feeder_ids.remove(&feeder) {
+                source.feeders = source.feeder_ids.len();
+                if source.feeders == 0 {
+                    source.connected = false;
+                    dropped.push(key.to_owned());
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Updates rates and marks sources quiet if nothing has arrived for `quiet_after`
+    /// while `live`. Returns true if any source went quiet or recovered.
+    pub fn refresh(&mut self, live: bool, quiet_after: Duration) -> bool {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut changed = false;
+        for source in self.sources.values_mut() {
+            source.update_rate(now);
+            let idle = now - source.last_message_at.unwrap_or(source.connected_at);
+            let quiet = live && idle > quiet_after.as_millis() as i64;
+            if source.quiet != quiet {
+                source.quiet = quiet;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<Source> {
+        self.sources.values().cloned().collect()
+    }
+}
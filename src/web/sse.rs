@@ -0,0 +1,126 @@
+use actix::*;
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use std::time::Duration;
+
+use super::filter::EventFilter;
+use super::message;
+use super::ChatServer;
+use crate::config;
+
+/// Live events an SSE response may have buffered before the client counts as too slow.
+/// Catch-up events on connect don't count towards it.
+pub const SSE_BUFFER: usize = 64;
+/// Comment sent periodically so proxies keep the stream open and we notice dead clients.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Read-only subscriber feeding a Server-Sent Events response.
+pub struct SseClient {
+    /// Connection ID
+    pub id: usize,
+    /// Chat server
+    pub server: Addr<ChatServer>,
+    /// Start of the HTTP response, until the client has caught up.
+    pub catch_up: Option<mpsc::UnboundedSender<Bytes>>,
+    /// Rest of the HTTP response.
+    pub sender: mpsc::Sender<Bytes>,
+    /// Last event ID the client saw before reconnecting, if any.
+    pub since: Option<u64>,
+    /// Recent chat messages to send on connect, if any.
+    pub backlog: Option<usize>,
+    pub filter: EventFilter,
+}
+
+impl SseClient {
+    /// Frames an event for the response.
+    fn frame(event: &message::Reply) -> Bytes {
+        let event = &event.0;
+        Bytes::from(format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            event.seq, event.tag, event.payload
+        ))
+    }
+
+    /// Queues a chunk of the response. Stops the actor if the client is gone or too slow.
+    fn push(&mut self, chunk: Bytes, ctx: &mut Context<Self>) {
+        if let Err(err) = self.sender.try_send(chunk) {
+            if err.is_full() {
+                log::warn!("SSE client {} fell behind, disconnecting.", self.id);
+            } else {
+                log::debug!("SSE client {} went away.", self.id);
+            }
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for SseClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(config::get().server.client_mailbox);
+
+        // Tell EventSource how long to wait before reconnecting.
+        if let Some(catch_up) = &self.catch_up {
+            let _ = catch_up.unbounded_send(Bytes::from_static(b"retry: 3000\n\n"));
+        }
+
+        ctx.run_interval(KEEPALIVE_INTERVAL, |act, ctx| {
+            act.push(Bytes::from_static(b": keepalive\n\n"), ctx);
+        });
+
+        self.server
+            .send(message::Connect {
+                recipient: ctx.address().recipient(),
+                evict: ctx.address().recipient(),
+                since: self.since,
+                backlog: self.backlog,
+                filter: self.filter.clone(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => {
+                        act.id = res.id;
+                        // Dropping the sender ends the first part of the response,
+                        // so broadcasts follow the catch-up.
+                        if let Some(catch_up) = act.catch_up.take() {
+                            for reply in &res.catch_up {
+                                let _ = catch_up.unbounded_send(Self::frame(reply));
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to assign SSE conection id: {:?}", err);
+                        ctx.stop();
+                    }
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.server.do_send(message::Disconnect { id: self.id });
+        Running::Stop
+    }
+}
+
+/// Write each broadcast event as an SSE frame.
+impl Handler<message::Reply> for SseClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Reply, ctx: &mut Self::Context) {
+        self.push(Self::frame(&msg), ctx);
+    }
+}
+
+/// End the response when the chat server drops us.
+impl Handler<message::Evict> for SseClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Evict, ctx: &mut Self::Context) {
+        log::debug!("SSE client {} evicted: {}", self.id, msg.reason);
+        ctx.stop();
+    }
+}
@@ -0,0 +1,88 @@
+//! `POST /api/v1/webhooks/{provider}` for donation services.
+
+use actix_web::http::{header::HeaderMap, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Resource};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::api::{chat_server, ApiError};
+use super::ChatMessage;
+use crate::webhook::{Provider, WebhookError};
+
+/// Largest webhook body accepted.
+const MAX_WEBHOOK_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize)]
+pub struct WebhookAck {
+    /// One entry per donation in the event. Empty if it had none.
+    pub donations: Vec<DonationAck>,
+}
+
+#[derive(Serialize)]
+pub struct DonationAck {
+    /// Id of the donation message.
+    pub id: Uuid,
+    /// False if this was a redelivery of a donation we already have.
+    pub accepted: bool,
+}
+
+pub fn resource() -> Resource {
+    web::resource("/webhooks/{provider}")
+        .app_data(web::PayloadConfig::new(MAX_WEBHOOK_BYTES))
+        .route(web::post().to(webhook))
+}
+
+async fn webhook(req: HttpRequest, provider: web::Path<String>, body: web::Bytes) -> HttpResponse {
+    let provider: Provider = match provider.parse() {
+        Ok(provider) => provider,
+        Err(err) => return ApiError::response(StatusCode::NOT_FOUND, err.to_string()),
+    };
+
+    let headers: &HeaderMap = req.headers();
+    let donations = match provider.handle(headers, &body) {
+        Ok(donations) => donations,
+        Err(WebhookError::Disabled) => {
+            return ApiError::response(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Set webhooks.{} to enable this webhook.",
+                    provider.secret_setting()
+                ),
+            )
+        }
+        Err(WebhookError::Unauthorized) => {
+            log::warn!(
+                "Rejected {} webhook with a bad signature.",
+                provider.platform()
+            );
+            return ApiError::response(StatusCode::UNAUTHORIZED, "Invalid signature.");
+        }
+        Err(WebhookError::Invalid(err)) => {
+            log::warn!("Invalid {} webhook: {:?}", provider.platform(), err);
+            return ApiError::response(StatusCode::BAD_REQUEST, err.to_string());
+        }
+    };
+
+    if donations.is_empty() {
+        log::debug!(
+            "Ignoring {} webhook that isn't a donation.",
+            provider.platform()
+        );
+    }
+
+    let chat_server = chat_server(&req);
+    let mut acks = Vec::with_capacity(donations.len());
+    for donation in donations {
+        let id = donation.id;
+        match chat_server
+            .send(ChatMessage {
+                chat_message: donation,
+            })
+            .await
+        {
+            Ok(accepted) => acks.push(DonationAck { id, accepted }),
+            Err(err) => return ApiError::unavailable(err),
+        }
+    }
+    HttpResponse::Ok().json(WebhookAck { donations: acks })
+}
@@ -0,0 +1,61 @@
+//! Ko-fi posts a form with a single `data` field holding JSON.
+//! The JSON carries the verification token from the Ko-fi webhook settings.
+
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+use super::{amount, donation, Provider, WebhookError};
+use crate::message::Message;
+
+#[derive(Deserialize)]
+struct Form {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    verification_token: String,
+    kofi_transaction_id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    is_public: bool,
+    from_name: String,
+    message: Option<String>,
+    amount: serde_json::Value,
+    currency: String,
+}
+
+pub fn handle(body: &[u8], secret: &str) -> Result<Vec<Message>, WebhookError> {
+    let form: Form = serde_urlencoded::from_bytes(body).map_err(anyhow::Error::from)?;
+    let payload: Payload = serde_json::from_str(&form.data)?;
+
+    if !bool::from(
+        payload
+            .verification_token
+            .as_bytes()
+            .ct_eq(secret.as_bytes()),
+    ) {
+        return Err(WebhookError::Unauthorized);
+    }
+
+    // Shop orders aren't donations.
+    if payload.kind != "Donation" && payload.kind != "Subscription" {
+        return Ok(Vec::new());
+    }
+
+    // Private donations keep their amount but not their name or message.
+    let (username, text) = if payload.is_public {
+        (payload.from_name, payload.message.unwrap_or_default())
+    } else {
+        (String::new(), String::new())
+    };
+
+    Ok(vec![donation(
+        Provider::Kofi,
+        &payload.kofi_transaction_id,
+        &username,
+        amount(&payload.amount)?,
+        &payload.currency,
+        &text,
+    )])
+}
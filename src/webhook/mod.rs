@@ -0,0 +1,370 @@
+//! Donation webhooks from third-party services.
+//!
+//! Each provider verifies the request with its own secret and maps the payload
+//! into a paid `Message`, which then goes through the normal chat pipeline.
+
+mod kofi;
+mod patreon;
+mod streamelements;
+mod streamlabs;
+
+use actix_web::http::header::HeaderMap;
+use hmac::{Hmac, Mac};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::config;
+use crate::message::Message;
+
+/// Namespace for donation message ids, so redelivered webhooks map to the same message.
+const DONATION_NAMESPACE: Uuid = uuid::uuid!("5eedd0e5-0000-4000-8000-000000000032");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Kofi,
+    Patreon,
+    StreamElements,
+    Streamlabs,
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    /// The provider has no secret configured.
+    Disabled,
+    /// The signature or verification token didn't match.
+    Unauthorized,
+    /// The payload couldn't be understood.
+    Invalid(anyhow::Error),
+}
+
+impl From<anyhow::Error> for WebhookError {
+    fn from(err: anyhow::Error) -> Self {
+        WebhookError::Invalid(err)
+    }
+}
+
+impl From<serde_json::Error> for WebhookError {
+    fn from(err: serde_json::Error) -> Self {
+        WebhookError::Invalid(err.into())
+    }
+}
+
+impl FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kofi" => Ok(Provider::Kofi),
+            "patreon" => Ok(Provider::Patreon),
+            "streamelements" => Ok(Provider::StreamElements),
+            "streamlabs" => Ok(Provider::Streamlabs),
+            _ => Err(anyhow::anyhow!("Unknown webhook provider {:?}.", s)),
+        }
+    }
+}
+
+impl Provider {
+    /// Platform name given to donations from this provider.
+    pub fn platform(&self) -> &'static str {
+        match self {
+            Provider::Kofi => "kofi",
+            Provider::Patreon => "patreon",
+            Provider::StreamElements => "streamelements",
+            Provider::Streamlabs => "streamlabs",
+        }
+    }
+
+    /// Setting under `[webhooks]` holding the secret or verification token.
+    pub fn secret_setting(&self) -> &'static str {
+        match self {
+            Provider::Kofi => "kofi_verification_token",
+            Provider::Patreon => "patreon_secret",
+            Provider::StreamElements => "streamelements_secret",
+            Provider::Streamlabs => "streamlabs_secret",
+        }
+    }
+
+    fn secret(&self) -> String {
+        let webhooks = &config::get().webhooks;
+        match self {
+            Provider::Kofi => &webhooks.kofi_verification_token,
+            Provider::Patreon => &webhooks.patreon_secret,
+            Provider::StreamElements => &webhooks.streamelements_secret,
+            Provider::Streamlabs => &webhooks.streamlabs_secret,
+        }
+        .to_owned()
+    }
+
+    /// Verifies a delivery and maps it into paid messages, one per donation.
+    /// Events that aren't donations map to none.
+    pub fn handle(&self, headers: &HeaderMap, body: &[u8]) -> Result<Vec<Message>, WebhookError> {
+        let secret = self.secret();
+        if secret.is_empty() {
+            return Err(WebhookError::Disabled);
+        }
+
+        match self {
+            Provider::Kofi => kofi::handle(body, &secret),
+            Provider::Patreon => patreon::handle(headers, body, &secret),
+            Provider::StreamElements => streamelements::handle(headers, body, &secret),
+            Provider::Streamlabs => streamlabs::handle(headers, body, &secret),
+        }
+    }
+}
+
+/// Checks a hex HMAC of the body in constant time.
+fn verify_hmac<M: Mac + hmac::digest::KeyInit>(
+    headers: &HeaderMap,
+    header: &str,
+    body: &[u8],
+    secret: &str,
+) -> Result<(), WebhookError> {
+    let signature = headers
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_start_matches("sha256="))
+        .and_then(|value| hex::decode(value).ok())
+        .ok_or(WebhookError::Unauthorized)?;
+
+    let mut mac =
+        <M as Mac>::new_from_slice(secret.as_bytes()).map_err(|_| WebhookError::Unauthorized)?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| WebhookError::Unauthorized)
+}
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+type HmacMd5 = Hmac<md5::Md5>;
+
+/// Reads an amount that providers send either as a number or a string.
+fn amount(value: &serde_json::Value) -> Result<f64, WebhookError> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| WebhookError::Invalid(anyhow::anyhow!("Invalid amount {}.", value)))
+}
+
+/// Builds the paid message for a donation.
+/// The id is derived from the provider's transaction id so redeliveries are deduplicated.
+fn donation(
+    provider: Provider,
+    transaction_id: &str,
+    username: &str,
+    amount: f64,
+    currency: &str,
+    text: &str,
+) -> Message {
+    let platform = provider.platform();
+    Message {
+        id: Uuid::new_v5(
+            &DONATION_NAMESPACE,
+            format!("{}:{}", platform, transaction_id).as_bytes(),
+        ),
+        platform: platform.to_owned(),
+        message: text.to_owned(),
+        username: if username.trim().is_empty() {
+            "Anonymous".to_owned()
+        } else {
+            username.to_owned()
+        },
+        amount,
+        currency: currency.to_uppercase(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    const SECRET: &str = "hunter2";
+
+    fn sign<M: Mac + hmac::digest::KeyInit>(body: &[u8]) -> String {
+        let mut mac = <M as Mac>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn kofi_body(token: &str) -> Vec<u8> {
+        let data =
+            include_str!("../../fixtures/webhooks/kofi.json").replace("VERIFICATION_TOKEN", token);
+        serde_urlencoded::to_string([("data", data)])
+            .unwrap()
+            .into_bytes()
+    }
+
+    #[test]
+    fn kofi_maps_donation() {
+        let messages = kofi::handle(&kofi_body(SECRET), SECRET).unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.platform, "kofi");
+        assert_eq!(message.username, "Jo Example");
+        assert_eq!(message.message, "Good luck with the integration!");
+        assert_eq!(message.amount, 3.0);
+        assert_eq!(message.currency, "USD");
+    }
+
+    #[test]
+    fn escapes_donor_name() {
+        config::init_for_tests();
+        let data = include_str!("../../fixtures/webhooks/kofi.json")
+            .replace("VERIFICATION_TOKEN", SECRET)
+            .replace("Jo Example", "<img src=x onerror=alert(1)>");
+        let body = serde_urlencoded::to_string([("data", data)]).unwrap();
+        let messages = kofi::handle(body.as_bytes(), SECRET).unwrap();
+        let html = messages[0].to_html();
+        assert!(!html.contains("<img src=x"));
+        assert!(html.contains("&lt;img src=x onerror=alert(1)&gt;"));
+    }
+
+    #[test]
+    fn kofi_rejects_wrong_token() {
+        assert!(matches!(
+            kofi::handle(&kofi_body("not the token"), SECRET),
+            Err(WebhookError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn patreon_maps_pledge() {
+        config::init_for_tests();
+        let body = include_bytes!("../../fixtures/webhooks/patreon.json");
+        let signature = sign::<HmacMd5>(body);
+        let messages = patreon::handle(
+            &headers(&[
+                ("x-patreon-signature", &signature),
+                ("x-patreon-event", "members:pledge:create"),
+            ]),
+            body,
+            SECRET,
+        )
+        .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].platform, "patreon");
+        assert_eq!(messages[0].username, "Pat Ron");
+        assert_eq!(messages[0].amount, 5.0);
+        assert_eq!(messages[0].currency, "USD");
+    }
+
+    #[test]
+    fn patreon_rejects_bad_signature() {
+        let body = include_bytes!("../../fixtures/webhooks/patreon.json");
+        let signature = sign::<HmacMd5>(b"something else");
+        assert!(matches!(
+            patreon::handle(
+                &headers(&[
+                    ("x-patreon-signature", &signature),
+                    ("x-patreon-event", "members:pledge:create"),
+                ]),
+                body,
+                SECRET,
+            ),
+            Err(WebhookError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn streamelements_maps_tip() {
+        let body = include_bytes!("../../fixtures/webhooks/streamelements.json");
+        let signature = sign::<HmacSha256>(body);
+        let messages = streamelements::handle(
+            &headers(&[("x-streamelements-signature", &signature)]),
+            body,
+            SECRET,
+        )
+        .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].platform, "streamelements");
+        assert_eq!(messages[0].username, "tipper");
+        assert_eq!(messages[0].message, "Keep it up");
+        assert_eq!(messages[0].amount, 10.0);
+        assert_eq!(messages[0].currency, "EUR");
+    }
+
+    #[test]
+    fn streamelements_rejects_missing_signature() {
+        let body = include_bytes!("../../fixtures/webhooks/streamelements.json");
+        assert!(matches!(
+            streamelements::handle(&HeaderMap::new(), body, SECRET),
+            Err(WebhookError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn streamlabs_maps_donation() {
+        let body = include_bytes!("../../fixtures/webhooks/streamlabs.json");
+        let signature = sign::<HmacSha256>(body);
+        let messages = streamlabs::handle(
+            &headers(&[("x-streamlabs-signature", &signature)]),
+            body,
+            SECRET,
+        )
+        .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].platform, "streamlabs");
+        assert_eq!(messages[0].username, "Jane");
+        assert_eq!(messages[0].message, "Great stream!");
+        assert_eq!(messages[0].amount, 13.37);
+    }
+
+    #[test]
+    fn streamlabs_maps_every_donation_in_a_batch() {
+        let mut payload: serde_json::Value =
+            serde_json::from_str(include_str!("../../fixtures/webhooks/streamlabs.json")).unwrap();
+        let mut second = payload["message"][0].clone();
+        second["_id"] = "b0a1f2e3d4c5b6a7f8e9d0c1b2a3f4e5".into();
+        second["name"] = "Sam".into();
+        second["amount"] = 5.into();
+        payload["message"].as_array_mut().unwrap().push(second);
+        let body = serde_json::to_vec(&payload).unwrap();
+        let signature = sign::<HmacSha256>(&body);
+
+        let messages = streamlabs::handle(
+            &headers(&[("x-streamlabs-signature", &signature)]),
+            &body,
+            SECRET,
+        )
+        .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].username, "Jane");
+        assert_eq!(messages[1].username, "Sam");
+        assert_eq!(messages[1].amount, 5.0);
+        assert_ne!(messages[0].id, messages[1].id);
+    }
+
+    #[test]
+    fn streamlabs_rejects_bad_signature() {
+        let body = include_bytes!("../../fixtures/webhooks/streamlabs.json");
+        let signature = sign::<HmacSha256>(b"{}");
+        assert!(matches!(
+            streamlabs::handle(
+                &headers(&[("x-streamlabs-signature", &signature)]),
+                body,
+                SECRET,
+            ),
+            Err(WebhookError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn redelivery_keeps_the_id() {
+        let first = kofi::handle(&kofi_body(SECRET), SECRET).unwrap();
+        let again = kofi::handle(&kofi_body(SECRET), SECRET).unwrap();
+        assert_eq!(first[0].id, again[0].id);
+    }
+}
@@ -0,0 +1,74 @@
+//! Patreon signs the raw body with HMAC-MD5 in `X-Patreon-Signature`.
+//! The event name is in `X-Patreon-Event`; only new pledges become donations.
+
+use actix_web::http::header::HeaderMap;
+use serde::Deserialize;
+
+use super::{donation, verify_hmac, HmacMd5, Provider, WebhookError};
+use crate::config;
+use crate::message::Message;
+
+#[derive(Deserialize)]
+struct Payload {
+    data: Member,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    id: String,
+    attributes: MemberAttributes,
+}
+
+#[derive(Deserialize)]
+struct MemberAttributes {
+    full_name: Option<String>,
+    currently_entitled_amount_cents: Option<u64>,
+    will_pay_amount_cents: Option<u64>,
+    last_charge_date: Option<String>,
+    note: Option<String>,
+}
+
+pub fn handle(
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: &str,
+) -> Result<Vec<Message>, WebhookError> {
+    verify_hmac::<HmacMd5>(headers, "X-Patreon-Signature", body, secret)?;
+
+    let event = headers
+        .get("X-Patreon-Event")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if event != "members:pledge:create" && event != "members:create" {
+        return Ok(Vec::new());
+    }
+
+    let payload: Payload = serde_json::from_slice(body)?;
+    let attributes = payload.data.attributes;
+    let cents = attributes
+        .currently_entitled_amount_cents
+        .or(attributes.will_pay_amount_cents)
+        .unwrap_or_default();
+    if cents == 0 {
+        return Ok(Vec::new());
+    }
+
+    // A member may pledge again later, so the charge date is part of the transaction.
+    let transaction_id = format!(
+        "{}:{}",
+        payload.data.id,
+        attributes.last_charge_date.unwrap_or_default()
+    );
+
+    // Patreon amounts are in the campaign currency, which is USD unless configured otherwise.
+    let currency = config::get().webhooks.patreon_currency.to_owned();
+
+    Ok(vec![donation(
+        Provider::Patreon,
+        &transaction_id,
+        &attributes.full_name.unwrap_or_default(),
+        cents as f64 / 100.0,
+        &currency,
+        &attributes.note.unwrap_or_default(),
+    )])
+}
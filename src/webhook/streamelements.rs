@@ -0,0 +1,51 @@
+//! StreamElements tip events, as forwarded by a relay subscribed to the
+//! StreamElements socket. The relay signs the raw body with HMAC-SHA256
+//! in `X-StreamElements-Signature`.
+
+use actix_web::http::header::HeaderMap;
+use serde::Deserialize;
+
+use super::{amount, donation, verify_hmac, HmacSha256, Provider, WebhookError};
+use crate::message::Message;
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    data: Tip,
+}
+
+#[derive(Deserialize)]
+struct Tip {
+    #[serde(rename = "tipId")]
+    tip_id: Option<String>,
+    username: String,
+    amount: serde_json::Value,
+    currency: String,
+    message: Option<String>,
+}
+
+pub fn handle(
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: &str,
+) -> Result<Vec<Message>, WebhookError> {
+    verify_hmac::<HmacSha256>(headers, "X-StreamElements-Signature", body, secret)?;
+
+    let payload: Payload = serde_json::from_slice(body)?;
+    if payload.kind != "tip" {
+        return Ok(Vec::new());
+    }
+
+    let tip = payload.data;
+    Ok(vec![donation(
+        Provider::StreamElements,
+        tip.tip_id.as_deref().unwrap_or(&payload.id),
+        &tip.username,
+        amount(&tip.amount)?,
+        &tip.currency,
+        &tip.message.unwrap_or_default(),
+    )])
+}
@@ -0,0 +1,55 @@
+//! Streamlabs donation events, as forwarded by a relay subscribed to the
+//! Streamlabs socket API. The relay signs the raw body with HMAC-SHA256
+//! in `X-Streamlabs-Signature`.
+
+use actix_web::http::header::HeaderMap;
+use serde::Deserialize;
+
+use super::{amount, donation, verify_hmac, HmacSha256, Provider, WebhookError};
+use crate::message::Message;
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(rename = "type")]
+    kind: String,
+    message: Vec<Donation>,
+}
+
+#[derive(Deserialize)]
+struct Donation {
+    #[serde(rename = "_id")]
+    id: String,
+    name: String,
+    amount: serde_json::Value,
+    currency: String,
+    message: Option<String>,
+}
+
+/// Streamlabs batches events, so one delivery can carry several donations.
+pub fn handle(
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: &str,
+) -> Result<Vec<Message>, WebhookError> {
+    verify_hmac::<HmacSha256>(headers, "X-Streamlabs-Signature", body, secret)?;
+
+    let payload: Payload = serde_json::from_slice(body)?;
+    if payload.kind != "donation" {
+        return Ok(Vec::new());
+    }
+
+    payload
+        .message
+        .into_iter()
+        .map(|donation_event| {
+            Ok(donation(
+                Provider::Streamlabs,
+                &donation_event.id,
+                &donation_event.name,
+                amount(&donation_event.amount)?,
+                &donation_event.currency,
+                &donation_event.message.unwrap_or_default(),
+            ))
+        })
+        .collect()
+}